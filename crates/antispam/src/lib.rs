@@ -0,0 +1,715 @@
+//! Spam detection that is shared between the Discord and Twitch bots. Users are identified
+//! by their platform-prefixed user key, e.g. `discord:1234` or `twitch:someone`.
+
+use std::{collections::HashMap, collections::VecDeque, time::Duration};
+
+use log::debug;
+use model::config::{AntispamConfig, SpamAction};
+use tokio::time::Instant;
+
+/// The max amount of messages kept per user.
+const MAX_HISTORY: usize = 20;
+/// Only this many characters are compared when looking for duplicate messages.
+const MAX_COMPARE_LEN: usize = 200;
+/// The rule name used for blocklist matches.
+pub const BLOCKLIST_RULE: &str = "blocklist";
+/// How often expired histories are removed.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A message to check for spam.
+pub struct SpamMessage<'a> {
+    pub content: &'a str,
+    /// The amount of users and roles mentioned, including `@everyone` on Discord.
+    pub mentions: usize,
+    /// The age of the author's account in seconds, if known.
+    pub account_age_secs: Option<u64>,
+}
+
+/// The result of a message breaking a rule.
+#[derive(Debug, Clone)]
+pub struct Verdict {
+    pub rule: &'static str,
+    pub action: SpamAction,
+    pub reason: String,
+    /// Whether the user has broken rules so often that they should not be replied to.
+    pub silent: bool,
+    /// How long to silence the user for in seconds if the action is `SpamAction::Timeout`.
+    pub silence_secs: u64,
+}
+
+struct HistoryEntry {
+    timestamp: Instant,
+    /// The lowercased message with whitespace collapsed.
+    content: String,
+}
+
+pub struct History {
+    messages: VecDeque<HistoryEntry>,
+    strikes: u8,
+    last_strike: Instant,
+    silences: usize,
+}
+
+impl History {
+    /// Create a new `History`.
+    fn new() -> Self {
+        Self {
+            messages: VecDeque::new(),
+            strikes: 0,
+            last_strike: Instant::now(),
+            silences: 0,
+        }
+    }
+
+    /// Messages sent within the last `secs` seconds, including the current message.
+    /// Negative, NaN or too large windows match nothing, which disables the rule.
+    fn messages_within(&self, secs: f32) -> impl Iterator<Item = &HistoryEntry> {
+        let window = Duration::try_from_secs_f32(secs).ok();
+        self.messages
+            .iter()
+            .filter(move |m| window.is_some_and(|w| m.timestamp.elapsed() <= w))
+    }
+
+    fn push(&mut self, content: String) {
+        self.messages.push_back(HistoryEntry {
+            timestamp: Instant::now(),
+            content,
+        });
+        while self.messages.len() > MAX_HISTORY {
+            self.messages.pop_front();
+        }
+    }
+
+    /// The strikes left after decaying since the last strike.
+    fn current_strikes(&self, config: &AntispamConfig) -> u8 {
+        let decayed = self.last_strike.elapsed().as_secs() / config.strike_decay_secs.max(1);
+
+        self.strikes
+            .saturating_sub(decayed.try_into().unwrap_or(u8::MAX))
+    }
+
+    fn add_strike(&mut self, config: &AntispamConfig) {
+        // Strikes only decay after a while without breaking rules, not after a single
+        // message that happens to be fine
+        let strikes = self.current_strikes(config);
+        if strikes == 0 {
+            self.silences = 0;
+        }
+
+        self.strikes = strikes.saturating_add(1);
+        self.last_strike = Instant::now();
+    }
+
+    /// Whether the history can be forgotten without losing any active strikes.
+    fn is_expired(&self, config: &AntispamConfig) -> bool {
+        let ttl = Duration::from_secs(config.history_ttl_secs);
+        let is_recent = self
+            .messages
+            .back()
+            .is_some_and(|m| m.timestamp.elapsed() < ttl);
+
+        self.current_strikes(config) == 0 && !is_recent
+    }
+}
+
+/// A check that is run against every message.
+pub trait Rule: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Check the `message`. `history` already contains the message being checked.
+    /// Returns the action to take and a reason if the rule was broken.
+    fn check(
+        &self,
+        message: &SpamMessage,
+        history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)>;
+}
+
+/// Too many messages in a short time.
+struct RateRule;
+
+impl Rule for RateRule {
+    fn name(&self) -> &'static str {
+        "rate"
+    }
+
+    fn check(
+        &self,
+        _message: &SpamMessage,
+        history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        let count = history.messages_within(config.rate_window_secs).count();
+
+        (count > config.rate_max_messages)
+            .then(|| (config.rate_action, "sending messages too quickly".into()))
+    }
+}
+
+/// The same or nearly the same message sent over and over.
+struct DuplicateRule;
+
+impl Rule for DuplicateRule {
+    fn name(&self) -> &'static str {
+        "duplicate"
+    }
+
+    fn check(
+        &self,
+        _message: &SpamMessage,
+        history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        let current = &history.messages.back()?.content;
+        if current.is_empty() {
+            return None;
+        }
+
+        let count = history
+            .messages_within(config.duplicate_window_secs)
+            .filter(|m| similarity(&m.content, current) >= config.duplicate_similarity)
+            .count();
+
+        (count > config.duplicate_max_messages)
+            .then(|| (config.duplicate_action, "repeating messages".into()))
+    }
+}
+
+/// Too many mentions in a single message.
+struct MentionRule;
+
+impl Rule for MentionRule {
+    fn name(&self) -> &'static str {
+        "mentions"
+    }
+
+    fn check(
+        &self,
+        message: &SpamMessage,
+        _history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        (message.mentions > config.max_mentions)
+            .then(|| (config.mention_action, "mentioning too many users".into()))
+    }
+}
+
+/// Messages that are mostly uppercase or emojis.
+struct RatioRule;
+
+impl Rule for RatioRule {
+    fn name(&self) -> &'static str {
+        "ratio"
+    }
+
+    fn check(
+        &self,
+        message: &SpamMessage,
+        _history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        let chars = message
+            .content
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .count();
+        if chars < config.ratio_min_length {
+            return None;
+        }
+
+        let letters = message
+            .content
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .count();
+        let caps = message.content.chars().filter(|c| c.is_uppercase()).count();
+        if letters >= config.ratio_min_length
+            && caps as f32 / letters as f32 > config.max_caps_ratio
+        {
+            return Some((config.ratio_action, "using too many caps".into()));
+        }
+
+        let emojis = message.content.chars().filter(|c| is_emoji(*c)).count()
+            + message.content.matches("<:").count()
+            + message.content.matches("<a:").count();
+        if emojis as f32 / chars as f32 > config.max_emoji_ratio {
+            return Some((config.ratio_action, "using too many emojis".into()));
+        }
+
+        None
+    }
+}
+
+/// Links and Discord invites.
+struct LinkRule;
+
+impl Rule for LinkRule {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn check(
+        &self,
+        message: &SpamMessage,
+        _history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        let content = message.content.to_lowercase();
+
+        if contains_invite(&content) {
+            Some((config.invite_action, "posting invites".into()))
+        } else if contains_link(&content) {
+            Some((config.link_action, "posting links".into()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Links and mass mentions from recently created accounts.
+struct NewAccountRule;
+
+impl Rule for NewAccountRule {
+    fn name(&self) -> &'static str {
+        "new_account"
+    }
+
+    fn check(
+        &self,
+        message: &SpamMessage,
+        _history: &History,
+        config: &AntispamConfig,
+    ) -> Option<(SpamAction, String)> {
+        let age = message.account_age_secs?;
+        if age >= config.new_account_days * 24 * 60 * 60 {
+            return None;
+        }
+
+        let content = message.content.to_lowercase();
+        (contains_link(&content) || contains_invite(&content) || message.mentions > 1).then(|| {
+            (
+                config.new_account_action,
+                "posting links or mentions from a new account".into(),
+            )
+        })
+    }
+}
+
+/// How much `Antispam` is currently keeping track of.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    pub tracked_users: usize,
+    /// Users with strikes that have not decayed yet.
+    pub users_with_strikes: usize,
+    pub tracked_messages: usize,
+}
+
+pub struct Antispam {
+    rules: Vec<Box<dyn Rule>>,
+    chatter_history: HashMap<String, History>,
+    last_prune: Instant,
+}
+
+impl Antispam {
+    /// Create a new instance of `Antispam` with all rules enabled.
+    pub fn new() -> Self {
+        Self {
+            rules: vec![
+                Box::new(RateRule),
+                Box::new(DuplicateRule),
+                Box::new(MentionRule),
+                Box::new(RatioRule),
+                Box::new(LinkRule),
+                Box::new(NewAccountRule),
+            ],
+            chatter_history: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// Check if the message from the given `user` is spam. Returns the most severe
+    /// verdict of all the rules that were broken.
+    pub fn check(
+        &mut self,
+        user: &str,
+        message: &SpamMessage,
+        config: &AntispamConfig,
+    ) -> Option<Verdict> {
+        if self.last_prune.elapsed() >= PRUNE_INTERVAL {
+            self.prune(config);
+        }
+
+        let history = self
+            .chatter_history
+            .entry(user.to_string())
+            .or_insert_with(History::new);
+        history.push(normalize(message.content));
+
+        let (rule, (action, reason)) = self
+            .rules
+            .iter()
+            .filter_map(|r| Some((r.name(), r.check(message, history, config)?)))
+            .filter(|(_, (action, _))| action > &SpamAction::Ignore)
+            .max_by_key(|(_, (action, _))| *action)?;
+
+        Some(self.punish(user, rule, action, reason, config))
+    }
+
+    /// Give the `user` a strike for breaking a rule that was checked elsewhere, e.g. a
+    /// blocklist. The action is escalated the same way as for the built-in rules. Actions
+    /// below `SpamAction::Delete` are only warnings and do not count as strikes.
+    pub fn punish(
+        &mut self,
+        user: &str,
+        rule: &'static str,
+        action: SpamAction,
+        reason: String,
+        config: &AntispamConfig,
+    ) -> Verdict {
+        let history = self
+            .chatter_history
+            .entry(user.to_string())
+            .or_insert_with(History::new);
+        if action < SpamAction::Delete {
+            return Verdict {
+                rule,
+                action,
+                reason,
+                silent: history.current_strikes(config) > config.silent_after_strikes,
+                silence_secs: 0,
+            };
+        }
+        history.add_strike(config);
+
+        // Repeat offenders get punished harder each time
+        let step = nth_or_last(&config.escalation, history.strikes as usize - 1)
+            .unwrap_or(SpamAction::Ignore);
+        let action = action.max(step);
+
+        let silence_secs = if action == SpamAction::Timeout {
+            history.silences += 1;
+            nth_or_last(&config.silence_secs, history.silences - 1).unwrap_or_default()
+        } else {
+            0
+        };
+
+        Verdict {
+            rule,
+            action,
+            reason,
+            silent: history.strikes > config.silent_after_strikes,
+            silence_secs,
+        }
+    }
+
+    /// Create a verdict for a message that matched a blocklist. The user only gets a strike
+    /// if `escalate` is set.
+    pub fn blocklist_verdict(
+        &mut self,
+        user: &str,
+        action: SpamAction,
+        escalate: bool,
+        config: &AntispamConfig,
+    ) -> Verdict {
+        let reason = "using blocked words".to_string();
+        if escalate {
+            return self.punish(user, BLOCKLIST_RULE, action, reason, config);
+        }
+
+        Verdict {
+            rule: BLOCKLIST_RULE,
+            action,
+            reason,
+            silent: false,
+            silence_secs: nth_or_last(&config.silence_secs, 0).unwrap_or_default(),
+        }
+    }
+
+    /// Forget users that have not sent a message in a while and have no strikes left.
+    /// Called automatically while checking messages so the history stays bounded.
+    pub fn prune(&mut self, config: &AntispamConfig) {
+        let before = self.chatter_history.len();
+        self.chatter_history.retain(|_, h| !h.is_expired(config));
+        self.last_prune = Instant::now();
+
+        let metrics = self.metrics(config);
+        debug!(
+            "Antispam forgot {} users, tracking {} users with {} messages, {} with strikes",
+            before - metrics.tracked_users,
+            metrics.tracked_users,
+            metrics.tracked_messages,
+            metrics.users_with_strikes
+        );
+    }
+
+    /// How much is currently being kept track of.
+    pub fn metrics(&self, config: &AntispamConfig) -> Metrics {
+        Metrics {
+            tracked_users: self.chatter_history.len(),
+            users_with_strikes: self
+                .chatter_history
+                .values()
+                .filter(|h| h.current_strikes(config) > 0)
+                .count(),
+            tracked_messages: self
+                .chatter_history
+                .values()
+                .map(|h| h.messages.len())
+                .sum(),
+        }
+    }
+}
+
+impl Default for Antispam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the `n`th item, or the last item if there are not enough items.
+fn nth_or_last<T: Copy>(items: &[T], n: usize) -> Option<T> {
+    items.get(n).or_else(|| items.last()).copied()
+}
+
+/// Lowercase the message and collapse whitespace so small changes are ignored.
+fn normalize(content: &str) -> String {
+    content
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// How similar two strings are from 0.0 to 1.0, based on their edit distance.
+fn similarity(a: &str, b: &str) -> f32 {
+    let a = a.chars().take(MAX_COMPARE_LEN).collect::<Vec<char>>();
+    let b = b.chars().take(MAX_COMPARE_LEN).collect::<Vec<char>>();
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != cb);
+            current[j + 1] = (previous[j + 1] + 1)
+                .min(current[j] + 1)
+                .min(previous[j] + cost);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    1.0 - previous[b.len()] as f32 / max_len as f32
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF)
+}
+
+fn contains_link(content: &str) -> bool {
+    content.contains("http://") || content.contains("https://") || content.contains("www.")
+}
+
+fn contains_invite(content: &str) -> bool {
+    [
+        "discord.gg/",
+        "discord.com/invite/",
+        "discordapp.com/invite/",
+    ]
+    .iter()
+    .any(|i| content.contains(i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> SpamMessage<'_> {
+        SpamMessage {
+            content,
+            mentions: 0,
+            account_age_secs: None,
+        }
+    }
+
+    /// Send `count` different messages as quickly as possible.
+    fn flood(antispam: &mut Antispam, user: &str, count: usize, config: &AntispamConfig) {
+        for i in 0..count {
+            antispam.check(user, &message(&format!("message {i}")), config);
+        }
+    }
+
+    fn strikes(antispam: &Antispam, user: &str, config: &AntispamConfig) -> u8 {
+        antispam.chatter_history[user].current_strikes(config)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_rule_uses_sliding_window() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        flood(&mut antispam, "twitch:a", config.rate_max_messages, &config);
+        let verdict = antispam.check("twitch:a", &message("one more"), &config);
+        assert_eq!(verdict.map(|v| v.rule), Some("rate"));
+
+        tokio::time::advance(Duration::from_secs_f32(config.rate_window_secs + 1.0)).await;
+        assert!(antispam
+            .check("twitch:a", &message("slow message"), &config)
+            .is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn strikes_survive_a_single_good_message() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        flood(
+            &mut antispam,
+            "twitch:a",
+            config.rate_max_messages + 2,
+            &config,
+        );
+        assert_eq!(strikes(&antispam, "twitch:a", &config), 2);
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(antispam
+            .check("twitch:a", &message("hello"), &config)
+            .is_none());
+        assert_eq!(strikes(&antispam, "twitch:a", &config), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn strikes_decay_over_time() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        flood(
+            &mut antispam,
+            "twitch:a",
+            config.rate_max_messages + 2,
+            &config,
+        );
+
+        tokio::time::advance(Duration::from_secs(config.strike_decay_secs)).await;
+        assert_eq!(strikes(&antispam, "twitch:a", &config), 1);
+
+        tokio::time::advance(Duration::from_secs(config.strike_decay_secs)).await;
+        assert_eq!(strikes(&antispam, "twitch:a", &config), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn escalates_with_strikes() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        flood(&mut antispam, "twitch:a", config.rate_max_messages, &config);
+        let actions = (0..config.escalation.len())
+            .filter_map(|i| antispam.check("twitch:a", &message(&format!("spam {i}")), &config))
+            .map(|v| v.action.max(SpamAction::Warn))
+            .collect::<Vec<SpamAction>>();
+
+        assert_eq!(actions.last(), Some(&SpamAction::Kick));
+        assert!(actions.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn warnings_do_not_escalate() {
+        let config = AntispamConfig {
+            rate_action: SpamAction::Ignore,
+            duplicate_action: SpamAction::Ignore,
+            ratio_action: SpamAction::Warn,
+            ..Default::default()
+        };
+        let mut antispam = Antispam::new();
+
+        for _ in 0..config.escalation.len() + 1 {
+            let verdict = antispam.check("twitch:a", &message("STOP SHOUTING AT ME"), &config);
+            assert_eq!(
+                verdict.map(|v| (v.rule, v.action)),
+                Some(("ratio", SpamAction::Warn))
+            );
+        }
+        assert_eq!(strikes(&antispam, "twitch:a", &config), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn history_expires_after_ttl() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        antispam.check("twitch:a", &message("hello"), &config);
+        antispam.check("discord:1", &message("hello"), &config);
+        assert_eq!(antispam.metrics(&config).tracked_users, 2);
+
+        tokio::time::advance(Duration::from_secs(config.history_ttl_secs)).await;
+        antispam.prune(&config);
+        assert_eq!(antispam.metrics(&config), Metrics::default());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pruning_keeps_active_strikes() {
+        let config = AntispamConfig {
+            strike_decay_secs: 1000,
+            ..Default::default()
+        };
+        let mut antispam = Antispam::new();
+
+        flood(
+            &mut antispam,
+            "twitch:spammer",
+            config.rate_max_messages + 1,
+            &config,
+        );
+        antispam.check("twitch:quiet", &message("hello"), &config);
+
+        tokio::time::advance(Duration::from_secs(config.history_ttl_secs)).await;
+        antispam.prune(&config);
+
+        let metrics = antispam.metrics(&config);
+        assert_eq!(metrics.tracked_users, 1);
+        assert_eq!(metrics.users_with_strikes, 1);
+        assert_eq!(strikes(&antispam, "twitch:spammer", &config), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn prunes_automatically_while_checking() {
+        let config = AntispamConfig::default();
+        let mut antispam = Antispam::new();
+
+        antispam.check("twitch:a", &message("hello"), &config);
+
+        tokio::time::advance(Duration::from_secs(config.history_ttl_secs)).await;
+        antispam.check("twitch:b", &message("hello"), &config);
+
+        assert_eq!(antispam.metrics(&config).tracked_users, 1);
+        assert!(!antispam.chatter_history.contains_key("twitch:a"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn invalid_windows_disable_rules() {
+        for secs in [-1.0, f32::NAN, f32::INFINITY, f32::MAX] {
+            let config = AntispamConfig {
+                rate_window_secs: secs,
+                duplicate_window_secs: secs,
+                ..Default::default()
+            };
+            let mut antispam = Antispam::new();
+
+            for _ in 0..=config.rate_max_messages.max(config.duplicate_max_messages) {
+                assert!(antispam
+                    .check("twitch:a", &message("same message"), &config)
+                    .is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn detects_near_duplicates() {
+        assert!(similarity("buy my stuff", "buy my stuff!") >= 0.9);
+        assert!(similarity("hello there", "goodbye") < 0.5);
+    }
+}
@@ -1,7 +1,13 @@
 use std::fmt::Display;
 
 use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand};
-use model::config::Config;
+use model::{
+    blocklist::BlockKind,
+    config::Config,
+    giveaway::{self, Eligibility, Giveaway},
+    messages::ChannelRequest,
+    state::{Poll, State},
+};
 use strum::{EnumIter, IntoEnumIterator};
 
 use super::commands;
@@ -49,6 +55,105 @@ pub enum Commands {
     },
     /// Announce that you are lurking.
     Lurk,
+    /// Start a poll. The question must be quoted, e.g. poll "Question?" yes no
+    Poll {
+        /// How long the poll should run for in seconds.
+        #[arg(short, long, default_value_t = 60)]
+        duration: u64,
+        /// The question followed by the options.
+        #[arg(num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// Vote for an option in the running poll.
+    Vote {
+        /// The number of the option to vote for.
+        option: usize,
+    },
+    /// Enter or manage a giveaway.
+    Giveaway(GiveawayArgs),
+    /// Link your Twitch and Discord accounts. Run in Discord to receive a code, then
+    /// send the code in Twitch chat.
+    Link {
+        /// The code received from Discord.
+        code: Option<String>,
+    },
+    /// Unlink your Twitch and Discord accounts.
+    Unlink,
+    /// Show how many points you have.
+    Points,
+    /// Give some of your points to someone else.
+    Give {
+        /// The user to give points to. Either a Discord mention or a Twitch name.
+        user: String,
+        /// The amount of points to give.
+        amount: i64,
+    },
+    /// Show who has the most points.
+    Leaderboard,
+    /// Bet points on a roll from 1 - 100. Rolling over 50 doubles the bet.
+    Gamble {
+        /// The amount of points to bet.
+        amount: i64,
+    },
+    /// Add points to a user. Use a negative amount to remove points.
+    #[command(name = "addpoints")]
+    AddPoints {
+        /// The user to add points to. Either a Discord mention or a Twitch name.
+        user: String,
+        /// The amount of points to add.
+        #[arg(allow_negative_numbers = true)]
+        amount: i64,
+    },
+    /// Set the points of a user.
+    #[command(name = "setpoints")]
+    SetPoints {
+        /// The user to set points for. Either a Discord mention or a Twitch name.
+        user: String,
+        /// The amount of points to set.
+        #[arg(allow_negative_numbers = true)]
+        amount: i64,
+    },
+    /// Give a shoutout to a Twitch channel.
+    #[command(aliases = ["shoutout"])]
+    So {
+        /// The Twitch name of the channel.
+        user: String,
+    },
+    /// Start a raid to a Twitch channel.
+    Raid {
+        /// The Twitch name of the channel.
+        user: String,
+    },
+    /// Show how long the stream has been live.
+    Uptime,
+    /// Show the game of the stream. Moderators can change it by adding a name.
+    Game {
+        /// The new game.
+        #[arg(num_args = 0..)]
+        name: Vec<String>,
+    },
+    /// Show the title of the stream. Moderators can change it by adding a title.
+    Title {
+        /// The new title.
+        #[arg(num_args = 0..)]
+        text: Vec<String>,
+    },
+    /// Show how long someone has followed the channel.
+    Followage {
+        /// The Twitch name of the follower. Defaults to you.
+        user: Option<String>,
+    },
+    /// Show who is waiting in a queue.
+    Queue {
+        /// The name of the queue.
+        name: String,
+    },
+    /// Show the most recent moderation actions against a user.
+    Modlog {
+        /// Either a Discord mention or a Twitch name.
+        user: String,
+    },
+    /// Commands for moderators.
     Admin(Admin),
 }
 
@@ -63,6 +168,27 @@ impl Display for Commands {
             Self::AdHoc { text } => write!(f, "ad-hoc {}", text),
             Self::Rhai { script } => write!(f, "rhai {}", script.join(" ")),
             Self::Lurk => write!(f, "lurk"),
+            Self::Poll { duration, text } => write!(f, "poll -d {duration} {}", text.join(" ")),
+            Self::Vote { option } => write!(f, "vote {option}"),
+            Self::Giveaway(giveaway) => write!(f, "giveaway {}", &giveaway.command),
+            Self::Link { code } => write!(f, "link {}", code.clone().unwrap_or_default()),
+            Self::Unlink => write!(f, "unlink"),
+            Self::Points => write!(f, "points"),
+            Self::Give { user, amount } => write!(f, "give {user} {amount}"),
+            Self::Leaderboard => write!(f, "leaderboard"),
+            Self::Gamble { amount } => write!(f, "gamble {amount}"),
+            Self::AddPoints { user, amount } => write!(f, "addpoints {user} {amount}"),
+            Self::SetPoints { user, amount } => write!(f, "setpoints {user} {amount}"),
+            Self::So { user } => write!(f, "so {user}"),
+            Self::Raid { user } => write!(f, "raid {user}"),
+            Self::Uptime => write!(f, "uptime"),
+            Self::Game { name } => write!(f, "game {}", name.join(" ")),
+            Self::Title { text } => write!(f, "title {}", text.join(" ")),
+            Self::Followage { user } => {
+                write!(f, "followage {}", user.clone().unwrap_or_default())
+            }
+            Self::Queue { name } => write!(f, "queue {name}"),
+            Self::Modlog { user } => write!(f, "modlog {user}"),
             Self::Admin(admin) => write!(f, "admin {}", &admin.command),
         }
     }
@@ -94,6 +220,37 @@ impl Default for Admin {
 pub enum AdminCommands {
     Test,
     ReloadConfig,
+    /// Sync Discord roles for linked Twitch subscribers, VIPs and moderators.
+    SyncRoles,
+    /// Block a word, phrase or regex, e.g. admin block phrase free followers
+    Block {
+        /// Either word, phrase or regex.
+        kind: BlockKind,
+        #[arg(num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// Remove a word, phrase or regex from the blocklist.
+    Unblock {
+        /// Either word, phrase or regex.
+        kind: BlockKind,
+        #[arg(num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// Show the blocklist.
+    Blocklist,
+    /// Stop filtering messages from a Discord role ID or Twitch badge, e.g. vip.
+    Exempt {
+        role: String,
+    },
+    /// Start filtering messages from a Discord role ID or Twitch badge again.
+    Unexempt {
+        role: String,
+    },
+    /// Remove and show the next user in a queue.
+    Next {
+        /// The name of the queue.
+        queue: String,
+    },
 }
 
 impl Display for AdminCommands {
@@ -101,6 +258,60 @@ impl Display for AdminCommands {
         match self {
             Self::Test => write!(f, "test"),
             Self::ReloadConfig => write!(f, "reload-config"),
+            Self::SyncRoles => write!(f, "sync-roles"),
+            Self::Block { kind, text } => write!(f, "block {kind} {}", text.join(" ")),
+            Self::Unblock { kind, text } => write!(f, "unblock {kind} {}", text.join(" ")),
+            Self::Blocklist => write!(f, "blocklist"),
+            Self::Exempt { role } => write!(f, "exempt {role}"),
+            Self::Unexempt { role } => write!(f, "unexempt {role}"),
+            Self::Next { queue } => write!(f, "next {queue}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct GiveawayArgs {
+    #[command(subcommand)]
+    pub command: GiveawayCommands,
+}
+
+impl Default for GiveawayArgs {
+    fn default() -> Self {
+        Self {
+            command: GiveawayCommands::Enter,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Subcommand, EnumIter)]
+pub enum GiveawayCommands {
+    /// Start a giveaway, e.g. giveaway start "Cool prize" [seconds] [everyone|subs|vips]
+    Start {
+        /// The prize followed by the optional duration and eligibility.
+        #[arg(num_args = 1..)]
+        text: Vec<String>,
+    },
+    /// Enter the running giveaway.
+    Enter,
+    /// Close entries and draw a winner.
+    End,
+    /// Draw a new winner.
+    Reroll,
+    /// Cancel the giveaway.
+    Cancel,
+    /// Show the information needed to verify the draws.
+    Audit,
+}
+
+impl Display for GiveawayCommands {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Start { text } => write!(f, "start {}", text.join(" ")),
+            Self::Enter => write!(f, "enter"),
+            Self::End => write!(f, "end"),
+            Self::Reroll => write!(f, "reroll"),
+            Self::Cancel => write!(f, "cancel"),
+            Self::Audit => write!(f, "audit"),
         }
     }
 }
@@ -163,25 +374,100 @@ pub enum AdditionalInfo {
     Discord {
         name: String,
         user_id: u64,
+        guild_id: u64,
         channel_id: u64,
+        is_moderator: bool,
     },
     Twitch {
         name: String,
+        channel: String,
         is_vip: bool,
+        is_subscriber: bool,
+        is_moderator: bool,
     },
 }
 
-pub fn parse(input: impl Display, info: AdditionalInfo, config: &Config) -> CommandOutput {
+impl AdditionalInfo {
+    /// Whether the user is allowed to run moderator-only commands. Requests without
+    /// additional info have already been authenticated, so they are always allowed.
+    pub fn is_moderator(&self) -> bool {
+        match self {
+            Self::None => true,
+            Self::Discord { is_moderator, .. } => *is_moderator,
+            Self::Twitch { is_moderator, .. } => *is_moderator,
+        }
+    }
+
+    /// A unique key for the user, prefixed with their platform.
+    pub fn user_key(&self) -> Option<String> {
+        match self {
+            Self::None => None,
+            Self::Discord { user_id, .. } => Some(format!("discord:{user_id}")),
+            Self::Twitch { name, .. } => Some(format!("twitch:{}", name.to_lowercase())),
+        }
+    }
+
+    /// The blocklist scope of the guild or channel the command was sent in.
+    pub fn blocklist_scope(&self) -> Option<String> {
+        match self {
+            Self::None => None,
+            Self::Discord { guild_id, .. } => Some(format!("discord:{guild_id}")),
+            Self::Twitch { channel, .. } => Some(format!("twitch:{}", channel.to_lowercase())),
+        }
+    }
+
+    /// The display name of the user.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::None => None,
+            Self::Discord { name, .. } | Self::Twitch { name, .. } => Some(name),
+        }
+    }
+
+    /// Create a giveaway entry for the user if they are eligible.
+    pub fn giveaway_entry(&self, eligibility: Eligibility) -> Result<giveaway::Entry, String> {
+        let eligible = match (self, eligibility) {
+            (Self::None, _) => false,
+            (_, Eligibility::Everyone) => true,
+            (Self::Twitch { is_subscriber, .. }, Eligibility::Subscribers) => *is_subscriber,
+            (Self::Twitch { is_vip, .. }, Eligibility::Vips) => *is_vip,
+            (Self::Discord { .. }, _) => false,
+        };
+        if !eligible {
+            return Err(format!("This giveaway is only for {eligibility}!"));
+        }
+
+        match (self.user_key(), self) {
+            (Some(user), Self::Discord { name, .. } | Self::Twitch { name, .. }) => {
+                Ok(giveaway::Entry {
+                    user,
+                    name: name.clone(),
+                })
+            }
+            _ => Err("Unable to enter without a user!".into()),
+        }
+    }
+}
+
+pub fn parse(
+    input: impl Display,
+    info: AdditionalInfo,
+    config: &Config,
+    state: &mut State,
+) -> CommandOutput {
     let args = match Cli::try_parse_from(format!("{input}",).split(' ')) {
         Ok(args) => args,
         Err(e) => {
-            let ad_hoc_val = config.ad_hoc_command(
+            let ad_hoc_val = run_ad_hoc(
                 &input
                     .to_string()
                     .split_once(" ")
                     .unwrap_or_default()
                     .1
                     .to_string(),
+                &info,
+                config,
+                state,
             );
 
             if ad_hoc_val.is_some() {
@@ -233,7 +519,7 @@ pub fn parse(input: impl Display, info: AdditionalInfo, config: &Config) -> Comm
             Some(commands::roll(sides.parse().unwrap_or(6)).to_string())
         }
         Commands::AdHoc { ref text } => {
-            let ad_hoc_val = config.ad_hoc_command(text);
+            let ad_hoc_val = run_ad_hoc(text, &info, config, state);
 
             if ad_hoc_val.is_some() {
                 ad_hoc_val
@@ -279,15 +565,436 @@ pub fn parse(input: impl Display, info: AdditionalInfo, config: &Config) -> Comm
 
             Some(commands::lurk(&name))
         }
-        Commands::Admin(ref admin) => match admin.command {
-            AdminCommands::ReloadConfig => None,
-            _ => Some(show_help()),
+        // Polls need to be posted by the caller, see `start_poll`
+        Commands::Poll { .. } => None,
+        Commands::Vote { option } => {
+            let voter = info.user_key().map(|k| state.canonical_user(&k));
+            match (state.active_poll(), voter) {
+                (Some(poll), Some(voter)) => {
+                    if poll.vote(voter, option) {
+                        None
+                    } else {
+                        Some(format!(
+                            "Please vote for an option from 1 - {}",
+                            poll.options.len()
+                        ))
+                    }
+                }
+                (Some(_), None) => Some("Unable to vote without a user!".to_string()),
+                (None, _) => Some("There is no poll running!".to_string()),
+            }
+        }
+        Commands::Giveaway(ref giveaway) => handle_giveaway(&giveaway.command, &info, state),
+        Commands::Link { ref code } => match (&info, code) {
+            // Codes need to be sent privately by the caller, see `start_link`
+            (AdditionalInfo::Discord { .. }, None) => None,
+            (AdditionalInfo::Discord { .. }, Some(_)) => {
+                Some("Send the code in Twitch chat to finish linking!".to_string())
+            }
+            (AdditionalInfo::Twitch { name, .. }, Some(code)) => {
+                match state.complete_link(code, name) {
+                    Some(_) => Some(format!("{name} is now linked to Discord!")),
+                    None => Some("That code is invalid or has expired!".to_string()),
+                }
+            }
+            (AdditionalInfo::Twitch { .. }, None) => {
+                Some("Run link in Discord to receive a code!".to_string())
+            }
+            (AdditionalInfo::None, _) => Some("Unable to link without a user!".to_string()),
         },
+        Commands::Unlink => {
+            let removed = match &info {
+                AdditionalInfo::Discord { user_id, .. } => state.unlink(*user_id),
+                AdditionalInfo::Twitch { name, .. } => match state.linked_discord(name) {
+                    Some(id) => state.unlink(id),
+                    None => false,
+                },
+                AdditionalInfo::None => false,
+            };
+
+            if removed {
+                Some("Your accounts are no longer linked.".to_string())
+            } else {
+                Some("Your accounts are not linked!".to_string())
+            }
+        }
+        Commands::Points => match (info.user_key(), info.name()) {
+            (Some(user), Some(name)) => Some(format!("{name} has {} points!", state.points(&user))),
+            _ => Some("Unable to get points without a user!".to_string()),
+        },
+        Commands::Give { ref user, amount } => Some(give_points(user, amount, &info, state)),
+        Commands::Leaderboard => {
+            let leaderboard = state.leaderboard(10);
+            if leaderboard.is_empty() {
+                Some("Nobody has any points yet!".to_string())
+            } else {
+                Some(
+                    leaderboard
+                        .iter()
+                        .enumerate()
+                        .map(|(i, w)| format!("{}. {} - {}", i + 1, w.name, w.points))
+                        .collect::<Vec<String>>()
+                        .join(" | "),
+                )
+            }
+        }
+        Commands::Gamble { amount } => Some(gamble(amount, &info, state)),
+        Commands::AddPoints { .. } | Commands::SetPoints { .. } if !info.is_moderator() => {
+            Some("Only moderators can change points!".to_string())
+        }
+        Commands::AddPoints { ref user, amount } => {
+            let total = state.add_points(&target_user_key(user), &target_user_name(user), amount);
+            Some(format!("{user} now has {total} points."))
+        }
+        Commands::SetPoints { ref user, amount } => {
+            state.set_points(&target_user_key(user), &target_user_name(user), amount);
+            Some(format!("{user} now has {amount} points."))
+        }
+        Commands::So { .. } if !info.is_moderator() => {
+            Some("Only moderators can give shoutouts!".to_string())
+        }
+        Commands::Raid { .. } if !info.is_moderator() => {
+            Some("Only moderators can start raids!".to_string())
+        }
+        Commands::Game { ref name } if !name.is_empty() && !info.is_moderator() => {
+            Some("Only moderators can change the game!".to_string())
+        }
+        Commands::Title { ref text } if !text.is_empty() && !info.is_moderator() => {
+            Some("Only moderators can change the title!".to_string())
+        }
+        // These need Helix, so they are run by the Twitch bot
+        Commands::So { .. } | Commands::Raid { .. } => match info {
+            AdditionalInfo::Twitch { .. } => None,
+            _ => Some("This only works in Twitch chat!".to_string()),
+        },
+        // Discord asks the Twitch bot for these, see `channel_request`
+        Commands::Uptime
+        | Commands::Game { .. }
+        | Commands::Title { .. }
+        | Commands::Followage { .. } => match info {
+            AdditionalInfo::None => Some("This only works in Twitch chat or Discord!".to_string()),
+            _ => None,
+        },
+        Commands::Queue { ref name } => Some(show_queue(name, state)),
+        Commands::Modlog { .. } if !info.is_moderator() => {
+            Some("Only moderators can view the mod log!".to_string())
+        }
+        Commands::Modlog { ref user } => {
+            let actions = state.mod_actions_for(&target_user_key(user), 5);
+            if actions.is_empty() {
+                Some(format!("No moderation actions found for {user}."))
+            } else {
+                Some(
+                    actions
+                        .iter()
+                        .map(|a| a.summary())
+                        .collect::<Vec<String>>()
+                        .join(" | "),
+                )
+            }
+        }
+        Commands::Admin(_) if !info.is_moderator() => {
+            Some("Only moderators can run admin commands!".to_string())
+        }
+        Commands::Admin(ref admin) => {
+            let output = match admin.command {
+                AdminCommands::ReloadConfig => None,
+                AdminCommands::SyncRoles => Some("Syncing roles!".to_string()),
+                AdminCommands::Block { .. }
+                | AdminCommands::Unblock { .. }
+                | AdminCommands::Blocklist
+                | AdminCommands::Exempt { .. }
+                | AdminCommands::Unexempt { .. } => {
+                    Some(handle_blocklist(&admin.command, &info, state))
+                }
+                AdminCommands::Next { ref queue } => match state.dequeue(queue) {
+                    Some(entry) => Some(format!("Next in the {queue} queue: {}", entry.summary())),
+                    None => Some(format!("The {queue} queue is empty!")),
+                },
+                _ => Some(show_help()),
+            };
+
+            return CommandOutput::from((admin.command.clone(), output));
+        }
     };
 
     CommandOutput::from((args.command, output))
 }
 
+/// Start a new poll and store it in the `state`. Returns an error message if the
+/// poll could not be started.
+pub fn start_poll(
+    text: &[String],
+    duration: u64,
+    info: &AdditionalInfo,
+    state: &mut State,
+) -> Result<Poll, String> {
+    if !info.is_moderator() {
+        return Err("Only moderators can start polls!".into());
+    }
+    if state.active_poll().is_some() {
+        return Err("A poll is already running!".into());
+    }
+
+    let (question, options) = commands::poll(&text.join(" "))?;
+    let poll = Poll::new(question, options, duration);
+    state.poll = Some(poll.clone());
+
+    Ok(poll)
+}
+
+fn give_points(target: &String, amount: i64, info: &AdditionalInfo, state: &mut State) -> String {
+    let (user, name) = match (info.user_key(), info.name()) {
+        (Some(user), Some(name)) => (user, name),
+        _ => return "Unable to give points without a user!".into(),
+    };
+    if amount <= 0 {
+        return "You can only give a positive amount of points!".into();
+    }
+
+    let target_user = target_user_key(target);
+    if state.canonical_user(&user) == state.canonical_user(&target_user) {
+        return "You cannot give points to yourself!".into();
+    }
+    if !state.spend_points(&user, amount) {
+        return format!("{name} does not have {amount} points!");
+    }
+
+    state.add_points(&target_user, &target_user_name(target), amount);
+
+    format!("{name} gave {amount} points to {target}!")
+}
+
+fn gamble(amount: i64, info: &AdditionalInfo, state: &mut State) -> String {
+    let (user, name) = match (info.user_key(), info.name()) {
+        (Some(user), Some(name)) => (user, name),
+        _ => return "Unable to gamble without a user!".into(),
+    };
+    if amount <= 0 {
+        return "You can only bet a positive amount of points!".into();
+    }
+    if !state.spend_points(&user, amount) {
+        return format!("{name} does not have {amount} points!");
+    }
+
+    let roll = commands::roll(100);
+    if roll > 50 {
+        let total = state.add_points(&user, name, amount.saturating_mul(2));
+        format!("{name} rolled {roll} and won {amount} points! They now have {total} points.")
+    } else {
+        let total = state.points(&user);
+        format!("{name} rolled {roll} and lost {amount} points! They now have {total} points.")
+    }
+}
+
+/// Run the ad-hoc command, spending the points it costs.
+fn run_ad_hoc(
+    command: &String,
+    info: &AdditionalInfo,
+    config: &Config,
+    state: &mut State,
+) -> Option<String> {
+    let value = config.ad_hoc_command(command)?;
+
+    let cost = config
+        .ad_hoc_costs
+        .get(command)
+        .copied()
+        .unwrap_or_default();
+    if let Some(user) = info.user_key() {
+        if cost > 0 && !state.spend_points(&user, cost) {
+            return Some(format!("You need {cost} points to use {command}!"));
+        }
+    }
+
+    Some(value)
+}
+
+/// List the first entries of a queue.
+fn show_queue(name: &str, state: &State) -> String {
+    match state.queues.get(&name.to_lowercase()) {
+        Some(queue) if !queue.is_empty() => format!(
+            "{name} queue: {}",
+            queue
+                .iter()
+                .take(10)
+                .enumerate()
+                .map(|(i, e)| format!("{}. {}", i + 1, e.summary()))
+                .collect::<Vec<String>>()
+                .join(" | ")
+        ),
+        _ => format!("The {name} queue is empty!"),
+    }
+}
+
+/// Convert a Discord mention or a Twitch name into a platform-prefixed user key.
+fn target_user_key(user: &str) -> String {
+    match user
+        .strip_prefix("<@")
+        .and_then(|v| v.strip_suffix('>'))
+        .map(|v| v.trim_start_matches('!'))
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        Some(id) => format!("discord:{id}"),
+        None => format!("twitch:{}", user.trim_start_matches('@').to_lowercase()),
+    }
+}
+
+/// A wallet name for a user given as a command argument. Mentions are not kept, since the
+/// name is shown in the leaderboard.
+fn target_user_name(user: &str) -> String {
+    match target_user_key(user).split_once(':') {
+        Some(("discord", id)) => format!("Discord user {id}"),
+        _ => user.trim_start_matches('@').to_string(),
+    }
+}
+
+/// Handle the admin commands that manage the blocklist of the current guild or channel.
+fn handle_blocklist(command: &AdminCommands, info: &AdditionalInfo, state: &mut State) -> String {
+    let blocklist = match info.blocklist_scope() {
+        Some(scope) => state.blocklists.entry(scope).or_default(),
+        None => return "Blocklists can only be managed from Discord or Twitch!".into(),
+    };
+
+    match command {
+        AdminCommands::Block { kind, text } => match blocklist.add(*kind, &text.join(" ")) {
+            Ok(_) => format!("Blocked {kind} {}.", text.join(" ")),
+            Err(e) => e,
+        },
+        AdminCommands::Unblock { kind, text } => {
+            if blocklist.remove(*kind, &text.join(" ")) {
+                format!("Unblocked {kind} {}.", text.join(" "))
+            } else {
+                format!("That {kind} is not blocked!")
+            }
+        }
+        AdminCommands::Blocklist => blocklist.summary(),
+        AdminCommands::Exempt { role } => {
+            if !blocklist.exempt_roles.contains(role) {
+                blocklist.exempt_roles.push(role.clone());
+            }
+            format!("{role} is now exempt from the blocklist.")
+        }
+        AdminCommands::Unexempt { role } => {
+            blocklist.exempt_roles.retain(|r| r != role);
+            format!("{role} is no longer exempt from the blocklist.")
+        }
+        _ => show_help(),
+    }
+}
+
+/// Create a one-time link code for the Discord user. The code should only be shown
+/// to the user.
+pub fn start_link(info: &AdditionalInfo, state: &mut State) -> Result<String, String> {
+    match info {
+        AdditionalInfo::Discord { user_id, .. } => Ok(state.create_link_code(*user_id)),
+        _ => Err("Run link in Discord to receive a code!".into()),
+    }
+}
+
+/// The Helix request that a stream info command needs. `sender` is the Twitch name of the
+/// user that sent the command.
+pub fn channel_request(command: Commands, sender: &str) -> Option<ChannelRequest> {
+    let request = match command {
+        Commands::Uptime => ChannelRequest::Uptime,
+        Commands::Game { name } if name.is_empty() => ChannelRequest::Game,
+        Commands::Game { name } => ChannelRequest::SetGame(name.join(" ")),
+        Commands::Title { text } if text.is_empty() => ChannelRequest::Title,
+        Commands::Title { text } => ChannelRequest::SetTitle(text.join(" ")),
+        Commands::Followage { user } => ChannelRequest::Followage(
+            user.as_deref()
+                .unwrap_or(sender)
+                .trim_start_matches('@')
+                .to_lowercase(),
+        ),
+        _ => return None,
+    };
+
+    Some(request)
+}
+
+/// Handle every giveaway command except for `Start`, since giveaways need to be
+/// posted by the caller. See `start_giveaway`.
+fn handle_giveaway(
+    command: &GiveawayCommands,
+    info: &AdditionalInfo,
+    state: &mut State,
+) -> Option<String> {
+    // Linked accounts should only be able to enter once
+    let user = info.user_key().map(|k| state.canonical_user(&k));
+
+    let giveaway = match state.giveaway.as_mut() {
+        Some(v) => v,
+        None => return Some("There is no giveaway!".into()),
+    };
+
+    let text = match command {
+        GiveawayCommands::Start { .. } => return None,
+        GiveawayCommands::Enter => match info.giveaway_entry(giveaway.eligibility) {
+            Ok(mut entry) => {
+                if let Some(user) = user {
+                    entry.user = user;
+                }
+                let name = entry.name.clone();
+                if giveaway.enter(entry) {
+                    format!("{name} entered the giveaway!")
+                } else if giveaway.is_closed {
+                    "The giveaway is closed!".into()
+                } else {
+                    format!("{name} has already entered!")
+                }
+            }
+            Err(e) => e,
+        },
+        GiveawayCommands::Audit => giveaway.audit_text(),
+        _ if !info.is_moderator() => "Only moderators can manage giveaways!".into(),
+        GiveawayCommands::End => {
+            if giveaway.is_closed {
+                "The giveaway is already closed!".into()
+            } else {
+                giveaway.ends_at = Some(model::state::unix_now());
+                "Entries are closed, drawing a winner!".into()
+            }
+        }
+        GiveawayCommands::Reroll => {
+            if !giveaway.is_closed {
+                return Some("The giveaway is still running!".into());
+            }
+            match giveaway.draw() {
+                Some(winner) => format!("The new winner of {} is {}!", giveaway.prize, winner.name),
+                None => "There is nobody left to draw!".into(),
+            }
+        }
+        GiveawayCommands::Cancel => {
+            state.giveaway = None;
+            "The giveaway has been cancelled.".into()
+        }
+    };
+
+    Some(text)
+}
+
+/// Start a new giveaway and store it in the `state`. Returns an error message if the
+/// giveaway could not be started.
+pub fn start_giveaway(
+    text: &[String],
+    info: &AdditionalInfo,
+    state: &mut State,
+) -> Result<Giveaway, String> {
+    if !info.is_moderator() {
+        return Err("Only moderators can start giveaways!".into());
+    }
+    if state.giveaway.as_ref().is_some_and(|g| !g.is_closed) {
+        return Err("A giveaway is already running!".into());
+    }
+
+    let (prize, duration, eligibility) = commands::giveaway(&text.join(" "))?;
+    let giveaway = Giveaway::new(prize, duration, eligibility);
+    state.giveaway = Some(giveaway.clone());
+
+    Ok(giveaway)
+}
+
 fn show_help() -> String {
     Cli::command().render_long_help().to_string()
 }
@@ -1,7 +1,10 @@
 use std::io::BufWriter;
 
+use model::{giveaway::Eligibility, state::MAX_POLL_OPTIONS};
 use rand::Rng;
 
+use crate::utils;
+
 /// Ping pong.
 pub fn ping() -> String {
     "pong".into()
@@ -49,6 +52,53 @@ pub fn lurk(name: &String) -> String {
     format!("You are now lurking, {}", name)
 }
 
+/// Split poll text into a question and its options. The question must be in
+/// double quotes if it contains spaces.
+pub fn poll(text: &String) -> Result<(String, Vec<String>), String> {
+    let mut parts = utils::split_quoted(text).into_iter();
+
+    let question = match parts.next() {
+        Some(v) => v,
+        None => return Err("A poll needs a question!".into()),
+    };
+    let options = parts.collect::<Vec<String>>();
+
+    if options.len() < 2 {
+        return Err("A poll needs at least 2 options!".into());
+    }
+    if options.len() > MAX_POLL_OPTIONS {
+        return Err(format!(
+            "A poll can have at most {MAX_POLL_OPTIONS} options!"
+        ));
+    }
+
+    Ok((question, options))
+}
+
+/// Split giveaway text into a prize, an optional duration and an optional eligibility.
+/// The prize must be in double quotes if it contains spaces.
+pub fn giveaway(text: &String) -> Result<(String, Option<u64>, Eligibility), String> {
+    let mut parts = utils::split_quoted(text).into_iter();
+
+    let prize = match parts.next() {
+        Some(v) => v,
+        None => return Err("A giveaway needs a prize!".into()),
+    };
+    let duration = match parts.next() {
+        Some(v) => Some(
+            v.parse::<u64>()
+                .map_err(|_| format!("Invalid duration {v}, expected seconds"))?,
+        ),
+        None => None,
+    };
+    let eligibility = match parts.next() {
+        Some(v) => v.parse()?,
+        None => Eligibility::default(),
+    };
+
+    Ok((prize, duration, eligibility))
+}
+
 /// Returns public fields from the config.
 // pub async fn config() -> String {
 //     // let config = crate::CONFIG.read().await;
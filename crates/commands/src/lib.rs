@@ -1,5 +1,7 @@
 mod cli;
 mod commands;
+mod spec;
 pub mod utils;
 
 pub use cli::*;
+pub use spec::*;
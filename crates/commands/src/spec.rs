@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use clap::{ArgAction, CommandFactory};
+use model::config::Config;
+
+use super::Cli;
+
+/// Options whose values can be suggested while typing, as `(command, option)`.
+const AUTOCOMPLETE_OPTIONS: [(&str, &str); 1] = [("ad-hoc", "text")];
+/// Most chat platforms only show a limited number of suggestions.
+const MAX_SUGGESTIONS: usize = 25;
+
+/// A platform independent description of a command. Generated from the clap definitions
+/// so platforms with their own command UIs stay in sync with `parse`.
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    pub name: String,
+    pub description: String,
+    pub options: Vec<OptionSpec>,
+    pub subcommands: Vec<CommandSpec>,
+}
+
+/// A single argument of a command.
+#[derive(Debug, Clone)]
+pub struct OptionSpec {
+    pub name: String,
+    pub description: String,
+    pub required: bool,
+    /// Whether the option is passed as `--name value` instead of by position.
+    pub is_named: bool,
+    /// Whether the option is a flag that does not take a value.
+    pub is_flag: bool,
+    pub choices: Vec<String>,
+    pub autocomplete: bool,
+}
+
+impl CommandSpec {
+    fn from_command(command: &clap::Command) -> Self {
+        let name = command.get_name().to_string();
+
+        let options = command
+            .get_arguments()
+            .filter(|a| !a.is_hide_set() && !["help", "version"].contains(&a.get_id().as_str()))
+            .map(|a| {
+                let option = a.get_id().to_string();
+                OptionSpec {
+                    description: a
+                        .get_help()
+                        .map_or_else(|| option.clone(), |h| h.to_string()),
+                    required: a.is_required_set(),
+                    is_named: !a.is_positional(),
+                    is_flag: matches!(a.get_action(), ArgAction::SetTrue | ArgAction::SetFalse),
+                    choices: a
+                        .get_possible_values()
+                        .iter()
+                        .map(|v| v.get_name().to_string())
+                        .collect(),
+                    autocomplete: AUTOCOMPLETE_OPTIONS.contains(&(name.as_str(), option.as_str())),
+                    name: option,
+                }
+            })
+            .collect::<Vec<OptionSpec>>();
+
+        Self {
+            description: command
+                .get_about()
+                .map_or_else(|| name.clone(), |a| a.to_string()),
+            options,
+            subcommands: command
+                .get_subcommands()
+                .filter(|c| !c.is_hide_set())
+                .map(Self::from_command)
+                .collect(),
+            name,
+        }
+    }
+
+    /// Turn the option values into arguments that `parse` understands.
+    pub fn args(&self, values: &HashMap<String, String>) -> String {
+        let mut named = vec![];
+        let mut positional = vec![];
+
+        // Options are in the order they were defined in, which positional options rely on
+        for option in self.options.iter() {
+            let value = match values.get(&option.name) {
+                Some(v) => v,
+                None => continue,
+            };
+            match (option.is_named, option.is_flag) {
+                (true, true) if value == "true" => named.push(format!("--{}", option.name)),
+                (true, true) => {}
+                (true, false) => named.push(format!("--{} {value}", option.name)),
+                (false, _) => positional.push(value.clone()),
+            }
+        }
+
+        named
+            .into_iter()
+            .chain(positional)
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Describe every command, including `help`.
+pub fn command_specs() -> Vec<CommandSpec> {
+    let cli = Cli::command();
+    let mut specs = cli
+        .get_subcommands()
+        .map(CommandSpec::from_command)
+        .collect::<Vec<CommandSpec>>();
+
+    specs.push(CommandSpec {
+        name: "help".into(),
+        description: "Show the available commands.".into(),
+        options: vec![OptionSpec {
+            name: "command".into(),
+            description: "The command to show help for.".into(),
+            required: false,
+            is_named: false,
+            is_flag: false,
+            choices: vec![],
+            autocomplete: false,
+        }],
+        subcommands: vec![],
+    });
+
+    specs
+}
+
+/// Suggest values for an option marked as `autocomplete` that start with `partial`.
+pub fn autocomplete(command: &str, option: &str, partial: &str, config: &Config) -> Vec<String> {
+    let partial = partial.to_lowercase();
+
+    match (command, option) {
+        ("ad-hoc", "text") => config
+            .ad_hoc_commands()
+            .into_iter()
+            .filter(|c| c.to_lowercase().starts_with(&partial))
+            .take(MAX_SUGGESTIONS)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn option(name: &str, is_named: bool, is_flag: bool) -> OptionSpec {
+        OptionSpec {
+            name: name.into(),
+            description: String::new(),
+            required: false,
+            is_named,
+            is_flag,
+            choices: vec![],
+            autocomplete: false,
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn spec(name: &str) -> CommandSpec {
+        command_specs()
+            .into_iter()
+            .find(|s| s.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn keeps_positional_options_in_definition_order() {
+        let give = spec("give");
+        let names = give
+            .options
+            .iter()
+            .map(|o| o.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["user", "amount"]);
+
+        let args = give.args(&values(&[("amount", "5"), ("user", "someone")]));
+        assert_eq!(args, "someone 5");
+    }
+
+    #[test]
+    fn puts_named_options_first() {
+        let poll = spec("poll");
+        assert!(poll.options[0].is_named && !poll.options[0].is_flag);
+
+        let args = poll.args(&values(&[("text", "\"Tea?\" yes no"), ("duration", "30")]));
+        assert_eq!(args, "--duration 30 \"Tea?\" yes no");
+        assert_eq!(
+            poll.args(&values(&[("text", "\"Tea?\" yes no")])),
+            "\"Tea?\" yes no"
+        );
+    }
+
+    #[test]
+    fn only_passes_flags_that_are_set() {
+        let spec = CommandSpec {
+            name: "test".into(),
+            description: String::new(),
+            options: vec![option("loud", true, true), option("text", false, false)],
+            subcommands: vec![],
+        };
+
+        assert_eq!(
+            spec.args(&values(&[("loud", "true"), ("text", "hi")])),
+            "--loud hi"
+        );
+        assert_eq!(
+            spec.args(&values(&[("loud", "false"), ("text", "hi")])),
+            "hi"
+        );
+    }
+
+    #[test]
+    fn describes_subcommands() {
+        let giveaway = spec("giveaway");
+        let start = giveaway
+            .subcommands
+            .iter()
+            .find(|s| s.name == "start")
+            .unwrap();
+        assert_eq!(start.args(&values(&[("text", "Prize 60")])), "Prize 60");
+
+        let admin = spec("admin");
+        let block = admin
+            .subcommands
+            .iter()
+            .find(|s| s.name == "block")
+            .unwrap();
+        let args = block.args(&values(&[("text", "free followers"), ("kind", "phrase")]));
+        assert_eq!(args, "phrase free followers");
+    }
+}
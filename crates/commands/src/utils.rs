@@ -4,3 +4,60 @@ pub fn strip_command_prefix(text: &String) -> String {
         None => text.clone(),
     }
 }
+
+/// Split text on whitespace while keeping double-quoted sections together.
+/// The quotes themselves are removed.
+pub fn split_quoted(text: &str) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in text.chars() {
+        match c {
+            '"' => {
+                if in_quotes && !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+                in_quotes = !in_quotes;
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    parts.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_quoted_text_together() {
+        assert_eq!(
+            split_quoted(r#"poll "Best game?" Celeste  "Hollow Knight""#),
+            vec!["poll", "Best game?", "Celeste", "Hollow Knight"]
+        );
+    }
+
+    #[test]
+    fn skips_empty_quotes() {
+        assert_eq!(split_quoted(r#"a "" b"#), vec!["a", "b"]);
+        assert!(split_quoted(r#""""#).is_empty());
+    }
+
+    #[test]
+    fn unbalanced_quotes_run_to_the_end() {
+        assert_eq!(
+            split_quoted(r#"say "hello there world"#),
+            vec!["say", "hello there world"]
+        );
+    }
+}
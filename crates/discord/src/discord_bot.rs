@@ -1,14 +1,31 @@
-use super::Antispam;
-use chrono::Local;
-use commands::CommandOutput;
+use super::{notifications, onboarding, relay, role_menus, slash_commands, Antispam, SpamMessage};
+use commands::{AdminCommands, CommandOutput, Commands, GiveawayArgs, GiveawayCommands};
 use model::{
-    config::{self, Config},
+    config::{self, Config, SpamAction},
     creds::{BotCreds, DiscordCreds},
-    messages::{CentralMessage, DiscordMessage, TwitchMessage},
+    giveaway::{Eligibility, Entry, Giveaway},
+    messages::{
+        send_request, CentralMessage, DiscordMessage, Request, Response, Subsystem, TwitchMessage,
+        TwitchUserRoles,
+    },
+    modlog::ModAction,
+    relay::{ChatLine, RateLimiter},
+    state::{unix_now, Poll, State, MAX_POLL_OPTIONS},
 };
 
 use log::{debug, error, info};
-use serenity::{async_trait, http::CacheHttp, model::prelude::*, prelude::*};
+use serenity::{
+    async_trait,
+    http::CacheHttp,
+    model::{
+        interactions::{
+            application_command::ApplicationCommandInteraction, modal::ModalSubmitInteraction,
+            InteractionResponseType,
+        },
+        prelude::*,
+    },
+    prelude::*,
+};
 use std::{
     collections::HashMap,
     sync::{
@@ -17,130 +34,418 @@ use std::{
     },
     time::Duration,
 };
-use strfmt::Format;
 use tokio::{
     sync::{
         broadcast::{error::TryRecvError, Receiver, Sender},
+        mpsc::UnboundedReceiver,
         Mutex, RwLock,
     },
     time::Interval,
 };
 
 const UNKNOWN_MEMBER_CODE: isize = 10007;
+const POLL_EMOJIS: [&str; MAX_POLL_OPTIONS] =
+    ["1️⃣", "2️⃣", "3️⃣", "4️⃣", "5️⃣", "6️⃣", "7️⃣", "8️⃣", "9️⃣", "🔟"];
+const GIVEAWAY_EMOJI: &str = "🎉";
+/// How many times a role update is attempted before giving up.
+const ROLE_UPDATE_ATTEMPTS: u32 = 3;
+/// Discord does not allow timeouts longer than 28 days.
+const MAX_NATIVE_TIMEOUT_SECS: u64 = 28 * 24 * 60 * 60;
 
 pub struct Bot {
     config: Arc<RwLock<Config>>,
     creds: DiscordCreds,
+    state: Arc<RwLock<State>>,
 
     is_initted: AtomicBool,
 
     antispam: Arc<RwLock<Antispam>>,
     reaction_roles: Arc<RwLock<HashMap<String, u64>>>,
+    /// Role IDs by role name, used by the role menus.
+    role_ids: Arc<RwLock<HashMap<String, u64>>>,
 
     receiver: Receiver<CentralMessage>,
     sender: Sender<DiscordMessage>,
+    /// Twitch chat to mirror into Discord, taken by the relay task once the bot is ready.
+    relay_receiver: Mutex<Option<UnboundedReceiver<ChatLine>>>,
 
     interval: Arc<Mutex<Interval>>,
+    /// Limits the messages relayed into Twitch chat.
+    relay_limiter: Mutex<RateLimiter>,
 }
 
 impl Bot {
     pub fn new(
         config: Arc<RwLock<Config>>,
         creds: DiscordCreds,
+        state: Arc<RwLock<State>>,
         receiver: Receiver<CentralMessage>,
         sender: Sender<DiscordMessage>,
+        relay_receiver: UnboundedReceiver<ChatLine>,
     ) -> Self {
         Self {
             config,
             creds,
+            state,
 
             is_initted: AtomicBool::new(false),
 
             antispam: Arc::new(RwLock::new(Antispam::new())),
             reaction_roles: Arc::new(RwLock::new(HashMap::new())),
+            role_ids: Arc::new(RwLock::new(HashMap::new())),
 
             receiver,
             sender,
+            relay_receiver: Mutex::new(Some(relay_receiver)),
 
             interval: Arc::new(Mutex::new(tokio::time::interval(Duration::from_secs_f32(
                 config::default_tick_duration(),
             )))),
+            relay_limiter: Mutex::new(RateLimiter::default()),
         }
     }
 }
 
-#[async_trait]
-impl EventHandler for Bot {
-    async fn ready(&self, ctx: Context, _ready: Ready) {
-        if !self.is_initted.load(Ordering::Relaxed) {
-            self.is_initted.store(true, Ordering::Relaxed);
-            start_job_thread(self, &ctx).await;
-        }
+impl Bot {
+    /// Run the antispam rules against the `message` and act on the result.
+    /// Returns true if the message was removed.
+    async fn check_spam(&self, ctx: &Context, message: &Message) -> bool {
+        let config = self.config.read().await;
+        let user = format!("discord:{}", message.author.id.as_u64());
+
+        let blocked = {
+            let scope = format!(
+                "discord:{}",
+                message
+                    .guild_id
+                    .map_or(self.creds.guild_id, |g| *g.as_u64())
+            );
+            let roles: Vec<String> = message
+                .member
+                .as_ref()
+                .map(|m| m.roles.iter().map(|r| r.as_u64().to_string()).collect())
+                .unwrap_or_default();
+
+            self.state
+                .read()
+                .await
+                .blocklists
+                .get(&scope)
+                .filter(|b| !b.is_exempt(&roles))
+                .and_then(|b| b.find_match(&message.content))
+        };
+
+        let verdict = if let Some(entry) = blocked {
+            debug!("Blocked {entry} from {}", &message.author.name);
+
+            self.antispam.write().await.blocklist_verdict(
+                &user,
+                config.blocklist_action,
+                config.blocklist_escalate,
+                &config.antispam,
+            )
+        } else {
+            let spam_message = SpamMessage {
+                content: &message.content,
+                mentions: message.mentions.len()
+                    + message.mention_roles.len()
+                    + usize::from(message.mention_everyone),
+                account_age_secs: u64::try_from(
+                    Timestamp::now().unix_timestamp()
+                        - message.author.id.created_at().unix_timestamp(),
+                )
+                .ok(),
+            };
+
+            match self
+                .antispam
+                .write()
+                .await
+                .check(&user, &spam_message, &config.antispam)
+            {
+                Some(v) => v,
+                None => return false,
+            }
+        };
 
-        {
-            debug!("Reading Discord config from Ready");
+        debug!(
+            "Spam detected from {} by rule {}: {:?}",
+            &message.author.name, verdict.rule, verdict.action
+        );
 
-            if let Err(e) = process_config(&ctx, &self.sender, self.creds.data_channel).await {
+        let action = ModAction::new(
+            "antispam",
+            &user,
+            &message.author.name,
+            verdict.action.to_string(),
+            &verdict.reason,
+        )
+        .with_rule(verdict.rule)
+        .with_content(&message.content);
+        log_mod_action(ctx, &self.state, config.mod_log_channel, action).await;
+
+        if !verdict.silent {
+            let text = format!("Please do not spam! >:( ({})", verdict.reason);
+            if let Err(e) = message.reply_mention(ctx, text).await {
                 error!("{e}");
             }
+        }
 
-            debug!("Finished reading Discord config from Ready");
+        if verdict.action == SpamAction::Warn {
+            return false;
         }
 
-        if let Err(e) = self.sender.send(DiscordMessage::Ready) {
+        if let Err(e) = message.delete(ctx).await {
             error!("{e}");
-        } else {
-            info!("Discord bot ready!");
         }
-    }
 
-    async fn message(&self, ctx: Context, message: Message) {
-        let author_id = message.author.id.as_u64();
-        if author_id == &self.creds.bot_id {
-            return;
+        let guild_id = match message.guild_id {
+            Some(v) => v,
+            None => return true,
+        };
+
+        match verdict.action {
+            SpamAction::Timeout => {
+                self.silence(
+                    ctx,
+                    guild_id,
+                    &message.author,
+                    verdict.silence_secs,
+                    &config,
+                )
+                .await
+            }
+            SpamAction::Kick => {
+                if let Err(e) = guild_id
+                    .kick_with_reason(&ctx.http, message.author.id, &verdict.reason)
+                    .await
+                {
+                    error!("Unable to kick {}: {e}", &message.author.name);
+                }
+            }
+            _ => {}
         }
 
-        let mut antispam = self.antispam.write().await;
-        if antispam.is_spam(author_id) {
-            debug!("Spammer detected: {}", &message.author.name);
-
-            if antispam.too_many_strikes(author_id) {
-                debug!("Too many strikes for {}", &message.author.name);
+        true
+    }
 
-                if !antispam.should_silent_delete(author_id) {
-                    if let Err(e) = message.reply_mention(&ctx, "Please do not spam! >:(").await {
-                        error!("{e}");
-                    }
-                }
-                if let Err(e) = message.delete(&ctx).await {
-                    error!("{e}");
-                }
+    /// Post a message from the relay channel in Twitch chat if the relay is on.
+    async fn relay_to_twitch(&self, ctx: &Context, message: &Message) {
+        let (text, max_per_minute) = {
+            let config = self.config.read().await;
+            let relay = &config.relay;
+            // Mirrored Twitch chat comes from a webhook, so it must not be sent back
+            if !relay.discord_to_twitch
+                || relay.discord_channel != *message.channel_id.as_u64()
+                || message.author.bot
+                || message.webhook_id.is_some()
+            {
+                return;
             }
-        }
+            let content = match message.content.strip_prefix(relay.discord_trigger.as_str()) {
+                Some(v) => v,
+                None => return,
+            };
+
+            let user = message
+                .member
+                .as_ref()
+                .and_then(|m| m.nick.clone())
+                .unwrap_or_else(|| message.author.name.clone());
+            let content = model::relay::for_twitch(content, |id| {
+                message
+                    .mentions
+                    .iter()
+                    .find(|u| *u.id.as_u64() == id)
+                    .map(|u| u.name.clone())
+                    .or_else(|| RoleId(id).to_role_cached(ctx).map(|r| r.name))
+                    .or_else(|| ctx.cache.guild_channel(id).map(|c| c.name))
+            });
+            match model::relay::twitch_message(&relay.twitch_format, &user, &content) {
+                Some(v) => (v, relay.discord_to_twitch_per_minute),
+                None => return,
+            }
+        };
 
-        if !&message.content.starts_with(self.creds.bot_prefix()) {
+        if !self
+            .relay_limiter
+            .lock()
+            .await
+            .allow(max_per_minute, unix_now())
+        {
+            debug!(
+                "Too many relayed messages, not relaying {}",
+                message.author.name
+            );
             return;
         }
+        let request = Request::PostTwitch(text);
+        if let Err(e) = send_request(&self.sender, DiscordMessage::Request, request).await {
+            error!("Unable to relay to Twitch: {e}");
+        }
+    }
 
-        let config = &*self.config.read().await;
+    /// Whether `user` may use moderator commands. That is the admin and any member of the
+    /// guild who can manage messages.
+    async fn is_moderator(&self, ctx: &Context, guild_id: GuildId, user: &User) -> bool {
+        if *user.id.as_u64() == self.creds.admin_id {
+            return true;
+        }
 
-        let output = commands::parse(
-            &message.content,
-            commands::AdditionalInfo::Discord {
-                name: message.author.name.clone(),
-                user_id: *message.id.as_u64(),
-                channel_id: *message.channel_id.as_u64(),
-            },
-            &config,
-        );
+        let member = match guild_id.member(ctx, user.id).await {
+            Ok(v) => v,
+            Err(e) => {
+                debug!("Unable to get member data for {}: {e}", user.name);
+                return false;
+            }
+        };
+        match member.permissions(ctx) {
+            Ok(permissions) => permissions.manage_messages(),
+            Err(e) => {
+                error!("Unable to get permissions for {}: {e}", user.name);
+                false
+            }
+        }
+    }
+
+    /// Parse and run the command in `input`, sending the output to `reply`.
+    async fn run_command(
+        &self,
+        ctx: &Context,
+        reply: &Reply<'_>,
+        input: &str,
+        guild_id: Option<GuildId>,
+    ) {
+        let author = reply.author();
+        let guild_id = guild_id.unwrap_or(GuildId(self.creds.guild_id));
+        let is_moderator = self.is_moderator(ctx, guild_id, author).await;
+        let config = self.config.read().await;
+        let mut state = self.state.write().await;
+
+        let info = commands::AdditionalInfo::Discord {
+            name: author.name.clone(),
+            user_id: *author.id.as_u64(),
+            guild_id: *guild_id.as_u64(),
+            channel_id: *reply.channel_id().as_u64(),
+            is_moderator,
+        };
+        let output = commands::parse(input, info.clone(), &config, &mut state);
 
         match output {
+            CommandOutput::Command {
+                command: Commands::Poll { duration, text },
+                ..
+            } => match commands::start_poll(&text, duration, &info, &mut state) {
+                Ok(poll) => match post_poll(ctx, reply.channel_id(), &poll).await {
+                    Ok(m) => {
+                        if let Some(p) = state.poll.as_mut() {
+                            p.discord_message = Some((*m.channel_id.as_u64(), *m.id.as_u64()));
+                        }
+                        if let Err(e) = self.sender.send(DiscordMessage::PollStarted(poll)) {
+                            error!("{e}");
+                        }
+                    }
+                    Err(e) => error!("Unable to post poll: {e}"),
+                },
+                Err(e) => reply.send(ctx, &e, true).await,
+            },
+            CommandOutput::Command {
+                command:
+                    Commands::Giveaway(GiveawayArgs {
+                        command: GiveawayCommands::Start { text },
+                    }),
+                ..
+            } => match commands::start_giveaway(&text, &info, &mut state) {
+                Ok(giveaway) => {
+                    match post_giveaway(ctx, reply.channel_id(), &giveaway, &config).await {
+                        Ok(m) => {
+                            if let Some(g) = state.giveaway.as_mut() {
+                                g.discord_message = Some((*m.channel_id.as_u64(), *m.id.as_u64()));
+                            }
+                            if let Err(e) =
+                                self.sender.send(DiscordMessage::GiveawayStarted(giveaway))
+                            {
+                                error!("{e}");
+                            }
+                        }
+                        Err(e) => error!("Unable to post giveaway: {e}"),
+                    }
+                }
+                Err(e) => reply.send(ctx, &e, true).await,
+            },
+            CommandOutput::Command {
+                command: Commands::Link { code: None },
+                ..
+            } => match commands::start_link(&info, &mut state) {
+                Ok(code) => {
+                    let text = format!(
+                        "Send `{} link {code}` in Twitch chat to link your accounts. The code expires in {} minutes.",
+                        self.creds.bot_prefix(),
+                        model::state::LINK_CODE_SECS / 60
+                    );
+                    match author.direct_message(ctx, |m| m.content(text)).await {
+                        Ok(_) => reply.send(ctx, "Check your DMs!", true).await,
+                        Err(e) => {
+                            error!("Unable to DM link code: {e}");
+                            reply.send(ctx, "Unable to DM you a code!", true).await
+                        }
+                    }
+                }
+                Err(e) => reply.send(ctx, &e, true).await,
+            },
+            CommandOutput::Command {
+                value: None,
+                command:
+                    command @ (Commands::Uptime
+                    | Commands::Game { .. }
+                    | Commands::Title { .. }
+                    | Commands::Followage { .. }),
+            } => {
+                // Followage defaults to the linked Twitch account
+                let twitch_name = state
+                    .links
+                    .get(author.id.as_u64())
+                    .cloned()
+                    .unwrap_or_else(|| author.name.clone());
+                // The Twitch bot may need the state to answer, and the answer can take seconds
+                drop(state);
+                drop(config);
+
+                if let Some(request) = commands::channel_request(command, &twitch_name) {
+                    reply.defer(ctx).await;
+                    let text = match send_request(
+                        &self.sender,
+                        DiscordMessage::Request,
+                        Request::Channel(request),
+                    )
+                    .await
+                    {
+                        Ok(Response::Text(v)) => v,
+                        Ok(_) => "Done!".into(),
+                        Err(e) => {
+                            error!("Unable to ask Twitch: {e}");
+                            "Unable to reach the Twitch bot, try again later!".into()
+                        }
+                    };
+                    reply.send(ctx, &text, false).await;
+                }
+            }
             CommandOutput::Command { value, .. } => {
                 if let Some(v) = value {
-                    reply_mention(&ctx, &message, &v).await;
+                    reply.send(ctx, &v, false).await;
+                }
+            }
+            CommandOutput::AdminCommand { value, command } => {
+                if let AdminCommands::SyncRoles = command {
+                    if let Err(e) = self.sender.send(DiscordMessage::SyncRolesRequested) {
+                        error!("{e}");
+                    }
+                }
+
+                if let Some(v) = value {
+                    reply.send(ctx, &v, false).await;
                 }
             }
-            CommandOutput::AdminCommand { .. } => {}
             CommandOutput::Error {
                 message: error_text,
                 is_help,
@@ -153,9 +458,237 @@ impl EventHandler for Bot {
                 } else {
                     format!("```{error_text}```")
                 };
-                reply_mention(&ctx, &message, &text).await
+                reply.send(ctx, &text, true).await
+            }
+        }
+
+        reply.finish(ctx).await;
+    }
+
+    /// Grant or revoke the role for a reaction on a role menu or in the roles channel as
+    /// soon as it is added or removed.
+    async fn handle_reaction_role(&self, ctx: &Context, reaction: &Reaction, is_add: bool) {
+        let user_id = match reaction.user_id {
+            Some(v) if *v.as_u64() != self.creds.bot_id => v,
+            _ => return,
+        };
+
+        {
+            let config = self.config.read().await;
+            if is_add
+                && onboarding::handle_rules_reaction(ctx, &config.onboarding, &self.state, reaction)
+                    .await
+            {
+                return;
+            }
+
+            let role_ids = self.role_ids.read().await;
+            if role_menus::handle_menu_reaction(
+                ctx,
+                &config,
+                &self.state,
+                &role_ids,
+                reaction,
+                is_add,
+            )
+            .await
+            {
+                return;
+            }
+
+            if *reaction.channel_id.as_u64() != config.roles_channel {
+                return;
+            }
+        }
+
+        let role_id = match self
+            .reaction_roles
+            .read()
+            .await
+            .iter()
+            .find(|(emoji, _)| is_same_emoji(&parse_emoji(emoji), &reaction.emoji))
+        {
+            Some((_, id)) => *id,
+            None => return,
+        };
+        let guild_id = reaction
+            .guild_id
+            .map_or(self.creds.guild_id, |g| *g.as_u64());
+
+        if let Err(e) = update_member_role(ctx, guild_id, *user_id.as_u64(), role_id, is_add).await
+        {
+            error!(
+                "Unable to {} role {role_id} for {user_id}: {e}",
+                if is_add { "add" } else { "remove" }
+            );
+        }
+    }
+
+    /// Silence the `user` for `secs` seconds, using the timeout role if one is configured
+    /// and Discord's own timeouts otherwise.
+    async fn silence(
+        &self,
+        ctx: &Context,
+        guild_id: GuildId,
+        user: &User,
+        secs: u64,
+        config: &Config,
+    ) {
+        if config.timeout_role_id != 0 {
+            if let Err(e) = ctx
+                .http
+                .add_member_role(
+                    *guild_id.as_u64(),
+                    *user.id.as_u64(),
+                    config.timeout_role_id,
+                    Some("Spamming"),
+                )
+                .await
+            {
+                error!("Unable to silence {}: {e}", &user.name);
+                return;
+            }
+
+            self.state
+                .write()
+                .await
+                .add_silence(*user.id.as_u64(), config.timeout_role_id, secs);
+            return;
+        }
+
+        let secs = secs.min(MAX_NATIVE_TIMEOUT_SECS) as i64;
+        let until = match Timestamp::from_unix_timestamp(Timestamp::now().unix_timestamp() + secs) {
+            Ok(v) => v,
+            Err(e) => {
+                error!("{e}");
+                return;
+            }
+        };
+        if let Err(e) = guild_id
+            .edit_member(&ctx.http, user.id, |m| {
+                m.disable_communication_until_datetime(until)
+            })
+            .await
+        {
+            error!("Unable to time out {}: {e}", &user.name);
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Bot {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        if !self.is_initted.load(Ordering::Relaxed) {
+            self.is_initted.store(true, Ordering::Relaxed);
+            start_job_thread(self, &ctx).await;
+
+            if let Err(e) = slash_commands::register_commands(&ctx, self.creds.guild_id).await {
+                error!("Unable to register slash commands: {e}");
             }
         }
+
+        {
+            debug!("Reading Discord config from Ready");
+
+            if let Err(e) = process_config(&ctx, &self.sender, self.creds.data_channel).await {
+                error!("{e}");
+            }
+
+            debug!("Finished reading Discord config from Ready");
+        }
+
+        if let Err(e) = self.sender.send(DiscordMessage::Ready) {
+            error!("{e}");
+        } else {
+            info!("Discord bot ready!");
+        }
+    }
+
+    async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
+        let config = self.config.read().await;
+        onboarding::welcome(&ctx, &config.onboarding, &new_member).await;
+    }
+
+    async fn guild_member_removal(
+        &self,
+        ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member_data_if_available: Option<Member>,
+    ) {
+        let config = self.config.read().await;
+        onboarding::goodbye(&ctx, &config.onboarding, guild_id, &user).await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        self.handle_reaction_role(&ctx, &reaction, true).await;
+    }
+
+    async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        self.handle_reaction_role(&ctx, &reaction, false).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::ApplicationCommand(command) => {
+                if command.data.name == slash_commands::RHAI_COMMAND {
+                    slash_commands::show_rhai_modal(&ctx, &command).await;
+                    return;
+                }
+
+                let input = slash_commands::command_input(self.creds.bot_prefix(), &command);
+                let reply = Reply::new(ReplyTarget::Command(&command));
+                self.run_command(&ctx, &reply, &input, command.guild_id)
+                    .await;
+            }
+            Interaction::Autocomplete(autocomplete) => {
+                let config = self.config.read().await;
+                slash_commands::handle_autocomplete(&ctx, &config, &autocomplete).await;
+            }
+            Interaction::ModalSubmit(modal) => {
+                if let Some(input) = slash_commands::modal_input(self.creds.bot_prefix(), &modal) {
+                    let reply = Reply::new(ReplyTarget::Modal(&modal));
+                    self.run_command(&ctx, &reply, &input, modal.guild_id).await;
+                }
+            }
+            Interaction::MessageComponent(component) => {
+                let config = self.config.read().await;
+                if onboarding::handle_rules_interaction(&ctx, &config.onboarding, &component).await
+                {
+                    return;
+                }
+
+                let role_ids = self.role_ids.read().await;
+                role_menus::handle_menu_interaction(&ctx, &config, &role_ids, &component).await;
+            }
+            Interaction::Ping(_) => {}
+        }
+    }
+
+    async fn message(&self, ctx: Context, message: Message) {
+        let author_id = message.author.id.as_u64();
+        if author_id == &self.creds.bot_id {
+            return;
+        }
+        // Mirrored Twitch chat was already checked by the Twitch bot
+        if message.webhook_id.is_some()
+            && *message.channel_id.as_u64() == self.config.read().await.relay.discord_channel
+        {
+            return;
+        }
+
+        if author_id != &self.creds.admin_id && self.check_spam(&ctx, &message).await {
+            return;
+        }
+
+        if !&message.content.starts_with(self.creds.bot_prefix()) {
+            self.relay_to_twitch(&ctx, &message).await;
+            return;
+        }
+
+        let reply = Reply::new(ReplyTarget::Message(&message));
+        self.run_command(&ctx, &reply, &message.content, message.guild_id)
+            .await;
     }
 
     async fn message_update(
@@ -212,12 +745,524 @@ async fn process_config(
     Ok(())
 }
 
-async fn reply_mention(cache_http: impl CacheHttp, message: &Message, text: &String) {
-    if let Err(e) = message.reply(cache_http, text).await {
+/// Post the `poll` in the given `channel` and add a reaction for each option.
+async fn post_poll(ctx: &Context, channel: ChannelId, poll: &Poll) -> anyhow::Result<Message> {
+    let message = channel
+        .send_message(ctx, |m| {
+            m.content(format!(
+                "**Poll:** {}\n{}\nReact to vote! Closes <t:{}:R>",
+                poll.question,
+                poll.options_text(),
+                poll.ends_at
+            ))
+        })
+        .await?;
+
+    for emoji in POLL_EMOJIS.iter().take(poll.options.len()) {
+        message
+            .react(ctx, ReactionType::Unicode(emoji.to_string()))
+            .await?;
+    }
+
+    Ok(message)
+}
+
+/// Close the running poll if it has finished. Discord reactions are only counted
+/// for users that did not already vote with a command.
+async fn close_poll(
+    ctx: &Context,
+    creds: &DiscordCreds,
+    state: &RwLock<State>,
+    sender: &Sender<DiscordMessage>,
+    poll_channel_id: u64,
+) {
+    let poll = {
+        let mut state = state.write().await;
+        match state.poll.as_ref() {
+            Some(p) if p.is_finished() => state.poll.take(),
+            _ => None,
+        }
+    };
+    let poll = match poll {
+        Some(v) => v,
+        None => return,
+    };
+
+    let mut reactions = vec![0; poll.options.len()];
+    if let Some((channel_id, message_id)) = poll.discord_message {
+        let mut counted = vec![];
+        for (i, emoji) in POLL_EMOJIS.iter().take(poll.options.len()).enumerate() {
+            let users = reaction_users(
+                ctx,
+                ChannelId(channel_id),
+                MessageId(message_id),
+                ReactionType::Unicode(emoji.to_string()),
+            )
+            .await;
+
+            for user in users {
+                let id = *user.id.as_u64();
+                if id == creds.bot_id
+                    || counted.contains(&id)
+                    || poll.votes.contains_key(&format!("discord:{id}"))
+                {
+                    continue;
+                }
+                counted.push(id);
+                reactions[i] += 1;
+            }
+        }
+    }
+
+    let results = poll.results_text(&poll.tally(&reactions));
+
+    let channel = match poll.discord_message {
+        Some((channel_id, _)) => channel_id,
+        None => poll_channel_id,
+    };
+    if channel != 0 {
+        if let Err(e) = ChannelId(channel).say(ctx, &results).await {
+            error!("Unable to post poll results: {e}");
+        }
+    }
+
+    if let Err(e) = sender.send(DiscordMessage::PollEnded(results)) {
+        error!("{e}");
+    }
+}
+
+/// Post the `giveaway` in the given `channel` and add the reaction used for entering.
+async fn post_giveaway(
+    ctx: &Context,
+    channel: ChannelId,
+    giveaway: &Giveaway,
+    config: &Config,
+) -> anyhow::Result<Message> {
+    let closes = match giveaway.ends_at {
+        Some(t) => format!("Closes <t:{t}:R>"),
+        None => "Closes when a moderator ends it".to_string(),
+    };
+    let enter = match giveaway.eligibility {
+        Eligibility::Everyone => format!(
+            "React with {GIVEAWAY_EMOJI} or type {} in Twitch chat to enter!",
+            config.giveaway_keyword
+        ),
+        _ => format!(
+            "Type {} in Twitch chat to enter! Only for {}.",
+            config.giveaway_keyword, giveaway.eligibility
+        ),
+    };
+
+    let message = channel
+        .send_message(ctx, |m| {
+            m.content(format!(
+                "**Giveaway:** {}\n{enter}\n{closes}",
+                giveaway.prize
+            ))
+        })
+        .await?;
+
+    if giveaway.eligibility == Eligibility::Everyone {
+        message
+            .react(ctx, ReactionType::Unicode(GIVEAWAY_EMOJI.to_string()))
+            .await?;
+    }
+
+    Ok(message)
+}
+
+/// Record the moderation `action` and post it to the mod log channel if one is set.
+async fn log_mod_action(
+    ctx: &Context,
+    state: &RwLock<State>,
+    mod_log_channel: u64,
+    action: ModAction,
+) {
+    if mod_log_channel != 0 {
+        if let Err(e) = post_mod_action(ctx, ChannelId(mod_log_channel), &action).await {
+            error!("Unable to post mod action: {e}");
+        }
+    }
+
+    state.write().await.record_mod_action(action);
+}
+
+/// Answer a request from another subsystem.
+async fn answer_request(ctx: &Context, request: &Request) -> Response {
+    match request {
+        Request::PostDiscord { channel_id, text } => {
+            // Requests can carry text from Twitch users, so they should not ping anyone
+            let result = ChannelId(*channel_id)
+                .send_message(ctx, |m| {
+                    m.content(text).allowed_mentions(|a| a.empty_parse())
+                })
+                .await;
+
+            match result {
+                Ok(_) => Response::Done,
+                Err(e) => Response::Error(format!("Unable to post in {channel_id}: {e}")),
+            }
+        }
+        _ => Response::Error(format!("Discord cannot answer {request:?}")),
+    }
+}
+
+async fn post_mod_action(
+    ctx: &Context,
+    channel: ChannelId,
+    action: &ModAction,
+) -> anyhow::Result<Message> {
+    let message = channel
+        .send_message(ctx, |m| {
+            m.embed(|e| {
+                e.title(format!("{}: {}", action.action, action.target_name))
+                    .field("User", &action.target, true)
+                    .field("Actor", &action.actor, true)
+                    .field("Reason", &action.reason, false);
+                if let Some(rule) = &action.rule {
+                    e.field("Rule", rule, true);
+                }
+                if let Some(content) = &action.content {
+                    // Embed fields cannot be empty or longer than 1024 characters
+                    let content = content.chars().take(1000).collect::<String>();
+                    if !content.is_empty() {
+                        e.field("Content", content, false);
+                    }
+                }
+                if let Ok(t) = Timestamp::from_unix_timestamp(action.timestamp as i64) {
+                    e.timestamp(t);
+                }
+
+                e
+            })
+        })
+        .await?;
+
+    Ok(message)
+}
+
+/// Convert an emoji from the config into a reaction. Custom emoji use Discord's
+/// `<:name:id>` format, anything else is treated as a unicode emoji.
+pub(crate) fn parse_emoji(emoji: &str) -> ReactionType {
+    ReactionType::try_from(emoji).unwrap_or_else(|_| ReactionType::Unicode(emoji.to_string()))
+}
+
+/// Compare reactions while ignoring the names of custom emoji, since those can be renamed.
+pub(crate) fn is_same_emoji(a: &ReactionType, b: &ReactionType) -> bool {
+    match (a, b) {
+        (ReactionType::Custom { id: a, .. }, ReactionType::Custom { id: b, .. }) => a == b,
+        (ReactionType::Unicode(a), ReactionType::Unicode(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Add or remove a role, retrying with a backoff if Discord is rate limiting us or
+/// having problems.
+pub(crate) async fn update_member_role(
+    ctx: &Context,
+    guild_id: u64,
+    user_id: u64,
+    role_id: u64,
+    is_add: bool,
+) -> serenity::Result<()> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let result = if is_add {
+            ctx.http
+                .add_member_role(guild_id, user_id, role_id, Some("Reaction role"))
+                .await
+        } else {
+            ctx.http
+                .remove_member_role(guild_id, user_id, role_id, Some("Reaction role"))
+                .await
+        };
+
+        let is_retryable = match &result {
+            Err(SerenityError::Http(e)) => e
+                .status_code()
+                .is_some_and(|s| s.as_u16() == 429 || s.is_server_error()),
+            _ => false,
+        };
+        if !is_retryable || attempt >= ROLE_UPDATE_ATTEMPTS {
+            return result;
+        }
+
+        debug!("Retrying role update for {user_id}, attempt {attempt}");
+        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+    }
+}
+
+/// Remove the timeout role from users whose silence has run out.
+async fn unsilence_expired(ctx: &Context, creds: &DiscordCreds, state: &RwLock<State>) {
+    let expired = state.write().await.take_expired_silences();
+    for silence in expired {
+        if let Err(e) = ctx
+            .http
+            .remove_member_role(
+                creds.guild_id,
+                silence.user_id,
+                silence.role_id,
+                Some("Silence ended"),
+            )
+            .await
+        {
+            error!("Unable to unsilence {}: {e}", silence.user_id);
+        }
+    }
+}
+
+/// Remove the synced Twitch roles from users that unlinked their accounts.
+async fn revoke_unlinked_roles(
+    ctx: &Context,
+    creds: &DiscordCreds,
+    config: &RwLock<Config>,
+    state: &RwLock<State>,
+) {
+    let unlinked = state.write().await.take_unlinked();
+    if unlinked.is_empty() {
+        return;
+    }
+
+    let role_ids = {
+        let config = config.read().await;
+        [
+            config.twitch_subscriber_role,
+            config.twitch_vip_role,
+            config.twitch_moderator_role,
+        ]
+    };
+
+    for user_id in unlinked {
+        for role_id in role_ids.iter().filter(|r| **r != 0) {
+            if let Err(e) = ctx
+                .http
+                .remove_member_role(
+                    creds.guild_id,
+                    user_id,
+                    *role_id,
+                    Some("Twitch account unlinked"),
+                )
+                .await
+            {
+                error!("Unable to remove role {role_id} from unlinked user {user_id}: {e}");
+            }
+        }
+    }
+}
+
+/// Close the giveaway if entries have closed, adding everyone that reacted on Discord
+/// as an entry before drawing the winner.
+async fn close_giveaway(
+    ctx: &Context,
+    creds: &DiscordCreds,
+    state: &RwLock<State>,
+    sender: &Sender<DiscordMessage>,
+    giveaway_channel_id: u64,
+) {
+    let (discord_message, eligibility) = match state.read().await.giveaway.as_ref() {
+        Some(g) if g.should_close() => (g.discord_message, g.eligibility),
+        _ => return,
+    };
+
+    let mut entries = vec![];
+    if let (Some((channel_id, message_id)), Eligibility::Everyone) = (discord_message, eligibility)
+    {
+        for user in reaction_users(
+            ctx,
+            ChannelId(channel_id),
+            MessageId(message_id),
+            ReactionType::Unicode(GIVEAWAY_EMOJI.to_string()),
+        )
+        .await
+        {
+            if *user.id.as_u64() == creds.bot_id {
+                continue;
+            }
+            entries.push(Entry {
+                user: format!("discord:{}", user.id.as_u64()),
+                name: user.name,
+            });
+        }
+    }
+
+    let results = {
+        let mut state = state.write().await;
+        let giveaway = match state.giveaway.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+
+        for entry in entries {
+            giveaway.enter(entry);
+        }
+
+        match giveaway.draw() {
+            Some(winner) => format!(
+                "The winner of {} is {}! Out of {} entries.",
+                giveaway.prize,
+                winner.name,
+                giveaway.entries.len()
+            ),
+            None => format!("Nobody entered the giveaway for {} :(", giveaway.prize),
+        }
+    };
+
+    let channel = match discord_message {
+        Some((channel_id, _)) => channel_id,
+        None => giveaway_channel_id,
+    };
+    if channel != 0 {
+        if let Err(e) = ChannelId(channel).say(ctx, &results).await {
+            error!("Unable to post giveaway results: {e}");
+        }
+    }
+
+    if let Err(e) = sender.send(DiscordMessage::GiveawayEnded(results)) {
         error!("{e}");
     }
 }
 
+/// Get every user that reacted with the given `reaction`, one page at a time.
+async fn reaction_users(
+    ctx: &Context,
+    channel: ChannelId,
+    message: MessageId,
+    reaction: ReactionType,
+) -> Vec<User> {
+    const PAGE_MAX: u8 = 100;
+
+    let mut all_users = vec![];
+    let mut after: Option<UserId> = None;
+    loop {
+        let users = match channel
+            .reaction_users(&ctx.http, message, reaction.clone(), Some(PAGE_MAX), after)
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to get users that reacted to {reaction}: {e}");
+                break;
+            }
+        };
+
+        let is_last_page = users.len() < PAGE_MAX.into();
+        after = users.last().map(|u| u.id);
+        all_users.extend(users);
+
+        if is_last_page || after.is_none() {
+            break;
+        }
+    }
+
+    all_users
+}
+
+/// Where a command came from, and where its output is sent.
+#[derive(Clone, Copy)]
+enum ReplyTarget<'a> {
+    Message(&'a Message),
+    Command(&'a ApplicationCommandInteraction),
+    Modal(&'a ModalSubmitInteraction),
+}
+
+/// Sends command output. Interactions need exactly one response, so `finish` sends a
+/// response if nothing else was sent.
+struct Reply<'a> {
+    target: ReplyTarget<'a>,
+    has_responded: AtomicBool,
+}
+
+impl<'a> Reply<'a> {
+    fn new(target: ReplyTarget<'a>) -> Self {
+        Self {
+            target,
+            has_responded: AtomicBool::new(false),
+        }
+    }
+
+    fn author(&self) -> &User {
+        match self.target {
+            ReplyTarget::Message(m) => &m.author,
+            ReplyTarget::Command(c) => &c.user,
+            ReplyTarget::Modal(m) => &m.user,
+        }
+    }
+
+    fn channel_id(&self) -> ChannelId {
+        match self.target {
+            ReplyTarget::Message(m) => m.channel_id,
+            ReplyTarget::Command(c) => c.channel_id,
+            ReplyTarget::Modal(m) => m.channel_id,
+        }
+    }
+
+    /// Reply with `text`. Ephemeral replies are only shown to the author of an
+    /// interaction, messages are always replied to publicly.
+    async fn send(&self, ctx: &Context, text: &str, is_ephemeral: bool) {
+        // Interactions can only be responded to once, anything after that is a follow up
+        let is_followup = self.has_responded.swap(true, Ordering::Relaxed);
+
+        let result = match (self.target, is_followup) {
+            (ReplyTarget::Message(m), _) => m.reply(ctx, text).await.map(|_| ()),
+            (ReplyTarget::Command(c), false) => {
+                c.create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| d.content(text).ephemeral(is_ephemeral))
+                })
+                .await
+            }
+            (ReplyTarget::Command(c), true) => c
+                .create_followup_message(&ctx.http, |f| f.content(text).ephemeral(is_ephemeral))
+                .await
+                .map(|_| ()),
+            (ReplyTarget::Modal(m), false) => {
+                m.create_interaction_response(&ctx.http, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| d.content(text).ephemeral(is_ephemeral))
+                })
+                .await
+            }
+            (ReplyTarget::Modal(m), true) => m
+                .create_followup_message(&ctx.http, |f| f.content(text).ephemeral(is_ephemeral))
+                .await
+                .map(|_| ()),
+        };
+
+        if let Err(e) = result {
+            error!("{e}");
+        }
+    }
+
+    /// Acknowledge a command interaction whose output takes a while, since Discord only
+    /// waits three seconds for a response.
+    async fn defer(&self, ctx: &Context) {
+        let command = match self.target {
+            ReplyTarget::Command(c) => c,
+            _ => return,
+        };
+        if self.has_responded.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        if let Err(e) = command.defer(&ctx.http).await {
+            error!("{e}");
+        }
+    }
+
+    /// Acknowledge an interaction that did not get a response.
+    async fn finish(&self, ctx: &Context) {
+        if matches!(self.target, ReplyTarget::Message(_))
+            || self.has_responded.load(Ordering::Relaxed)
+        {
+            return;
+        }
+
+        self.send(ctx, "Done!", true).await;
+    }
+}
+
 // TODO cleanup code after figuring out which things are needed in the job thread
 async fn start_job_thread(bot: &Bot, ctx: &Context) {
     debug!("Starting Discord job thread.");
@@ -227,134 +1272,204 @@ async fn start_job_thread(bot: &Bot, ctx: &Context) {
 
         let config = bot.config.clone();
         let creds = bot.creds.clone();
+        let state = bot.state.clone();
 
         // let antispam = bot.antispam.clone();
         let reaction_roles = bot.reaction_roles.clone();
+        let role_ids = bot.role_ids.clone();
 
         let mut receiver = bot.receiver.resubscribe();
-        // let sender = bot.sender.clone();
+        let sender = bot.sender.clone();
 
         let interval = bot.interval.clone();
 
+        if let Some(relay_receiver) = bot.relay_receiver.lock().await.take() {
+            tokio::spawn(relay::run(
+                ctx.clone(),
+                config.clone(),
+                sender.clone(),
+                relay_receiver,
+            ));
+        }
+
         async move {
-            loop {
+            'jobs: loop {
                 let _ = interval.lock().await.tick().await;
 
-                match receiver.try_recv() {
-                    Ok(m) => match m {
-                        CentralMessage::ConfigUpdated => {
-                            debug!("Updating config");
+                {
+                    let (poll_channel, giveaway_channel) = {
+                        let config = config.read().await;
+                        (config.poll_channel, config.giveaway_channel)
+                    };
+                    close_poll(&client, &creds, &state, &sender, poll_channel).await;
+                    close_giveaway(&client, &creds, &state, &sender, giveaway_channel).await;
+                    unsilence_expired(&client, &creds, &state).await;
+                    revoke_unlinked_roles(&client, &creds, &config, &state).await;
+                }
 
-                            let config = config.read().await;
+                // Drain every pending message, so bursts of relayed chat do not lag
+                loop {
+                    match receiver.try_recv() {
+                        Ok(m) => match m {
+                            CentralMessage::ConfigUpdated => {
+                                debug!("Updating config");
 
-                            *interval.lock().await = tokio::time::interval(
-                                Duration::from_secs_f32(config.tick_duration),
-                            );
+                                let config = config.read().await;
+
+                                *interval.lock().await = tokio::time::interval(
+                                    Duration::from_secs_f32(config.tick_duration),
+                                );
 
-                            {
-                                let mut rr = reaction_roles.write().await;
-
-                                // TODO panic or try again
-                                let guild = GuildId(creds.guild_id);
-                                for (id, role) in
-                                    guild.roles(&client.http).await.unwrap_or_else(|e| {
-                                        error!("Unable to get guild roles: {e}");
-                                        HashMap::new()
-                                    })
                                 {
-                                    let id = id.as_u64();
-                                    let emoji = match config.reaction_roles.get(&role.name) {
-                                        Some(v) => v,
-                                        None => continue,
-                                    };
-                                    rr.insert(emoji.clone(), id.clone());
+                                    let mut rr = reaction_roles.write().await;
+                                    let mut ids = role_ids.write().await;
+
+                                    // TODO panic or try again
+                                    let guild = GuildId(creds.guild_id);
+                                    for (id, role) in
+                                        guild.roles(&client.http).await.unwrap_or_else(|e| {
+                                            error!("Unable to get guild roles: {e}");
+                                            HashMap::new()
+                                        })
+                                    {
+                                        let id = id.as_u64();
+                                        ids.insert(role.name.clone(), *id);
+                                        let emoji = match config.reaction_roles.get(&role.name) {
+                                            Some(v) => v,
+                                            None => continue,
+                                        };
+                                        rr.insert(emoji.clone(), id.clone());
+                                    }
                                 }
-                            }
 
-                            {
-                                let rr = reaction_roles.read().await;
+                                {
+                                    let rr = reaction_roles.read().await;
+
+                                    process_old_reaction_roles(
+                                        &client,
+                                        &creds,
+                                        &state,
+                                        &rr.clone(),
+                                        config.roles_channel,
+                                        config.mod_log_channel,
+                                    )
+                                    .await;
+                                }
+
+                                role_menus::sync_role_menus(&client, &config, &state).await;
+                                onboarding::sync_rules(&client, &config.onboarding, &state).await;
 
-                                process_old_reaction_roles(
+                                debug!("Finished updating config!");
+                            }
+                            CentralMessage::Twitch(TwitchMessage::StreamStarted(stream)) => {
+                                let config = config.read().await;
+                                notifications::notify_live(&client, &config, &state, &stream).await;
+                            }
+                            CentralMessage::Twitch(TwitchMessage::StreamUpdated(stream)) => {
+                                notifications::notify_update(&client, &state, &stream).await;
+                            }
+                            CentralMessage::Twitch(TwitchMessage::StreamEnded {
+                                stream,
+                                vod_url,
+                            }) => {
+                                notifications::notify_offline(
                                     &client,
-                                    &creds,
-                                    &rr.clone(),
-                                    config.roles_channel,
+                                    &state,
+                                    &stream,
+                                    vod_url.as_deref(),
                                 )
                                 .await;
                             }
+                            CentralMessage::Twitch(TwitchMessage::Alert(alert)) => {
+                                let config = config.read().await;
+                                notifications::post_alert(&client, &config.alerts, &alert).await;
+                            }
+                            CentralMessage::Twitch(TwitchMessage::PollStarted(poll)) => {
+                                let poll_channel = config.read().await.poll_channel;
+                                if poll_channel == 0 {
+                                    debug!("No poll channel configured, not posting Twitch poll");
+                                    continue;
+                                }
 
-                            debug!("Finished updating config!");
-                        }
-                        CentralMessage::Twitch(TwitchMessage::ChannelLive {
-                            channel,
-                            title,
-                            url,
-                        }) => {
-                            let config = config.read().await;
-
-                            let notification_channel =
-                                ChannelId(config.stream_notification_channel);
-
-                            if let Ok(v) =
-                                notification_channel.messages(&client, |f| f.limit(1)).await
-                            {
-                                if let Some(m) = v.first() {
-                                    let since = m.timestamp.signed_duration_since(Local::now());
-
-                                    if since.num_seconds().unsigned_abs()
-                                        < config.min_stream_notification_secs
-                                    {
-                                        debug!("Too early to send a stream notification!");
-                                        return;
+                                match post_poll(&client, ChannelId(poll_channel), &poll).await {
+                                    Ok(m) => {
+                                        if let Some(p) = state.write().await.poll.as_mut() {
+                                            p.discord_message =
+                                                Some((*m.channel_id.as_u64(), *m.id.as_u64()));
+                                        }
                                     }
-                                } else {
-                                    error!("Unable to find last stream notification");
-                                    return;
+                                    Err(e) => error!("Unable to post poll: {e}"),
                                 }
-                            } else {
-                                error!("Unable to get last stream notification message");
-                                return;
                             }
+                            CentralMessage::Twitch(TwitchMessage::GiveawayStarted(giveaway)) => {
+                                let config = config.read().await;
+                                if config.giveaway_channel == 0 {
+                                    debug!("No giveaway channel, not posting Twitch giveaway");
+                                    continue;
+                                }
 
-                            if let Err(e) = notification_channel
-                                .send_message(&client, |f| {
-                                    // TODO roll my own dynamic formatter since this one is pretty simple
-                                    let mut map = HashMap::new();
-                                    map.insert("channel".to_string(), channel.to_string());
-                                    map.insert("title".to_string(), title.to_string());
-                                    map.insert("url".to_string(), url.to_string());
-
-                                    // config.stream_notification_format.replace("{channel}", channel.as_str()).replace("{title}", title.as_str()).replace("{url}", url.as_str());
-
-                                    f.content(
-                                        config
-                                            .stream_notification_format
-                                            .format(&map)
-                                            .unwrap_or(format!("{channel} is live! {title}\n{url}\n\nERROR occurred: Failed to format message using custom format :<")),
-                                    )
-                                })
+                                match post_giveaway(
+                                    &client,
+                                    ChannelId(config.giveaway_channel),
+                                    &giveaway,
+                                    &config,
+                                )
                                 .await
+                                {
+                                    Ok(m) => {
+                                        if let Some(g) = state.write().await.giveaway.as_mut() {
+                                            g.discord_message =
+                                                Some((*m.channel_id.as_u64(), *m.id.as_u64()));
+                                        }
+                                    }
+                                    Err(e) => error!("Unable to post giveaway: {e}"),
+                                }
+                            }
+                            CentralMessage::Twitch(TwitchMessage::UserRoles(roles)) => {
+                                sync_twitch_roles(&client, &creds, &config, &state, &roles).await;
+                            }
+                            CentralMessage::Twitch(TwitchMessage::ModAction(action)) => {
+                                let mod_log_channel = config.read().await.mod_log_channel;
+
+                                // Already recorded by the Twitch bot, only needs to be posted
+                                if mod_log_channel != 0 {
+                                    if let Err(e) = post_mod_action(
+                                        &client,
+                                        ChannelId(mod_log_channel),
+                                        &action,
+                                    )
+                                    .await
+                                    {
+                                        error!("Unable to post mod action: {e}");
+                                    }
+                                }
+                            }
+                            CentralMessage::Request(envelope)
+                                if envelope.is_for(Subsystem::Discord) =>
                             {
-                                error!("{e}");
+                                let response = answer_request(&client, &envelope.request).await;
+                                if !envelope.reply(response) {
+                                    debug!("Nobody is waiting for {:?}", envelope.request);
+                                }
                             }
-                        }
-                        CentralMessage::Shutdown => {
-                            info!("Shutdown received");
-                            break;
-                        }
-                        _ => {}
-                    },
-                    Err(e) => match e {
-                        TryRecvError::Closed => {
-                            error!("Channel closed");
+                            CentralMessage::Shutdown => {
+                                info!("Shutdown received");
+                                break 'jobs;
+                            }
+                            _ => {}
+                        },
+                        Err(e) => match e {
+                            TryRecvError::Closed => {
+                                error!("Channel closed");
 
-                            break;
-                        }
-                        TryRecvError::Lagged(n) => {
-                            debug!("Channel lagged by {n} messages");
-                        }
-                        _ => {}
-                    },
+                                break 'jobs;
+                            }
+                            TryRecvError::Lagged(n) => {
+                                debug!("Channel lagged by {n} messages");
+                            }
+                            TryRecvError::Empty => break,
+                        },
+                    }
                 }
             }
         }
@@ -363,11 +1478,73 @@ async fn start_job_thread(bot: &Bot, ctx: &Context) {
     debug!("Started Discord job thread!");
 }
 
+/// Add or remove the configured Discord roles for linked accounts based on their Twitch roles.
+async fn sync_twitch_roles(
+    ctx: &Context,
+    creds: &DiscordCreds,
+    config: &RwLock<Config>,
+    state: &RwLock<State>,
+    roles: &[TwitchUserRoles],
+) {
+    debug!("Syncing Twitch roles");
+
+    // Chat handling needs the state, so it is not held during the HTTP calls
+    let linked: Vec<_> = {
+        let config = config.read().await;
+        let state = state.read().await;
+
+        roles
+            .iter()
+            .filter_map(|user_roles| {
+                let discord_id = state.linked_discord(&user_roles.login)?;
+                let wanted = [
+                    (config.twitch_subscriber_role, user_roles.is_subscriber),
+                    (config.twitch_vip_role, user_roles.is_vip),
+                    (config.twitch_moderator_role, user_roles.is_moderator),
+                ];
+
+                Some((&user_roles.login, discord_id, wanted))
+            })
+            .collect()
+    };
+
+    let guild = GuildId(creds.guild_id);
+
+    for (login, discord_id, wanted) in linked {
+        let member = match guild.member(ctx, discord_id).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to get member data for {login}: {e}");
+                continue;
+            }
+        };
+
+        for (role_id, should_have) in wanted {
+            if role_id == 0 || member.roles.contains(&RoleId(role_id)) == should_have {
+                continue;
+            }
+
+            if let Err(e) =
+                update_member_role(ctx, creds.guild_id, discord_id, role_id, should_have).await
+            {
+                error!(
+                    "Unable to sync role {role_id} for user {} because of {e}",
+                    member.user.name
+                );
+            }
+        }
+    }
+
+    debug!("Finished syncing Twitch roles");
+}
+
 async fn process_old_reaction_roles(
     ctx: &Context,
     creds: &DiscordCreds,
+    state: &RwLock<State>,
     cached_rr: &HashMap<String, u64>,
     roles_channel_id: u64,
+    mod_log_channel: u64,
 ) {
     debug!("Processing old reaction roles");
 
@@ -384,7 +1561,7 @@ async fn process_old_reaction_roles(
                 let users = match message
                     .reaction_users(
                         &ctx.http,
-                        ReactionType::Unicode(emoji.clone()),
+                        parse_emoji(emoji),
                         Some(PAGE_MAX),
                         if starting_user_id == 0 {
                             None
@@ -397,7 +1574,7 @@ async fn process_old_reaction_roles(
                     Ok(v) => v,
                     Err(e) => {
                         error!("Unable to get users that reacted to {emoji}: {e}");
-                        continue;
+                        break;
                     }
                 };
 
@@ -433,7 +1610,7 @@ async fn process_old_reaction_roles(
                                     roles_channel_id,
                                     message.id.into(),
                                     Some(user.id.into()),
-                                    &ReactionType::Unicode(emoji.to_string()),
+                                    &parse_emoji(emoji),
                                 )
                                 .await
                             {
@@ -443,6 +1620,15 @@ async fn process_old_reaction_roles(
                                     "Successfully removed {} from reaction {}",
                                     &user.name, &emoji
                                 );
+
+                                let action = ModAction::new(
+                                    "reaction roles",
+                                    format!("discord:{}", user.id.as_u64()),
+                                    &user.name,
+                                    "remove reaction",
+                                    format!("Reacted with {emoji} but is no longer a member"),
+                                );
+                                log_mod_action(ctx, state, mod_log_channel, action).await;
                             }
 
                             continue;
@@ -1,31 +1,35 @@
-mod antispam;
 mod discord_bot;
+mod notifications;
+mod onboarding;
+mod relay;
+mod role_menus;
+mod slash_commands;
 
-use antispam::Antispam;
+use antispam::{Antispam, SpamMessage};
 use model::{
     config::Config,
-    creds::{BotCreds, DiscordCreds},
+    creds::DiscordCreds,
     messages::{CentralMessage, DiscordMessage},
+    relay::ChatLine,
+    state::State,
 };
-use serenity::{framework::StandardFramework, model::prelude::*, prelude::*};
+use serenity::{model::prelude::*, prelude::*};
 
 use std::sync::Arc;
 use tokio::sync::{
     broadcast::{Receiver, Sender},
+    mpsc::UnboundedReceiver,
     RwLock,
 };
 
 pub async fn run_bot(
     config: Arc<RwLock<Config>>,
     creds: DiscordCreds,
+    state: Arc<RwLock<State>>,
     receiver: Receiver<CentralMessage>,
     sender: Sender<DiscordMessage>,
+    relay_receiver: UnboundedReceiver<ChatLine>,
 ) -> anyhow::Result<()> {
-    let framework = StandardFramework::new()
-        .configure(|c| c.prefix(creds.bot_prefix()).allow_dm(false))
-        // .group(&commands::GENERAL_GROUP)
-        ;
-
     let token = creds.token.to_owned();
     let intents = GatewayIntents::GUILDS
         | GatewayIntents::GUILD_MEMBERS
@@ -34,12 +38,9 @@ pub async fn run_bot(
         | GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::GUILD_MESSAGE_REACTIONS
         | GatewayIntents::MESSAGE_CONTENT;
-    let bot = discord_bot::Bot::new(config, creds, receiver, sender);
+    let bot = discord_bot::Bot::new(config, creds, state, receiver, sender, relay_receiver);
 
-    let mut client = Client::builder(token, intents)
-        .event_handler(bot)
-        .framework(framework)
-        .await?;
+    let mut client = Client::builder(token, intents).event_handler(bot).await?;
 
     client.start().await.map_err(anyhow::Error::from)
 }
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use log::{debug, error};
+use model::{
+    alerts::Alert,
+    config::{AlertsConfig, Config},
+    state::{unix_now, State},
+    stream::{format_duration, StreamInfo, StreamNotification},
+};
+use serenity::{builder::CreateEmbed, model::prelude::*, prelude::*};
+use strfmt::Format;
+
+const LIVE_COLOUR: u32 = 0x9146FF;
+const OFFLINE_COLOUR: u32 = 0x808080;
+
+/// Post a go-live notification using the settings of the stream's channel. A stream that
+/// starts within `min_notification_secs` of the previous one from the same channel reuses
+/// its notification instead of pinging again.
+pub async fn notify_live(
+    ctx: &Context,
+    config: &Config,
+    state: &RwLock<State>,
+    stream: &StreamInfo,
+) {
+    let watched = config.watched_channel(&stream.login);
+    if watched.notification_channel == 0 {
+        return;
+    }
+
+    let previous = state
+        .read()
+        .await
+        .stream_notifications
+        .get(&stream.login)
+        .cloned();
+    let action = live_action(
+        previous.as_ref().map(|p| &p.stream),
+        stream,
+        watched.min_notification_secs,
+    );
+    match (action, previous) {
+        (LiveAction::Skip, _) => {
+            debug!("Stream {} has already been announced", stream.id);
+            return;
+        }
+        (LiveAction::Reuse, Some(previous)) => {
+            debug!("Too early to send a stream notification, reusing the previous one");
+            update_notification(ctx, state, previous, stream).await;
+            return;
+        }
+        _ => {}
+    }
+
+    let mut content = match watched.notification_role {
+        0 => String::new(),
+        role => format!("<@&{role}> "),
+    };
+    if !watched.notification_format.is_empty() {
+        let vars = HashMap::from([
+            ("channel".to_string(), stream.channel.clone()),
+            ("title".to_string(), stream.title.clone()),
+            ("url".to_string(), stream.url.clone()),
+            ("game".to_string(), stream.game.clone()),
+        ]);
+        match watched.notification_format.format(&vars) {
+            Ok(v) => content.push_str(&v),
+            Err(e) => error!("Failed to format message using custom format: {e}"),
+        }
+    }
+
+    // Titles come from other channels, so only the notification role may be pinged
+    let pinged_roles = match watched.notification_role {
+        0 => vec![],
+        role => vec![RoleId(role)],
+    };
+    match ChannelId(watched.notification_channel)
+        .send_message(ctx, |m| {
+            if !content.trim().is_empty() {
+                m.content(content.trim());
+            }
+            m.allowed_mentions(|a| a.empty_parse().roles(pinged_roles))
+                .set_embed(live_embed(stream))
+        })
+        .await
+    {
+        Ok(m) => {
+            state.write().await.stream_notifications.insert(
+                stream.login.clone(),
+                StreamNotification {
+                    channel_id: *m.channel_id.as_u64(),
+                    message_id: *m.id.as_u64(),
+                    stream: stream.clone(),
+                },
+            );
+        }
+        Err(e) => error!("Unable to send stream notification: {e}"),
+    }
+}
+
+/// Show the new title or game of the live stream in its notification.
+pub async fn notify_update(ctx: &Context, state: &RwLock<State>, stream: &StreamInfo) {
+    let notification = state
+        .read()
+        .await
+        .stream_notifications
+        .get(&stream.login)
+        .cloned();
+    match notification {
+        Some(n) if n.stream.id == stream.id => update_notification(ctx, state, n, stream).await,
+        _ => debug!("No notification to update for stream {}", stream.id),
+    }
+}
+
+async fn update_notification(
+    ctx: &Context,
+    state: &RwLock<State>,
+    notification: StreamNotification,
+    stream: &StreamInfo,
+) {
+    if let Err(e) = ChannelId(notification.channel_id)
+        .edit_message(ctx, notification.message_id, |m| {
+            m.set_embed(live_embed(stream))
+        })
+        .await
+    {
+        error!("Unable to update stream notification: {e}");
+    }
+
+    if let Some(n) = state
+        .write()
+        .await
+        .stream_notifications
+        .get_mut(&stream.login)
+    {
+        n.stream = stream.clone();
+    }
+}
+
+/// Edit the notification of the stream that ended to show how long it lasted and
+/// where to watch the VOD.
+pub async fn notify_offline(
+    ctx: &Context,
+    state: &RwLock<State>,
+    stream: &StreamInfo,
+    vod_url: Option<&str>,
+) {
+    let notification = state
+        .read()
+        .await
+        .stream_notifications
+        .get(&stream.login)
+        .cloned();
+    let notification = match notification {
+        Some(v) if v.stream.id == stream.id => v,
+        _ => {
+            debug!("No notification to update for stream {}", stream.id);
+            return;
+        }
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .author(|a| {
+            a.name(format!("{} was live", stream.channel))
+                .url(&stream.url)
+        })
+        .title(&stream.title)
+        .url(vod_url.unwrap_or(&stream.url))
+        .description(offline_description(stream, vod_url, unix_now()))
+        .colour(OFFLINE_COLOUR);
+
+    if let Err(e) = ChannelId(notification.channel_id)
+        .edit_message(ctx, notification.message_id, |m| m.set_embed(embed))
+        .await
+    {
+        error!("Unable to update stream notification: {e}");
+    }
+}
+
+/// Post an alert from Twitch, if its type is posted to Discord.
+pub async fn post_alert(ctx: &Context, config: &AlertsConfig, alert: &Alert) {
+    let settings = config.settings(alert);
+    if config.discord_channel == 0 || !settings.discord {
+        return;
+    }
+
+    let format = match settings.discord_format.as_str() {
+        "" => alert.default_format(),
+        v => v,
+    };
+    let text = match format.format(&alert.vars()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to format alert using custom format: {e}");
+            return;
+        }
+    };
+
+    // Messages come from Twitch users, so they should not be able to ping anyone
+    if let Err(e) = ChannelId(config.discord_channel)
+        .send_message(ctx, |m| {
+            m.content(text).allowed_mentions(|a| a.empty_parse())
+        })
+        .await
+    {
+        error!("Unable to post alert: {e}");
+    }
+}
+
+fn live_embed(stream: &StreamInfo) -> CreateEmbed {
+    let mut embed = CreateEmbed::default();
+    embed
+        .author(|a| {
+            a.name(format!("{} is live!", stream.channel))
+                .url(&stream.url)
+        })
+        .title(&stream.title)
+        .url(&stream.url)
+        .field(
+            "Game",
+            if stream.game.is_empty() {
+                "Unknown"
+            } else {
+                &stream.game
+            },
+            true,
+        )
+        .field("Viewers", stream.viewer_count, true)
+        .image(&stream.thumbnail_url)
+        .colour(LIVE_COLOUR);
+
+    if let Ok(t) = Timestamp::parse(&stream.started_at) {
+        embed.timestamp(t);
+    }
+    if !stream.tags.is_empty() {
+        embed.footer(|f| f.text(stream.tags.join(" · ")));
+    }
+
+    embed
+}
+
+/// What a go-live notification does given the `previous` stream that was announced.
+#[derive(Debug, PartialEq, Eq)]
+enum LiveAction {
+    /// The stream has already been announced.
+    Skip,
+    /// Edit the previous notification instead of pinging again.
+    Reuse,
+    Post,
+}
+
+/// Decide how to announce the `stream`. Streams that start within `min_notification_secs`
+/// of the previous one reuse its notification.
+fn live_action(
+    previous: Option<&StreamInfo>,
+    stream: &StreamInfo,
+    min_notification_secs: u64,
+) -> LiveAction {
+    let previous = match previous {
+        Some(v) if v.id == stream.id => return LiveAction::Skip,
+        Some(v) => v,
+        None => return LiveAction::Post,
+    };
+
+    let since_previous = started_at_unix(stream)
+        .zip(started_at_unix(previous))
+        .map_or(i64::MAX, |(new, old)| new - old);
+    if since_previous < min_notification_secs as i64 {
+        LiveAction::Reuse
+    } else {
+        LiveAction::Post
+    }
+}
+
+/// Describe a stream that ended at `now`, linking the VOD if there is one.
+fn offline_description(stream: &StreamInfo, vod_url: Option<&str>, now: u64) -> String {
+    let mut description = match started_at_unix(stream) {
+        Some(started_at) => format!(
+            "Streamed {} for {}.",
+            stream.game,
+            format_duration((now as i64 - started_at).max(0) as u64)
+        ),
+        None => format!("Streamed {}.", stream.game),
+    };
+    if let Some(url) = vod_url {
+        description.push_str(&format!("\n[Watch the VOD]({url})"));
+    }
+
+    description
+}
+
+fn started_at_unix(stream: &StreamInfo) -> Option<i64> {
+    Timestamp::parse(&stream.started_at)
+        .ok()
+        .map(|t| t.unix_timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(id: &str, started_at: &str) -> StreamInfo {
+        StreamInfo {
+            id: id.into(),
+            channel: "Friend".into(),
+            title: "Speedruns".into(),
+            url: "https://twitch.tv/friend".into(),
+            game: "Celeste".into(),
+            viewer_count: 12,
+            started_at: started_at.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reuses_recent_notifications() {
+        let previous = stream("1", "2023-01-01T10:00:00Z");
+
+        assert_eq!(live_action(None, &previous, 600), LiveAction::Post);
+        assert_eq!(
+            live_action(Some(&previous), &previous, 600),
+            LiveAction::Skip
+        );
+        let restarted = stream("2", "2023-01-01T10:05:00Z");
+        assert_eq!(
+            live_action(Some(&previous), &restarted, 600),
+            LiveAction::Reuse
+        );
+        let later = stream("3", "2023-01-01T10:10:00Z");
+        assert_eq!(live_action(Some(&previous), &later, 600), LiveAction::Post);
+        // Without start times there is nothing to compare
+        assert_eq!(
+            live_action(Some(&previous), &stream("4", ""), 600),
+            LiveAction::Post
+        );
+    }
+
+    #[test]
+    fn describes_ended_streams() {
+        let ended = stream("1", "2023-01-01T10:00:00Z");
+        let ended_at = started_at_unix(&ended).unwrap() as u64 + 2 * 3600 + 5 * 60;
+
+        assert_eq!(
+            offline_description(&ended, None, ended_at),
+            format!(
+                "Streamed Celeste for {}.",
+                format_duration(2 * 3600 + 5 * 60)
+            )
+        );
+        assert_eq!(
+            offline_description(&ended, Some("https://twitch.tv/videos/1"), ended_at),
+            format!(
+                "Streamed Celeste for {}.\n[Watch the VOD](https://twitch.tv/videos/1)",
+                format_duration(2 * 3600 + 5 * 60)
+            )
+        );
+
+        let unknown_start = stream("1", "");
+        assert_eq!(
+            offline_description(&unknown_start, None, ended_at),
+            "Streamed Celeste."
+        );
+    }
+
+    #[test]
+    fn shows_stream_details_in_live_embeds() {
+        let mut stream = stream("1", "2023-01-01T10:00:00Z");
+        stream.tags = vec!["English".into(), "Speedrun".into()];
+        let embed = live_embed(&stream).0;
+
+        assert_eq!(embed["title"], "Speedruns");
+        assert_eq!(embed["author"]["name"], "Friend is live!");
+        assert_eq!(embed["fields"][0]["value"], "Celeste");
+        assert_eq!(embed["fields"][1]["value"], "12");
+        assert_eq!(embed["footer"]["text"], "English · Speedrun");
+
+        stream.game = String::new();
+        stream.tags = vec![];
+        let embed = live_embed(&stream).0;
+        assert_eq!(embed["fields"][0]["value"], "Unknown");
+        assert!(!embed.contains_key("footer"));
+    }
+}
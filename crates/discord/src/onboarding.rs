@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use log::{debug, error};
+use model::{config::OnboardingConfig, state::State};
+use serenity::{
+    builder::{CreateComponents, CreateEmbed},
+    model::{
+        interactions::{
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionResponseType,
+        },
+        prelude::*,
+    },
+    prelude::*,
+};
+use strfmt::Format;
+
+use crate::discord_bot::{is_same_emoji, parse_emoji, update_member_role};
+
+const RULES_BUTTON_ID: &str = "rules_accept";
+
+/// Greet a new member and give them the starter roles.
+pub async fn welcome(ctx: &Context, config: &OnboardingConfig, member: &Member) {
+    let vars = member_vars(ctx, member.guild_id, &member.user);
+
+    if config.welcome_channel != 0 {
+        if let Some(text) = fill(&config.welcome_format, &vars) {
+            if let Err(e) = ChannelId(config.welcome_channel).say(ctx, text).await {
+                error!("Unable to welcome {}: {e}", member.user.name);
+            }
+        }
+    }
+
+    if let Some(text) = fill(&config.welcome_dm_format, &vars) {
+        // Members can turn off DMs, which is not worth more than a debug message
+        if let Err(e) = member.user.direct_message(ctx, |m| m.content(text)).await {
+            debug!("Unable to DM {}: {e}", member.user.name);
+        }
+    }
+
+    for role in config.starter_roles.iter() {
+        if let Err(e) = update_member_role(
+            ctx,
+            *member.guild_id.as_u64(),
+            *member.user.id.as_u64(),
+            *role,
+            true,
+        )
+        .await
+        {
+            error!(
+                "Unable to give starter role {role} to {}: {e}",
+                member.user.name
+            );
+        }
+    }
+}
+
+/// Announce that a member left.
+pub async fn goodbye(ctx: &Context, config: &OnboardingConfig, guild_id: GuildId, user: &User) {
+    if config.goodbye_channel == 0 {
+        return;
+    }
+
+    let vars = member_vars(ctx, guild_id, user);
+    if let Some(text) = fill(&config.goodbye_format, &vars) {
+        if let Err(e) = ChannelId(config.goodbye_channel).say(ctx, text).await {
+            error!("Unable to say goodbye to {}: {e}", user.name);
+        }
+    }
+}
+
+fn member_vars(ctx: &Context, guild_id: GuildId, user: &User) -> HashMap<String, String> {
+    let member_count = ctx.cache.guild(guild_id).map_or(0, |g| g.member_count);
+
+    format_vars(&user.mention().to_string(), &user.name, member_count)
+}
+
+/// The variables available in the welcome and goodbye formats.
+fn format_vars(mention: &str, name: &str, member_count: u64) -> HashMap<String, String> {
+    HashMap::from([
+        ("user".to_string(), mention.to_string()),
+        ("name".to_string(), name.to_string()),
+        ("member_count".to_string(), member_count.to_string()),
+    ])
+}
+
+/// Fill in the `format`. Returns None if there is nothing to send.
+fn fill(format: &String, vars: &HashMap<String, String>) -> Option<String> {
+    if format.is_empty() {
+        return None;
+    }
+
+    match format.format(vars) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            error!("Failed to format message using custom format: {e}");
+            None
+        }
+    }
+}
+
+/// Post the rules, or edit them if they have already been posted. The old message is
+/// deleted if the rules channel changed or the rules gate was turned off.
+pub async fn sync_rules(ctx: &Context, config: &OnboardingConfig, state: &RwLock<State>) {
+    let existing = state.read().await.rules_message;
+
+    let existing = match rules_sync(existing, config.rules_channel) {
+        RulesSync::Nothing => return,
+        RulesSync::Post => None,
+        RulesSync::Edit(message) => Some(MessageId(message)),
+        RulesSync::Move {
+            channel,
+            message,
+            repost,
+        } => {
+            if let Err(e) = ChannelId(channel).delete_message(ctx, message).await {
+                error!("Unable to delete old rules: {e}");
+            }
+            state.write().await.rules_message = None;
+            if !repost {
+                return;
+            }
+            None
+        }
+    };
+
+    let mut embed = CreateEmbed::default();
+    embed.title("Rules").description(&config.rules_text);
+    let mut components = CreateComponents::default();
+    components.create_action_row(|r| {
+        r.create_button(|b| {
+            b.custom_id(RULES_BUTTON_ID)
+                .label("Accept the rules")
+                .style(ButtonStyle::Success)
+        })
+    });
+
+    let channel = ChannelId(config.rules_channel);
+    let edited = match existing {
+        Some(id) => channel
+            .edit_message(ctx, id, |m| {
+                m.set_embed(embed.clone())
+                    .set_components(components.clone())
+            })
+            .await
+            .ok(),
+        None => None,
+    };
+    let message = match edited {
+        Some(v) => v,
+        None => match channel
+            .send_message(ctx, |m| {
+                m.set_embed(embed.clone())
+                    .set_components(components.clone())
+            })
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Unable to post rules: {e}");
+                return;
+            }
+        },
+    };
+
+    if !config.rules_emoji.is_empty() {
+        if let Err(e) = message.react(ctx, parse_emoji(&config.rules_emoji)).await {
+            error!("Unable to react to rules: {e}");
+        }
+    }
+
+    state.write().await.rules_message = Some((*message.channel_id.as_u64(), *message.id.as_u64()));
+}
+
+/// What syncing does with the rules message.
+#[derive(Debug, PartialEq, Eq)]
+enum RulesSync {
+    /// Nothing is posted and nothing should be.
+    Nothing,
+    /// Post the rules as a new message.
+    Post,
+    /// Edit the posted message in place.
+    Edit(u64),
+    /// Delete the message posted in the old channel, then post the rules if `repost`.
+    Move {
+        channel: u64,
+        message: u64,
+        repost: bool,
+    },
+}
+
+/// Decide what to do with the `existing` rules message, as channel and message ID, when
+/// the rules should be in `rules_channel`.
+fn rules_sync(existing: Option<(u64, u64)>, rules_channel: u64) -> RulesSync {
+    match existing {
+        Some((channel, message)) if channel == rules_channel => RulesSync::Edit(message),
+        Some((channel, message)) => RulesSync::Move {
+            channel,
+            message,
+            repost: rules_channel != 0,
+        },
+        None if rules_channel == 0 => RulesSync::Nothing,
+        None => RulesSync::Post,
+    }
+}
+
+/// Grant the rules role for a reaction on the rules. Returns false if the reaction was
+/// not on the rules.
+pub async fn handle_rules_reaction(
+    ctx: &Context,
+    config: &OnboardingConfig,
+    state: &RwLock<State>,
+    reaction: &Reaction,
+) -> bool {
+    let is_rules = state
+        .read()
+        .await
+        .rules_message
+        .is_some_and(|(_, id)| id == *reaction.message_id.as_u64());
+    if !is_rules {
+        return false;
+    }
+
+    if config.rules_emoji.is_empty()
+        || !is_same_emoji(&parse_emoji(&config.rules_emoji), &reaction.emoji)
+    {
+        return true;
+    }
+    if let (Some(user_id), Some(guild_id)) = (reaction.user_id, reaction.guild_id) {
+        accept_rules(ctx, config, guild_id, user_id).await;
+    }
+
+    true
+}
+
+/// Grant the rules role for the accept button. Returns false if the interaction was not
+/// for the rules.
+pub async fn handle_rules_interaction(
+    ctx: &Context,
+    config: &OnboardingConfig,
+    component: &MessageComponentInteraction,
+) -> bool {
+    if component.data.custom_id != RULES_BUTTON_ID {
+        return false;
+    }
+
+    let text = match component.guild_id {
+        Some(guild_id) if accept_rules(ctx, config, guild_id, component.user.id).await => {
+            "Thanks for accepting the rules, welcome!"
+        }
+        _ => "Unable to give you access, please contact a moderator.",
+    };
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.content(text).ephemeral(true))
+        })
+        .await
+    {
+        error!("{e}");
+    }
+
+    true
+}
+
+/// Returns false if the role could not be granted.
+async fn accept_rules(
+    ctx: &Context,
+    config: &OnboardingConfig,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> bool {
+    if config.rules_role == 0 {
+        return false;
+    }
+
+    match update_member_role(
+        ctx,
+        *guild_id.as_u64(),
+        *user_id.as_u64(),
+        config.rules_role,
+        true,
+    )
+    .await
+    {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Unable to give rules role to {user_id}: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_member_formats() {
+        let vars = format_vars("<@1>", "Someone", 42);
+
+        assert_eq!(
+            fill(&"Welcome {user}, member #{member_count}!".into(), &vars),
+            Some("Welcome <@1>, member #42!".into())
+        );
+        assert_eq!(
+            fill(&"Bye {name}".into(), &vars),
+            Some("Bye Someone".into())
+        );
+        // Nothing is sent for empty or broken formats
+        assert_eq!(fill(&String::new(), &vars), None);
+        assert_eq!(fill(&"Hi {nobody}".into(), &vars), None);
+    }
+
+    #[test]
+    fn decides_what_to_do_with_the_rules() {
+        assert_eq!(rules_sync(None, 0), RulesSync::Nothing);
+        assert_eq!(rules_sync(None, 5), RulesSync::Post);
+        assert_eq!(rules_sync(Some((5, 9)), 5), RulesSync::Edit(9));
+        assert_eq!(
+            rules_sync(Some((5, 9)), 6),
+            RulesSync::Move {
+                channel: 5,
+                message: 9,
+                repost: true
+            }
+        );
+        assert_eq!(
+            rules_sync(Some((5, 9)), 0),
+            RulesSync::Move {
+                channel: 5,
+                message: 9,
+                repost: false
+            }
+        );
+    }
+}
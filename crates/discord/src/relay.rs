@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::Arc};
+
+use log::{debug, error};
+use model::{
+    config::Config,
+    messages::{send_request, DiscordMessage, Request, Response},
+    relay::{self, ChatLine},
+};
+use serenity::{model::prelude::*, prelude::*};
+use tokio::sync::{broadcast::Sender, mpsc::UnboundedReceiver};
+
+const WEBHOOK_NAME: &str = "Twitch chat";
+/// The avatar cache is cleared once it holds this many chatters.
+const MAX_AVATARS: usize = 1000;
+
+/// Post relayed Twitch chat in the order it was sent. Messages go through a webhook so
+/// they show the name and avatar of the chatter.
+pub async fn run(
+    ctx: Context,
+    config: Arc<RwLock<Config>>,
+    sender: Sender<DiscordMessage>,
+    mut receiver: UnboundedReceiver<ChatLine>,
+) {
+    let mut webhook: Option<Webhook> = None;
+    let mut avatars = HashMap::new();
+
+    while let Some(line) = receiver.recv().await {
+        let channel = ChannelId(config.read().await.relay.discord_channel);
+        if channel.0 == 0 {
+            continue;
+        }
+
+        let hook = match webhook.take().filter(|w| w.channel_id == Some(channel)) {
+            Some(v) => v,
+            None => match find_webhook(&ctx, channel).await {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Unable to get the relay webhook: {e}");
+                    continue;
+                }
+            },
+        };
+        let avatar_url = avatar(&sender, &mut avatars, &line.login).await;
+
+        // Chatters should not be able to ping anyone
+        let result = hook
+            .execute(&ctx.http, false, |w| {
+                w.username(relay::discord_username(&line.user))
+                    .content(relay::for_discord(&line.text))
+                    .allowed_mentions(|a| a.empty_parse());
+                if !avatar_url.is_empty() {
+                    w.avatar_url(&avatar_url);
+                }
+                w
+            })
+            .await;
+        match result {
+            Ok(_) => webhook = Some(hook),
+            // The webhook may have been deleted, so it is looked up again next time
+            Err(e) => error!("Unable to relay a message from {}: {e}", line.user),
+        }
+    }
+}
+
+/// The relay webhook the bot made in the channel, created if there is none yet.
+async fn find_webhook(ctx: &Context, channel: ChannelId) -> anyhow::Result<Webhook> {
+    let bot_id = ctx.cache.current_user_id();
+    let existing = channel.webhooks(&ctx.http).await?.into_iter().find(|w| {
+        w.token.is_some()
+            && w.name.as_deref() == Some(WEBHOOK_NAME)
+            && w.user.as_ref().map(|u| u.id) == Some(bot_id)
+    });
+
+    match existing {
+        Some(v) => Ok(v),
+        None => Ok(channel.create_webhook(&ctx.http, WEBHOOK_NAME).await?),
+    }
+}
+
+/// The profile image of a Twitch user, asked from the Twitch bot. Failed lookups are
+/// cached too, so they do not hold up every message.
+async fn avatar(
+    sender: &Sender<DiscordMessage>,
+    avatars: &mut HashMap<String, String>,
+    login: &str,
+) -> String {
+    if let Some(v) = avatars.get(login) {
+        return v.clone();
+    }
+    if avatars.len() >= MAX_AVATARS {
+        avatars.clear();
+    }
+
+    let request = Request::ProfileImage(login.to_string());
+    let url = match send_request(sender, DiscordMessage::Request, request).await {
+        Ok(Response::Text(v)) => v,
+        Ok(_) => String::new(),
+        Err(e) => {
+            debug!("No avatar for {login}: {e}");
+            String::new()
+        }
+    };
+    avatars.insert(login.to_string(), url.clone());
+
+    url
+}
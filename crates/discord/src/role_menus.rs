@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+
+use log::{debug, error};
+use model::{
+    config::{Config, RoleMenu, RoleMenuMode, RoleMenuStyle},
+    state::State,
+};
+use serenity::{
+    builder::{CreateComponents, CreateEmbed},
+    model::{
+        interactions::{
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionResponseType,
+        },
+        prelude::*,
+    },
+    prelude::*,
+};
+
+use crate::discord_bot::{is_same_emoji, parse_emoji, update_member_role};
+
+const BUTTON_PREFIX: &str = "role_menu:";
+const SELECT_PREFIX: &str = "role_menu_select:";
+/// Discord allows 5 rows of 5 buttons and 25 options per select menu.
+const MAX_OPTIONS: usize = 25;
+const BUTTONS_PER_ROW: usize = 5;
+
+/// The name and ID of a role in a menu.
+type MenuRole<'a> = (&'a str, u64);
+
+/// Post every configured role menu, or edit its message if it has already been posted.
+/// Messages for menus that are no longer configured are deleted.
+pub async fn sync_role_menus(ctx: &Context, config: &Config, state: &RwLock<State>) {
+    debug!("Syncing role menus");
+
+    let mut messages = state.read().await.role_menu_messages.clone();
+
+    let removed = messages
+        .keys()
+        .filter(|name| !config.role_menus.iter().any(|m| &&m.name == name))
+        .cloned()
+        .collect::<Vec<String>>();
+    for name in removed {
+        if let Some((channel, message)) = messages.remove(&name) {
+            if let Err(e) = ChannelId(channel).delete_message(ctx, message).await {
+                error!("Unable to delete role menu {name}: {e}");
+            }
+        }
+    }
+
+    for menu in config.role_menus.iter() {
+        let channel = match (menu.channel, config.roles_channel) {
+            (0, 0) => {
+                error!("No channel to post role menu {} in", menu.name);
+                continue;
+            }
+            (0, v) | (v, _) => ChannelId(v),
+        };
+
+        let existing = match messages.get(&menu.name) {
+            Some((c, m)) if *c == *channel.as_u64() => Some(MessageId(*m)),
+            Some((c, m)) => {
+                // The menu moved to another channel
+                if let Err(e) = ChannelId(*c).delete_message(ctx, *m).await {
+                    error!("Unable to delete old role menu {}: {e}", menu.name);
+                }
+                None
+            }
+            None => None,
+        };
+
+        match post_role_menu(ctx, channel, existing, menu).await {
+            Ok(m) => {
+                messages.insert(menu.name.clone(), (*channel.as_u64(), *m.id.as_u64()));
+            }
+            Err(e) => error!("Unable to post role menu {}: {e}", menu.name),
+        }
+    }
+
+    state.write().await.role_menu_messages = messages;
+
+    debug!("Finished syncing role menus");
+}
+
+/// Edit the `existing` message to show the menu, posting a new message if there is
+/// none or it was deleted.
+async fn post_role_menu(
+    ctx: &Context,
+    channel: ChannelId,
+    existing: Option<MessageId>,
+    menu: &RoleMenu,
+) -> anyhow::Result<Message> {
+    let embed = menu_embed(menu);
+    let components = menu_components(menu);
+
+    let edited = match existing {
+        Some(id) => channel
+            .edit_message(ctx, id, |m| {
+                m.set_embed(embed.clone())
+                    .set_components(components.clone())
+            })
+            .await
+            .ok(),
+        None => None,
+    };
+    let message = match edited {
+        Some(v) => v,
+        None => {
+            channel
+                .send_message(ctx, |m| {
+                    m.set_embed(embed.clone())
+                        .set_components(components.clone())
+                })
+                .await?
+        }
+    };
+
+    if menu.style == RoleMenuStyle::Reactions {
+        for option in menu.options.iter().take(MAX_OPTIONS) {
+            message.react(ctx, parse_emoji(&option.emoji)).await?;
+        }
+    }
+
+    Ok(message)
+}
+
+fn menu_embed(menu: &RoleMenu) -> CreateEmbed {
+    let options = menu
+        .options
+        .iter()
+        .take(MAX_OPTIONS)
+        .map(|o| match o.description.is_empty() {
+            true => format!("{} {}", o.emoji, o.role),
+            false => format!("{} {} - {}", o.emoji, o.role, o.description),
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let mut embed = CreateEmbed::default();
+    embed
+        .title(&menu.title)
+        .description(format!("{}\n\n{options}", menu.description).trim());
+
+    embed
+}
+
+fn menu_components(menu: &RoleMenu) -> CreateComponents {
+    let options = menu.options.iter().take(MAX_OPTIONS).enumerate();
+
+    let mut components = CreateComponents::default();
+    match menu.style {
+        RoleMenuStyle::Reactions => {}
+        RoleMenuStyle::Buttons => {
+            let options = options.collect::<Vec<_>>();
+            for row in options.chunks(BUTTONS_PER_ROW) {
+                components.create_action_row(|r| {
+                    for (i, option) in row {
+                        r.create_button(|b| {
+                            b.custom_id(format!("{BUTTON_PREFIX}{}:{i}", menu.name))
+                                .label(&option.role)
+                                .emoji(parse_emoji(&option.emoji))
+                                .style(ButtonStyle::Secondary)
+                        });
+                    }
+                    r
+                });
+            }
+        }
+        RoleMenuStyle::Select => {
+            let max_values = match menu.mode {
+                RoleMenuMode::Unique => 1,
+                _ => menu.options.len().min(MAX_OPTIONS) as u64,
+            };
+            components.create_action_row(|r| {
+                r.create_select_menu(|s| {
+                    s.custom_id(format!("{SELECT_PREFIX}{}", menu.name))
+                        .placeholder(&menu.title)
+                        .min_values(0)
+                        .max_values(max_values)
+                        .options(|o| {
+                            for (i, option) in options {
+                                o.create_option(|opt| {
+                                    opt.label(&option.role)
+                                        .value(i)
+                                        .emoji(parse_emoji(&option.emoji));
+                                    if !option.description.is_empty() {
+                                        opt.description(&option.description);
+                                    }
+                                    opt
+                                });
+                            }
+                            o
+                        })
+                })
+            });
+        }
+    }
+
+    components
+}
+
+/// Handle a reaction on a role menu message. Returns false if the reaction was not on
+/// a role menu.
+pub async fn handle_menu_reaction(
+    ctx: &Context,
+    config: &Config,
+    state: &RwLock<State>,
+    role_ids: &HashMap<String, u64>,
+    reaction: &Reaction,
+    is_add: bool,
+) -> bool {
+    let menu = {
+        let state = state.read().await;
+        config.role_menus.iter().find(|m| {
+            state
+                .role_menu_messages
+                .get(&m.name)
+                .is_some_and(|(_, id)| id == reaction.message_id.as_u64())
+        })
+    };
+    let menu = match menu {
+        Some(v) if v.style == RoleMenuStyle::Reactions => v,
+        Some(_) => return true,
+        None => return false,
+    };
+
+    let (user_id, guild_id) = match (reaction.user_id, reaction.guild_id) {
+        (Some(u), Some(g)) => (u, g),
+        _ => return true,
+    };
+    let index = match menu
+        .options
+        .iter()
+        .position(|o| is_same_emoji(&parse_emoji(&o.emoji), &reaction.emoji))
+    {
+        Some(v) => v,
+        None => return true,
+    };
+    let member = match guild_id.member(ctx, user_id).await {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to get member {user_id} for role menu: {e}");
+            return true;
+        }
+    };
+
+    if is_add {
+        choose(ctx, &member, menu, role_ids, &[index], &[]).await;
+
+        match menu.mode {
+            // Keep the menu clean, only the roles matter
+            RoleMenuMode::Verify => {
+                if let Err(e) = reaction.delete(ctx).await {
+                    error!("{e}");
+                }
+            }
+            // Only the new pick should stay, the user had reacted for the roles they have
+            RoleMenuMode::Unique => {
+                let others = menu.options.iter().enumerate().filter(|(i, o)| {
+                    *i != index
+                        && role_ids
+                            .get(&o.role)
+                            .is_some_and(|id| member.roles.contains(&RoleId(*id)))
+                });
+                for (_, option) in others {
+                    if let Err(e) = reaction
+                        .channel_id
+                        .delete_reaction(
+                            ctx,
+                            reaction.message_id,
+                            Some(user_id),
+                            parse_emoji(&option.emoji),
+                        )
+                        .await
+                    {
+                        error!("Unable to remove the {} reaction: {e}", option.role);
+                    }
+                }
+            }
+            RoleMenuMode::Normal | RoleMenuMode::AddOnly => {}
+        }
+    } else {
+        choose(ctx, &member, menu, role_ids, &[], &[index]).await;
+    }
+
+    true
+}
+
+/// Handle a button or select menu on a role menu message, replying with what changed.
+pub async fn handle_menu_interaction(
+    ctx: &Context,
+    config: &Config,
+    role_ids: &HashMap<String, u64>,
+    component: &MessageComponentInteraction,
+) {
+    let custom_id = component.data.custom_id.as_str();
+    let (name, index) = if let Some(v) = custom_id.strip_prefix(SELECT_PREFIX) {
+        (v, None)
+    } else if let Some((name, index)) = custom_id
+        .strip_prefix(BUTTON_PREFIX)
+        .and_then(|v| v.rsplit_once(':'))
+    {
+        (name, index.parse::<usize>().ok())
+    } else {
+        return;
+    };
+
+    let (menu, member) = match (
+        config.role_menus.iter().find(|m| m.name == name),
+        &component.member,
+    ) {
+        (Some(m), Some(member)) => (m, member),
+        _ => {
+            reply_ephemeral(ctx, component, "This menu is no longer available.").await;
+            return;
+        }
+    };
+
+    let (selected, deselected) = match index {
+        // Buttons toggle a single role
+        Some(i) => {
+            let has_role = menu
+                .options
+                .get(i)
+                .and_then(|o| role_ids.get(&o.role))
+                .is_some_and(|id| member.roles.contains(&RoleId(*id)));
+            if has_role {
+                (vec![], vec![i])
+            } else {
+                (vec![i], vec![])
+            }
+        }
+        // Select menus replace the picked roles
+        None => {
+            let selected = component
+                .data
+                .values
+                .iter()
+                .filter_map(|v| v.parse::<usize>().ok())
+                .collect::<Vec<usize>>();
+            let deselected = (0..menu.options.len())
+                .filter(|i| !selected.contains(i))
+                .collect::<Vec<usize>>();
+            (selected, deselected)
+        }
+    };
+
+    let text = choose(ctx, member, menu, role_ids, &selected, &deselected).await;
+    reply_ephemeral(ctx, component, &text).await;
+}
+
+/// Add the roles for the `selected` options and remove the roles for the `deselected`
+/// options, following the mode of the menu. Returns a summary of what changed.
+async fn choose(
+    ctx: &Context,
+    member: &Member,
+    menu: &RoleMenu,
+    role_ids: &HashMap<String, u64>,
+    selected: &[usize],
+    deselected: &[usize],
+) -> String {
+    let (to_add, to_remove) = role_changes(menu, role_ids, &member.roles, selected, deselected);
+
+    let mut added = vec![];
+    let mut removed = vec![];
+    for (name, id) in to_add {
+        match update_member_role(
+            ctx,
+            *member.guild_id.as_u64(),
+            *member.user.id.as_u64(),
+            id,
+            true,
+        )
+        .await
+        {
+            Ok(_) => added.push(name),
+            Err(e) => error!("Unable to add role {name} to {}: {e}", member.user.name),
+        }
+    }
+    for (name, id) in to_remove {
+        match update_member_role(
+            ctx,
+            *member.guild_id.as_u64(),
+            *member.user.id.as_u64(),
+            id,
+            false,
+        )
+        .await
+        {
+            Ok(_) => removed.push(name),
+            Err(e) => error!(
+                "Unable to remove role {name} from {}: {e}",
+                member.user.name
+            ),
+        }
+    }
+
+    match (added.is_empty(), removed.is_empty()) {
+        (true, true) => "Nothing changed.".to_string(),
+        (false, true) => format!("Added {}.", added.join(", ")),
+        (true, false) => format!("Removed {}.", removed.join(", ")),
+        (false, false) => format!(
+            "Added {}. Removed {}.",
+            added.join(", "),
+            removed.join(", ")
+        ),
+    }
+}
+
+/// The roles, as name and ID, to add and to remove when a member with `roles` picks the
+/// `selected` options and unpicks the `deselected` ones. Roles the member already has or
+/// lacks are left out.
+fn role_changes<'a>(
+    menu: &'a RoleMenu,
+    role_ids: &HashMap<String, u64>,
+    roles: &[RoleId],
+    selected: &[usize],
+    deselected: &[usize],
+) -> (Vec<MenuRole<'a>>, Vec<MenuRole<'a>>) {
+    let role_for = |i: &usize| {
+        menu.options
+            .get(*i)
+            .and_then(|o| Some((o.role.as_str(), *role_ids.get(&o.role)?)))
+    };
+
+    let mut selected = selected.iter().filter_map(role_for).collect::<Vec<_>>();
+    let mut deselected = match menu.mode {
+        RoleMenuMode::Normal | RoleMenuMode::Unique => {
+            deselected.iter().filter_map(role_for).collect::<Vec<_>>()
+        }
+        RoleMenuMode::Verify | RoleMenuMode::AddOnly => vec![],
+    };
+    // Picking a role replaces the others, removing one only removes that role
+    if menu.mode == RoleMenuMode::Unique && !selected.is_empty() {
+        selected.truncate(1);
+        deselected = (0..menu.options.len())
+            .filter_map(|i| role_for(&i))
+            .filter(|r| !selected.contains(r))
+            .collect();
+    }
+
+    selected.retain(|(_, id)| !roles.contains(&RoleId(*id)));
+    deselected.retain(|(_, id)| roles.contains(&RoleId(*id)));
+
+    (selected, deselected)
+}
+
+async fn reply_ephemeral(ctx: &Context, component: &MessageComponentInteraction, text: &str) {
+    if let Err(e) = component
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.content(text).ephemeral(true))
+        })
+        .await
+    {
+        error!("{e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::config::RoleMenuOption;
+
+    fn menu(mode: RoleMenuMode) -> RoleMenu {
+        RoleMenu {
+            name: "colors".into(),
+            title: "Colors".into(),
+            description: String::new(),
+            mode,
+            style: RoleMenuStyle::Reactions,
+            channel: 0,
+            options: ["Red", "Green", "Blue"]
+                .into_iter()
+                .map(|role| RoleMenuOption {
+                    role: role.into(),
+                    emoji: String::new(),
+                    description: String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    fn role_ids() -> HashMap<String, u64> {
+        HashMap::from([("Red".into(), 1), ("Green".into(), 2), ("Blue".into(), 3)])
+    }
+
+    fn changes(
+        mode: RoleMenuMode,
+        roles: &[u64],
+        selected: &[usize],
+        deselected: &[usize],
+    ) -> (Vec<u64>, Vec<u64>) {
+        let menu = menu(mode);
+        let roles = roles.iter().map(|r| RoleId(*r)).collect::<Vec<RoleId>>();
+        let (added, removed) = role_changes(&menu, &role_ids(), &roles, selected, deselected);
+
+        (
+            added.into_iter().map(|(_, id)| id).collect(),
+            removed.into_iter().map(|(_, id)| id).collect(),
+        )
+    }
+
+    #[test]
+    fn normal_menus_add_and_remove_freely() {
+        assert_eq!(
+            changes(RoleMenuMode::Normal, &[1], &[1], &[]),
+            (vec![2], vec![])
+        );
+        assert_eq!(
+            changes(RoleMenuMode::Normal, &[1, 2], &[], &[0]),
+            (vec![], vec![1])
+        );
+        // Select menus pick every option at once
+        assert_eq!(
+            changes(RoleMenuMode::Normal, &[1], &[1, 2], &[0]),
+            (vec![2, 3], vec![1])
+        );
+        // Roles that are already right are left alone
+        assert_eq!(
+            changes(RoleMenuMode::Normal, &[1], &[0], &[2]),
+            (vec![], vec![])
+        );
+    }
+
+    #[test]
+    fn unique_menus_replace_the_other_roles() {
+        assert_eq!(
+            changes(RoleMenuMode::Unique, &[1, 3], &[1], &[]),
+            (vec![2], vec![1, 3])
+        );
+        assert_eq!(
+            changes(RoleMenuMode::Unique, &[], &[2, 0], &[]),
+            (vec![3], vec![])
+        );
+        // Unreacting only removes the unreacted role
+        assert_eq!(
+            changes(RoleMenuMode::Unique, &[1, 2], &[], &[1]),
+            (vec![], vec![2])
+        );
+    }
+
+    #[test]
+    fn verify_and_add_only_menus_never_remove() {
+        for mode in [RoleMenuMode::Verify, RoleMenuMode::AddOnly] {
+            assert_eq!(changes(mode, &[1], &[1], &[0]), (vec![2], vec![]));
+            assert_eq!(changes(mode, &[1, 2], &[], &[0, 1]), (vec![], vec![]));
+        }
+    }
+
+    #[test]
+    fn ignores_unknown_options_and_roles() {
+        let mut role_ids = role_ids();
+        role_ids.remove("Blue");
+        let menu = menu(RoleMenuMode::Normal);
+
+        let (added, removed) = role_changes(&menu, &role_ids, &[], &[2, 7], &[]);
+        assert!(added.is_empty());
+        assert!(removed.is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use commands::{CommandSpec, OptionSpec};
+use log::{debug, error};
+use model::config::Config;
+use serenity::{
+    builder::{CreateApplicationCommand, CreateApplicationCommandOption},
+    model::{
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+                ApplicationCommandOptionType as CommandOptionType,
+            },
+            autocomplete::AutocompleteInteraction,
+            message_component::{ActionRowComponent, InputTextStyle},
+            modal::ModalSubmitInteraction,
+            InteractionResponseType,
+        },
+        prelude::*,
+    },
+    prelude::*,
+};
+
+/// Scripts are submitted through a modal since they don't fit in a single line.
+pub const RHAI_COMMAND: &str = "rhai";
+const RHAI_MODAL_ID: &str = "rhai_modal";
+const RHAI_SCRIPT_ID: &str = "script";
+/// Discord does not allow longer descriptions.
+const MAX_DESCRIPTION_LEN: usize = 100;
+/// Commands only moderators can run, hidden from other members.
+const MODERATOR_COMMANDS: [&str; 6] = ["admin", "addpoints", "setpoints", "so", "raid", "modlog"];
+
+/// Register every command as a guild slash command, replacing any old ones.
+pub async fn register_commands(ctx: &Context, guild_id: u64) -> anyhow::Result<()> {
+    let specs = commands::command_specs();
+
+    let registered = GuildId(guild_id)
+        .set_application_commands(&ctx.http, |c| {
+            for spec in specs.iter() {
+                c.create_application_command(|command| create_command(command, spec));
+            }
+            c
+        })
+        .await?;
+
+    debug!("Registered {} slash commands", registered.len());
+
+    Ok(())
+}
+
+fn create_command<'a>(
+    command: &'a mut CreateApplicationCommand,
+    spec: &CommandSpec,
+) -> &'a mut CreateApplicationCommand {
+    command
+        .name(&spec.name)
+        .description(truncate(&spec.description));
+    if MODERATOR_COMMANDS.contains(&spec.name.as_str()) {
+        command.default_member_permissions(Permissions::MANAGE_MESSAGES);
+    }
+
+    for subcommand in spec.subcommands.iter() {
+        command.create_option(|o| {
+            o.kind(CommandOptionType::SubCommand)
+                .name(&subcommand.name)
+                .description(truncate(&subcommand.description));
+            for option in sorted_options(subcommand) {
+                o.create_sub_option(|s| create_option(s, option));
+            }
+            o
+        });
+    }
+
+    // The script is entered in a modal instead
+    if spec.name != RHAI_COMMAND {
+        for option in sorted_options(spec) {
+            command.create_option(|o| create_option(o, option));
+        }
+    }
+
+    command
+}
+
+/// Discord requires the required options to come before any optional ones.
+fn sorted_options(spec: &CommandSpec) -> Vec<&OptionSpec> {
+    let mut options = spec.options.iter().collect::<Vec<&OptionSpec>>();
+    options.sort_by_key(|o| !o.required);
+
+    options
+}
+
+fn create_option<'a>(
+    option: &'a mut CreateApplicationCommandOption,
+    spec: &OptionSpec,
+) -> &'a mut CreateApplicationCommandOption {
+    let kind = if spec.is_flag {
+        CommandOptionType::Boolean
+    } else {
+        CommandOptionType::String
+    };
+
+    option
+        .kind(kind)
+        .name(&spec.name)
+        .description(truncate(&spec.description))
+        .required(spec.required)
+        .set_autocomplete(spec.autocomplete);
+    for choice in spec.choices.iter() {
+        option.add_string_choice(choice, choice);
+    }
+
+    option
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(MAX_DESCRIPTION_LEN).collect()
+}
+
+/// Convert a slash command into the text input that `commands::parse` expects.
+pub fn command_input(prefix: &str, command: &ApplicationCommandInteraction) -> String {
+    let subcommand = command.data.options.iter().find(|o| {
+        matches!(
+            o.kind,
+            CommandOptionType::SubCommand | CommandOptionType::SubCommandGroup
+        )
+    });
+
+    match subcommand {
+        Some(o) => input(
+            prefix,
+            &command.data.name,
+            Some(&o.name),
+            &option_values(&o.options),
+        ),
+        None => input(
+            prefix,
+            &command.data.name,
+            None,
+            &option_values(&command.data.options),
+        ),
+    }
+}
+
+/// The text input for the command `name`, its subcommand and the option values.
+fn input(
+    prefix: &str,
+    name: &str,
+    subcommand: Option<&str>,
+    values: &HashMap<String, String>,
+) -> String {
+    let specs = commands::command_specs();
+    let spec = match specs.iter().find(|s| s.name == name) {
+        Some(v) => v,
+        None => return format!("{prefix} {name}"),
+    };
+
+    match subcommand.and_then(|sub| spec.subcommands.iter().find(|s| s.name == sub)) {
+        Some(sub_spec) => format!(
+            "{prefix} {} {} {}",
+            spec.name,
+            sub_spec.name,
+            sub_spec.args(values)
+        ),
+        None => format!("{prefix} {} {}", spec.name, spec.args(values)),
+    }
+    .trim_end()
+    .to_string()
+}
+
+fn option_values(options: &[ApplicationCommandInteractionDataOption]) -> HashMap<String, String> {
+    options
+        .iter()
+        .filter_map(|o| {
+            let value = o.value.as_ref()?;
+            let value = value
+                .as_str()
+                .map_or_else(|| value.to_string(), |v| v.to_string());
+            Some((o.name.clone(), value))
+        })
+        .collect()
+}
+
+/// Suggest values for the option that is being typed.
+pub async fn handle_autocomplete(
+    ctx: &Context,
+    config: &Config,
+    autocomplete: &AutocompleteInteraction,
+) {
+    let mut command = autocomplete.data.name.as_str();
+    let mut options = &autocomplete.data.options;
+    if let Some(subcommand) = options
+        .iter()
+        .find(|o| o.kind == CommandOptionType::SubCommand)
+    {
+        command = &subcommand.name;
+        options = &subcommand.options;
+    }
+
+    let focused = match options.iter().find(|o| o.focused) {
+        Some(v) => v,
+        None => return,
+    };
+    let partial = focused
+        .value
+        .as_ref()
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let suggestions = commands::autocomplete(command, &focused.name, partial, config);
+
+    if let Err(e) = autocomplete
+        .create_autocomplete_response(&ctx.http, |r| {
+            for suggestion in suggestions {
+                r.add_string_choice(&suggestion, &suggestion);
+            }
+            r
+        })
+        .await
+    {
+        error!("{e}");
+    }
+}
+
+/// Ask for a script to run.
+pub async fn show_rhai_modal(ctx: &Context, command: &ApplicationCommandInteraction) {
+    if let Err(e) = command
+        .create_interaction_response(&ctx.http, |r| {
+            r.kind(InteractionResponseType::Modal)
+                .interaction_response_data(|d| {
+                    d.custom_id(RHAI_MODAL_ID)
+                        .title("Run a Rhai script")
+                        .components(|c| {
+                            c.create_action_row(|row| {
+                                row.create_input_text(|t| {
+                                    t.custom_id(RHAI_SCRIPT_ID)
+                                        .label("Script")
+                                        .style(InputTextStyle::Paragraph)
+                                        .placeholder("print(\"Hello!\");")
+                                        .required(true)
+                                })
+                            })
+                        })
+                })
+        })
+        .await
+    {
+        error!("Unable to show Rhai modal: {e}");
+    }
+}
+
+/// Convert a submitted Rhai modal into the text input that `commands::parse` expects.
+/// Returns None if the modal is not a Rhai modal.
+pub fn modal_input(prefix: &str, modal: &ModalSubmitInteraction) -> Option<String> {
+    if modal.data.custom_id != RHAI_MODAL_ID {
+        return None;
+    }
+
+    modal
+        .data
+        .components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|c| match c {
+            ActionRowComponent::InputText(t) if t.custom_id == RHAI_SCRIPT_ID => {
+                Some(rhai_input(prefix, &t.value))
+            }
+            _ => None,
+        })
+}
+
+/// Wrap a script in the code block that the Rhai command expects.
+fn rhai_input(prefix: &str, script: &str) -> String {
+    format!("{prefix} {RHAI_COMMAND} ```rhai\n{script}\n```")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn builds_input_for_commands() {
+        assert_eq!(input("bot?", "ping", None, &values(&[])), "bot? ping");
+        // Positional options follow the definition, not the order Discord sends them in
+        assert_eq!(
+            input(
+                "bot?",
+                "give",
+                None,
+                &values(&[("amount", "5"), ("user", "someone")])
+            ),
+            "bot? give someone 5"
+        );
+        assert_eq!(
+            input(
+                "bot?",
+                "poll",
+                None,
+                &values(&[("text", "\"Tea?\" yes no"), ("duration", "30")])
+            ),
+            "bot? poll --duration 30 \"Tea?\" yes no"
+        );
+        assert_eq!(input("bot?", "unknown", None, &values(&[])), "bot? unknown");
+    }
+
+    #[test]
+    fn builds_input_for_subcommands() {
+        assert_eq!(
+            input(
+                "bot?",
+                "giveaway",
+                Some("start"),
+                &values(&[("text", "\"Prize\" 60")])
+            ),
+            "bot? giveaway start \"Prize\" 60"
+        );
+        assert_eq!(
+            input("bot?", "giveaway", Some("enter"), &values(&[])),
+            "bot? giveaway enter"
+        );
+        assert_eq!(
+            input(
+                "bot?",
+                "admin",
+                Some("block"),
+                &values(&[("text", "free followers"), ("kind", "phrase")])
+            ),
+            "bot? admin block phrase free followers"
+        );
+    }
+
+    #[test]
+    fn registers_required_options_first() {
+        let specs = commands::command_specs();
+        for spec in specs
+            .iter()
+            .chain(specs.iter().flat_map(|s| s.subcommands.iter()))
+        {
+            let options = sorted_options(spec);
+            assert!(
+                options.windows(2).all(|w| w[0].required || !w[1].required),
+                "{}",
+                spec.name
+            );
+        }
+    }
+
+    #[test]
+    fn wraps_rhai_scripts() {
+        assert_eq!(
+            rhai_input("bot?", "let x = 1;\nprint(x);"),
+            "bot? rhai ```rhai\nlet x = 1;\nprint(x);\n```"
+        );
+    }
+}
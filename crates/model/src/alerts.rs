@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+/// Something that happened in the Twitch channel that chat should hear about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    Follow {
+        user: String,
+    },
+    Subscribe {
+        user: String,
+        tier: String,
+    },
+    Resubscribe {
+        user: String,
+        tier: String,
+        months: u64,
+        message: String,
+    },
+    /// The gifter is None for anonymous gifts.
+    GiftSub {
+        gifter: Option<String>,
+        recipient: String,
+        tier: String,
+    },
+    /// The user is None for anonymous cheers.
+    Cheer {
+        user: Option<String>,
+        bits: i64,
+        message: String,
+    },
+    Raid {
+        from: String,
+        viewers: i64,
+    },
+}
+
+impl Alert {
+    /// Identifies the alert so the same event reported by both chat and EventSub is only
+    /// posted once.
+    pub fn key(&self) -> String {
+        let key = match self {
+            Self::Follow { user } => format!("follow:{user}"),
+            Self::Subscribe { user, .. } => format!("subscribe:{user}"),
+            Self::Resubscribe { user, .. } => format!("resubscribe:{user}"),
+            Self::GiftSub { recipient, .. } => format!("gift_sub:{recipient}"),
+            Self::Cheer {
+                user,
+                bits,
+                message,
+            } => format!(
+                "cheer:{}:{bits}:{message}",
+                user.as_deref().unwrap_or_default()
+            ),
+            Self::Raid { from, .. } => format!("raid:{from}"),
+        };
+
+        key.to_lowercase()
+    }
+
+    /// The message to post when no format is configured.
+    pub fn default_format(&self) -> &'static str {
+        match self {
+            Self::Follow { .. } => "Thanks for the follow, {user}!",
+            Self::Subscribe { .. } => "{user} just subscribed with {tier}!",
+            Self::Resubscribe { .. } => "{user} resubscribed with {tier} for {months} months!",
+            Self::GiftSub { .. } => "{user} gifted a {tier} sub to {recipient}!",
+            Self::Cheer { .. } => "{user} cheered {bits} bits!",
+            Self::Raid { .. } => "{user} is raiding with {viewers} viewers!",
+        }
+    }
+
+    /// The variables that formats can use. `{user}` is always the person to thank.
+    pub fn vars(&self) -> HashMap<String, String> {
+        let vars = match self {
+            Self::Follow { user } => vec![("user", user.clone())],
+            Self::Subscribe { user, tier } => vec![("user", user.clone()), ("tier", tier.clone())],
+            Self::Resubscribe {
+                user,
+                tier,
+                months,
+                message,
+            } => vec![
+                ("user", user.clone()),
+                ("tier", tier.clone()),
+                ("months", months.to_string()),
+                ("message", message.clone()),
+            ],
+            Self::GiftSub {
+                gifter,
+                recipient,
+                tier,
+            } => vec![
+                (
+                    "user",
+                    gifter
+                        .clone()
+                        .unwrap_or_else(|| "An anonymous gifter".into()),
+                ),
+                ("recipient", recipient.clone()),
+                ("tier", tier.clone()),
+            ],
+            Self::Cheer {
+                user,
+                bits,
+                message,
+            } => vec![
+                ("user", user.clone().unwrap_or_else(|| "Anonymous".into())),
+                ("bits", bits.to_string()),
+                ("message", message.clone()),
+            ],
+            Self::Raid { from, viewers } => {
+                vec![("user", from.clone()), ("viewers", viewers.to_string())]
+            }
+        };
+
+        vars.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    }
+}
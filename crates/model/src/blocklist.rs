@@ -0,0 +1,311 @@
+use std::{fmt::Display, str::FromStr};
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// The kind of entry in a blocklist.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockKind {
+    /// A single word, only matched as a whole word.
+    #[default]
+    Word,
+    /// Multiple words that are matched anywhere in a message.
+    Phrase,
+    /// A case-insensitive regular expression.
+    Regex,
+}
+
+impl FromStr for BlockKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "word" => Ok(Self::Word),
+            "phrase" => Ok(Self::Phrase),
+            "regex" => Ok(Self::Regex),
+            _ => Err(format!("Unknown kind {s}, expected word, phrase or regex")),
+        }
+    }
+}
+
+impl Display for BlockKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Word => write!(f, "word"),
+            Self::Phrase => write!(f, "phrase"),
+            Self::Regex => write!(f, "regex"),
+        }
+    }
+}
+
+/// Words, phrases and patterns that are not allowed in a Discord guild or Twitch channel.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Blocklist {
+    #[serde(default)]
+    pub words: Vec<String>,
+    #[serde(default)]
+    pub phrases: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Discord role IDs or Twitch badges, e.g. `vip`, whose messages are not filtered.
+    #[serde(default)]
+    pub exempt_roles: Vec<String>,
+
+    #[serde(skip)]
+    compiled: Vec<Regex>,
+}
+
+impl Blocklist {
+    /// Compile the regex patterns. Needs to be called after deserializing.
+    pub fn compile(&mut self) {
+        self.compiled = self
+            .patterns
+            .iter()
+            .filter_map(|p| build_regex(p).ok())
+            .collect();
+    }
+
+    /// Add an entry. Returns an error message if the entry is invalid or already exists.
+    pub fn add(&mut self, kind: BlockKind, text: &str) -> Result<(), String> {
+        let text = match kind {
+            BlockKind::Regex => {
+                build_regex(text).map_err(|e| format!("Invalid regex: {e}"))?;
+                text.to_string()
+            }
+            _ => normalize(text),
+        };
+        if text.is_empty() {
+            return Err("Nothing to block!".into());
+        }
+
+        let entries = self.entries_mut(kind);
+        if entries.contains(&text) {
+            return Err(format!("That {kind} is already blocked!"));
+        }
+        entries.push(text);
+        self.compile();
+
+        Ok(())
+    }
+
+    /// Remove an entry. Returns false if the entry did not exist.
+    pub fn remove(&mut self, kind: BlockKind, text: &str) -> bool {
+        let text = match kind {
+            BlockKind::Regex => text.to_string(),
+            _ => normalize(text),
+        };
+
+        let entries = self.entries_mut(kind);
+        let len = entries.len();
+        entries.retain(|e| e != &text);
+        let removed = entries.len() != len;
+        self.compile();
+
+        removed
+    }
+
+    fn entries_mut(&mut self, kind: BlockKind) -> &mut Vec<String> {
+        match kind {
+            BlockKind::Word => &mut self.words,
+            BlockKind::Phrase => &mut self.phrases,
+            BlockKind::Regex => &mut self.patterns,
+        }
+    }
+
+    /// Whether any of the given roles are exempt from the blocklist.
+    pub fn is_exempt(&self, roles: &[String]) -> bool {
+        roles.iter().any(|r| self.exempt_roles.contains(r))
+    }
+
+    /// Find the first entry that matches the `content`, formatted as `kind: entry`.
+    pub fn find_match(&self, content: &str) -> Option<String> {
+        let normalized = normalize(content);
+        let words = normalized.split(' ').collect::<Vec<&str>>();
+
+        if let Some(w) = self.words.iter().find(|w| words.contains(&w.as_str())) {
+            return Some(format!("{}: {w}", BlockKind::Word));
+        }
+
+        // Pad with spaces so phrases only match whole words
+        let padded = format!(" {normalized} ");
+        if let Some(p) = self
+            .phrases
+            .iter()
+            .find(|p| padded.contains(&format!(" {p} ")))
+        {
+            return Some(format!("{}: {p}", BlockKind::Phrase));
+        }
+
+        self.compiled
+            .iter()
+            .find(|r| r.is_match(content) || r.is_match(&normalized))
+            .map(|r| format!("{}: {}", BlockKind::Regex, r.as_str()))
+    }
+
+    /// Format the entries on a single line.
+    pub fn summary(&self) -> String {
+        format!(
+            "Words: {} | Phrases: {} | Regexes: {} | Exempt roles: {}",
+            self.words.join(", "),
+            self.phrases.join(", "),
+            self.patterns.join(", "),
+            self.exempt_roles.join(", ")
+        )
+    }
+}
+
+fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(1 << 16)
+        .build()
+}
+
+/// Normalize text so look-alike characters, accents, invisible characters and common
+/// letter substitutions match their plain lowercase letters. Anything that is not a
+/// letter or digit is collapsed into a single space.
+pub fn normalize(text: &str) -> String {
+    let mapped = text
+        .nfkd()
+        .filter(|c| !is_combining_mark(*c) && !is_invisible(*c))
+        .flat_map(char::to_lowercase)
+        .map(unconfuse)
+        .collect::<String>();
+
+    mapped
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}' | '\u{034F}' | '\u{200B}'..='\u{200F}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}'
+    )
+}
+
+/// Map characters that are commonly used to evade filters to the letter they look like.
+fn unconfuse(c: char) -> char {
+    match c {
+        // Cyrillic
+        'а' => 'a',
+        'в' => 'b',
+        'е' => 'e',
+        'і' => 'i',
+        'ј' => 'j',
+        'к' => 'k',
+        'м' => 'm',
+        'н' => 'h',
+        'о' => 'o',
+        'р' => 'p',
+        'с' => 'c',
+        'т' => 't',
+        'у' => 'y',
+        'х' => 'x',
+        'ѕ' => 's',
+        'ԁ' => 'd',
+        // Greek
+        'α' => 'a',
+        'β' => 'b',
+        'ε' => 'e',
+        'ι' => 'i',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'τ' => 't',
+        'υ' => 'u',
+        'χ' => 'x',
+        // Substitutions
+        '0' => 'o',
+        '1' => 'i',
+        '3' => 'e',
+        '4' | '@' => 'a',
+        '5' | '$' => 's',
+        '7' => 't',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocklist() -> Blocklist {
+        let mut blocklist = Blocklist::default();
+        blocklist.add(BlockKind::Word, "scam").unwrap();
+        blocklist.add(BlockKind::Phrase, "free nitro").unwrap();
+        blocklist.add(BlockKind::Regex, r"bit\.ly/\w+").unwrap();
+
+        blocklist
+    }
+
+    #[test]
+    fn normalizes_fullwidth_letters() {
+        assert_eq!(normalize("ＳＣＡＭ"), "scam");
+    }
+
+    #[test]
+    fn normalizes_homoglyphs() {
+        // Cyrillic с and а, Greek α and κ
+        assert_eq!(normalize("\u{0441}\u{0430}m"), "cam");
+        assert_eq!(normalize("\u{03B1}\u{03BA}"), "ak");
+        assert_eq!(normalize("ΑΚ"), "ak");
+    }
+
+    #[test]
+    fn removes_invisible_characters_and_accents() {
+        assert_eq!(normalize("s\u{200B}c\u{00AD}a\u{FEFF}m"), "scam");
+        assert_eq!(normalize("scàm"), "scam");
+    }
+
+    #[test]
+    fn normalizes_leetspeak() {
+        assert_eq!(normalize("5c4m"), "scam");
+        assert_eq!(normalize("$c@m"), "scam");
+        assert_eq!(normalize("fr33 n1tr0"), "free nitro");
+    }
+
+    #[test]
+    fn matches_whole_words_and_phrases() {
+        let blocklist = blocklist();
+
+        assert_eq!(
+            blocklist.find_match("what a SC4M"),
+            Some("word: scam".into())
+        );
+        assert_eq!(blocklist.find_match("scampi for dinner"), None);
+        assert_eq!(
+            blocklist.find_match("get FREE... nitro now"),
+            Some("phrase: free nitro".into())
+        );
+        assert_eq!(blocklist.find_match("carefree nitrogen"), None);
+    }
+
+    #[test]
+    fn matches_regex_entries() {
+        let mut blocklist = blocklist();
+
+        assert_eq!(
+            blocklist.find_match("click BIT.LY/abc"),
+            Some(r"regex: bit\.ly/\w+".into())
+        );
+        assert!(blocklist.add(BlockKind::Regex, "(unclosed").is_err());
+
+        assert!(blocklist.remove(BlockKind::Regex, r"bit\.ly/\w+"));
+        assert_eq!(blocklist.find_match("click bit.ly/abc"), None);
+    }
+
+    #[test]
+    fn exempts_roles() {
+        let mut blocklist = blocklist();
+        blocklist.exempt_roles.push("vip".into());
+
+        assert!(blocklist.is_exempt(&["subscriber".into(), "vip".into()]));
+        assert!(!blocklist.is_exempt(&["subscriber".into()]));
+        assert!(!blocklist.is_exempt(&[]));
+    }
+}
@@ -1,7 +1,9 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
+use crate::alerts::Alert;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// The duration to wait between bot ticks.
@@ -10,33 +12,130 @@ pub struct Config {
     /// Ticks to elapse before the bot checks if the configured Twitch stream is live.
     #[serde(default = "default_check_live_ticks")]
     pub check_live_ticks: u64,
+    /// How long answers to stream info commands like `uptime` are reused in seconds.
+    #[serde(default = "default_channel_info_cache_secs")]
+    pub channel_info_cache_secs: u64,
     /// Role name to emoji.
     #[serde(default)]
     pub reaction_roles: HashMap<String, String>,
+    /// Reaction-role menus that are posted and kept up to date by the bot.
+    #[serde(default)]
+    pub role_menus: Vec<RoleMenu>,
     /// The max width to use for any message if the message has a configurable width.
     #[serde(default = "default_max_message_width")]
     pub max_message_width: u16,
-    /// The Discord role ID to use when silencing a user. A value of 0 means there is no role.
+    /// The Discord role ID to use when silencing a user. A value of 0 means there is no role
+    /// and Discord's own timeouts are used instead.
     #[serde(default)]
     pub timeout_role_id: u64,
 
     /// The minimum duration between stream notifications in seconds.
     #[serde(default = "default_min_stream_notification_secs")]
     pub min_stream_notification_secs: u64,
+    /// Text sent along with the go-live embed. Can use `{channel}`, `{title}`, `{url}` and
+    /// `{game}`.
     #[serde(default)]
     pub stream_notification_format: String,
+    /// The Discord role ID to ping when the stream goes live. A value of 0 means there is no ping.
+    #[serde(default)]
+    pub stream_notification_role: u64,
 
     /// The Discord channel ID to use when sending stream notifications.
     #[serde(default)]
     pub stream_notification_channel: u64,
+    /// Other Twitch channels to send go-live notifications for.
+    #[serde(default)]
+    pub watched_channels: Vec<WatchedChannel>,
+    /// How to receive EventSub notifications. Changes take effect when the Twitch bot restarts.
+    #[serde(default)]
+    pub eventsub_transport: EventSubTransport,
+    /// The public HTTPS URL of the server's `/eventsub` route for the webhook transport.
+    #[serde(default)]
+    pub eventsub_callback_url: String,
     /// The Discord channel ID to use when sending debug messages.
     #[serde(default)]
     pub debug_channel: u64,
     #[serde(default)]
     pub roles_channel: u64,
+    /// The Discord channel ID to use when posting moderation actions.
+    #[serde(default)]
+    pub mod_log_channel: u64,
+    /// The Discord channel ID to use when posting polls that were not started from Discord.
+    #[serde(default)]
+    pub poll_channel: u64,
+
+    /// The Discord channel ID to use when posting giveaways that were not started from Discord.
+    #[serde(default)]
+    pub giveaway_channel: u64,
+    /// The Twitch chat message that enters the current giveaway.
+    #[serde(default = "default_giveaway_keyword")]
+    pub giveaway_keyword: String,
+
+    /// The Discord role ID to grant to linked Twitch subscribers. A value of 0 means there is no role.
+    #[serde(default)]
+    pub twitch_subscriber_role: u64,
+    /// The Discord role ID to grant to linked Twitch VIPs. A value of 0 means there is no role.
+    #[serde(default)]
+    pub twitch_vip_role: u64,
+    /// The Discord role ID to grant to linked Twitch moderators. A value of 0 means there is no role.
+    #[serde(default)]
+    pub twitch_moderator_role: u64,
+    /// Ticks to elapse before Twitch roles are synced to Discord.
+    #[serde(default = "default_sync_roles_ticks")]
+    pub sync_roles_ticks: u64,
+
+    /// Points given to each viewer and active chatter every `points_ticks` while the stream
+    /// is live.
+    #[serde(default = "default_points_per_interval")]
+    pub points_per_interval: i64,
+    /// Ticks to elapse before points are given to active chatters.
+    #[serde(default = "default_points_ticks")]
+    pub points_ticks: u64,
+    /// Points given for chatting while the stream is live.
+    #[serde(default = "default_chat_bonus_points")]
+    pub chat_bonus_points: i64,
+    /// The minimum duration between chat bonuses for a chatter in seconds.
+    #[serde(default = "default_chat_bonus_cooldown_secs")]
+    pub chat_bonus_cooldown_secs: u64,
+    /// How long a chatter counts as watching after their last message in seconds.
+    #[serde(default = "default_active_chatter_secs")]
+    pub active_chatter_secs: u64,
+
+    /// Thresholds and actions for the antispam rules.
+    #[serde(default)]
+    pub antispam: AntispamConfig,
+    /// The action for messages that match a blocklist.
+    #[serde(default = "default_blocklist_action")]
+    pub blocklist_action: SpamAction,
+    /// Whether blocklist matches count as strikes, escalating like other spam.
+    #[serde(default = "default_blocklist_escalate")]
+    pub blocklist_escalate: bool,
+
+    /// Welcome and goodbye messages, starter roles and the rules gate.
+    #[serde(default)]
+    pub onboarding: OnboardingConfig,
+
+    /// Messages for follows, subs, cheers and raids.
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub shoutouts: ShoutoutConfig,
+    /// Mirroring between Twitch chat and a Discord channel.
+    #[serde(default)]
+    pub relay: RelayConfig,
+
+    /// Ticks to elapse before the bot state is saved to disk.
+    #[serde(default = "default_save_state_ticks")]
+    pub save_state_ticks: u64,
 
     #[serde(default)]
     pub ad_hoc: HashMap<String, String>,
+    /// Ad-hoc command to the points it costs to run.
+    #[serde(default)]
+    pub ad_hoc_costs: HashMap<String, i64>,
+    /// Channel point reward ID or title to the actions to run when it is redeemed.
+    #[serde(default)]
+    pub reward_actions: HashMap<String, Vec<RewardAction>>,
 }
 
 impl Config {
@@ -44,15 +143,44 @@ impl Config {
         Self {
             tick_duration: default_tick_duration(),
             check_live_ticks: default_check_live_ticks(),
+            channel_info_cache_secs: default_channel_info_cache_secs(),
             reaction_roles: HashMap::new(),
+            role_menus: vec![],
             max_message_width: default_max_message_width(),
             timeout_role_id: u64::default(),
             stream_notification_channel: u64::default(),
             min_stream_notification_secs: default_min_stream_notification_secs(),
             stream_notification_format: Default::default(),
+            stream_notification_role: u64::default(),
+            watched_channels: vec![],
+            eventsub_transport: EventSubTransport::default(),
+            eventsub_callback_url: String::new(),
             debug_channel: u64::default(),
             roles_channel: u64::default(),
+            mod_log_channel: u64::default(),
+            poll_channel: u64::default(),
+            giveaway_channel: u64::default(),
+            giveaway_keyword: default_giveaway_keyword(),
+            twitch_subscriber_role: u64::default(),
+            twitch_vip_role: u64::default(),
+            twitch_moderator_role: u64::default(),
+            sync_roles_ticks: default_sync_roles_ticks(),
+            points_per_interval: default_points_per_interval(),
+            points_ticks: default_points_ticks(),
+            chat_bonus_points: default_chat_bonus_points(),
+            chat_bonus_cooldown_secs: default_chat_bonus_cooldown_secs(),
+            active_chatter_secs: default_active_chatter_secs(),
+            antispam: AntispamConfig::default(),
+            blocklist_action: default_blocklist_action(),
+            blocklist_escalate: default_blocklist_escalate(),
+            onboarding: OnboardingConfig::default(),
+            alerts: AlertsConfig::default(),
+            shoutouts: ShoutoutConfig::default(),
+            relay: RelayConfig::default(),
+            save_state_ticks: default_save_state_ticks(),
             ad_hoc: HashMap::new(),
+            ad_hoc_costs: HashMap::new(),
+            reward_actions: HashMap::new(),
         }
     }
 
@@ -63,6 +191,396 @@ impl Config {
     pub fn ad_hoc_commands(&self) -> Vec<String> {
         self.ad_hoc.iter().map(|(k, _)| k.to_string()).collect()
     }
+
+    /// The actions for a channel point reward. Rewards are looked up by ID first, then
+    /// by title.
+    pub fn actions_for_reward(&self, reward_id: &str, title: &str) -> &[RewardAction] {
+        self.reward_actions
+            .get(reward_id)
+            .or_else(|| {
+                self.reward_actions
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case(title))
+                    .map(|(_, v)| v)
+            })
+            .map_or(&[], |v| v.as_slice())
+    }
+
+    /// The notification settings for the Twitch channel `login`. Channels that are not
+    /// watched use the settings of the bot's own channel.
+    pub fn watched_channel(&self, login: &str) -> WatchedChannel {
+        self.watched_channels
+            .iter()
+            .find(|c| c.login.eq_ignore_ascii_case(login))
+            .cloned()
+            .unwrap_or_else(|| WatchedChannel {
+                login: login.to_string(),
+                notification_channel: self.stream_notification_channel,
+                notification_format: self.stream_notification_format.clone(),
+                notification_role: self.stream_notification_role,
+                min_notification_secs: self.min_stream_notification_secs,
+            })
+    }
+}
+
+/// A Twitch channel, such as a friend or co-streamer, to send go-live notifications for.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedChannel {
+    /// The Twitch login of the channel.
+    pub login: String,
+    /// The Discord channel ID to send its notifications to.
+    pub notification_channel: u64,
+    /// Works like `stream_notification_format`.
+    #[serde(default)]
+    pub notification_format: String,
+    /// The Discord role ID to ping. A value of 0 means there is no ping.
+    #[serde(default)]
+    pub notification_role: u64,
+    /// The minimum duration between its notifications in seconds.
+    #[serde(default = "default_min_stream_notification_secs")]
+    pub min_notification_secs: u64,
+}
+
+/// Something to do when a channel point reward is redeemed. Text can use the `{user}`,
+/// `{reward}` and `{input}` variables.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RewardAction {
+    /// Run a bot command as the redeemer, e.g. `roll {input}`. Moderator-only commands
+    /// are never allowed.
+    Command { command: String },
+    /// Post a message in Twitch chat.
+    Message { format: String },
+    /// Run a Rhai script with `user`, `reward` and `input` variables and post its output.
+    Script { script: String },
+    /// Add the redeemer and their input to the end of a queue.
+    Queue { name: String },
+    /// Mark the redemption as fulfilled. Twitch only allows this for rewards created with
+    /// the bot's client ID.
+    Fulfill,
+    /// Cancel the redemption and refund the points. Has the same limits as `Fulfill`.
+    Refund,
+}
+
+/// How Twitch sends EventSub notifications. Whether the stream is live is still checked
+/// every `check_live_ticks` in case a notification is missed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSubTransport {
+    /// EventSub is not used.
+    Disabled,
+    #[default]
+    Websocket,
+    /// Notifications are sent to `eventsub_callback_url`, which needs the server to run.
+    Webhook,
+}
+
+/// How a role menu treats picking and unpicking options.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoleMenuMode {
+    /// Roles are added and removed freely.
+    #[default]
+    Normal,
+    /// Only one role from the menu can be held at a time.
+    Unique,
+    /// Roles can be picked but never removed, and reactions are cleared after picking.
+    Verify,
+    /// Roles can be picked, but unpicking does not remove them.
+    AddOnly,
+}
+
+/// How the options of a role menu are shown.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoleMenuStyle {
+    #[default]
+    Reactions,
+    Buttons,
+    Select,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleMenu {
+    /// Unique name used to find the posted message again when the config changes.
+    pub name: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub mode: RoleMenuMode,
+    #[serde(default)]
+    pub style: RoleMenuStyle,
+    /// The Discord channel ID to post the menu in. Uses `roles_channel` if not set.
+    #[serde(default)]
+    pub channel: u64,
+    pub options: Vec<RoleMenuOption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleMenuOption {
+    /// The name of the Discord role.
+    pub role: String,
+    /// A unicode emoji or a custom emoji in the `<:name:id>` format.
+    pub emoji: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// What to do with a message that breaks an antispam rule, from least to most severe.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpamAction {
+    /// The rule is disabled.
+    Ignore,
+    /// Reply to the message asking the user to stop.
+    #[default]
+    Warn,
+    /// Delete the message and warn the user.
+    Delete,
+    /// Delete the message and silence the user for one of the `silence_secs` durations.
+    Timeout,
+    /// Delete the message and kick the user. Twitch has no kicks, so Twitch users are banned.
+    Kick,
+}
+
+impl Display for SpamAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ignore => write!(f, "ignore"),
+            Self::Warn => write!(f, "warn"),
+            Self::Delete => write!(f, "delete"),
+            Self::Timeout => write!(f, "timeout"),
+            Self::Kick => write!(f, "kick"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AntispamConfig {
+    /// The max amount of messages a user can send within `rate_window_secs`.
+    pub rate_max_messages: usize,
+    pub rate_window_secs: f32,
+    pub rate_action: SpamAction,
+
+    /// The max amount of similar messages a user can send within `duplicate_window_secs`.
+    pub duplicate_max_messages: usize,
+    pub duplicate_window_secs: f32,
+    /// How similar two messages have to be to count as duplicates, from 0.0 to 1.0.
+    pub duplicate_similarity: f32,
+    pub duplicate_action: SpamAction,
+
+    /// The max amount of users and roles a single message can mention.
+    pub max_mentions: usize,
+    pub mention_action: SpamAction,
+
+    /// The max ratio of uppercase letters to letters in a message.
+    pub max_caps_ratio: f32,
+    /// The max ratio of emojis to non-whitespace characters in a message.
+    pub max_emoji_ratio: f32,
+    /// Messages shorter than this are not checked for caps or emojis.
+    pub ratio_min_length: usize,
+    pub ratio_action: SpamAction,
+
+    /// Action for messages containing links. Invites are handled by `invite_action`.
+    pub link_action: SpamAction,
+    /// Action for messages containing Discord invites.
+    pub invite_action: SpamAction,
+
+    /// Accounts younger than this many days cannot post links or mention more than one user.
+    pub new_account_days: u64,
+    pub new_account_action: SpamAction,
+
+    /// The action for a user's first, second, etc. strike. The last action is used for any
+    /// further strikes. A rule's own action is used instead if it is more severe.
+    pub escalation: Vec<SpamAction>,
+    /// How long a user's first, second, etc. silence lasts in seconds. The last duration is
+    /// used for any further silences.
+    pub silence_secs: Vec<u64>,
+    /// Strikes after which the bot stops replying to a user and only deletes their messages.
+    pub silent_after_strikes: u8,
+    /// One strike is removed for every this many seconds without breaking a rule.
+    pub strike_decay_secs: u64,
+    /// How long a user's messages are remembered for in seconds. Users with strikes left
+    /// are remembered until their strikes have decayed.
+    pub history_ttl_secs: u64,
+}
+
+impl Default for AntispamConfig {
+    fn default() -> Self {
+        Self {
+            rate_max_messages: 4,
+            rate_window_secs: 3.0,
+            rate_action: SpamAction::Delete,
+            duplicate_max_messages: 2,
+            duplicate_window_secs: 30.0,
+            duplicate_similarity: 0.9,
+            duplicate_action: SpamAction::Delete,
+            max_mentions: 5,
+            mention_action: SpamAction::Timeout,
+            max_caps_ratio: 0.7,
+            max_emoji_ratio: 0.5,
+            ratio_min_length: 12,
+            ratio_action: SpamAction::Warn,
+            link_action: SpamAction::Ignore,
+            invite_action: SpamAction::Delete,
+            new_account_days: 7,
+            new_account_action: SpamAction::Delete,
+            escalation: vec![
+                SpamAction::Warn,
+                SpamAction::Delete,
+                SpamAction::Timeout,
+                SpamAction::Timeout,
+                SpamAction::Kick,
+            ],
+            silence_secs: vec![300, 3600, 86400],
+            silent_after_strikes: 4,
+            strike_decay_secs: 120,
+            history_ttl_secs: 600,
+        }
+    }
+}
+
+/// Messages and roles for members joining and leaving the Discord guild. Formats can use
+/// `{user}` to mention the member, `{name}` and `{member_count}`. Empty formats are not sent.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnboardingConfig {
+    /// The Discord channel ID to welcome new members in.
+    pub welcome_channel: u64,
+    pub welcome_format: String,
+    /// Sent to new members in a DM.
+    pub welcome_dm_format: String,
+    /// The Discord channel ID to announce members leaving in.
+    pub goodbye_channel: u64,
+    pub goodbye_format: String,
+    /// Discord role IDs given to every new member.
+    pub starter_roles: Vec<u64>,
+
+    /// The Discord channel ID to post the rules in. A value of 0 means there is no rules gate.
+    pub rules_channel: u64,
+    pub rules_text: String,
+    /// The Discord role ID granted to members that accept the rules.
+    pub rules_role: u64,
+    /// Reacting to the rules with this emoji also accepts them. Empty means only the button
+    /// accepts the rules.
+    pub rules_emoji: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// The Discord channel ID to post alerts in.
+    pub discord_channel: u64,
+    /// The same alert is only posted once within this many seconds, since Twitch can
+    /// report it in both chat and EventSub.
+    pub dedup_secs: u64,
+    pub follow: AlertSettings,
+    pub subscribe: AlertSettings,
+    pub resubscribe: AlertSettings,
+    pub gift_sub: AlertSettings,
+    pub cheer: AlertSettings,
+    pub raid: AlertSettings,
+}
+
+impl AlertsConfig {
+    /// The settings for the type of the alert.
+    pub fn settings(&self, alert: &Alert) -> &AlertSettings {
+        match alert {
+            Alert::Follow { .. } => &self.follow,
+            Alert::Subscribe { .. } => &self.subscribe,
+            Alert::Resubscribe { .. } => &self.resubscribe,
+            Alert::GiftSub { .. } => &self.gift_sub,
+            Alert::Cheer { .. } => &self.cheer,
+            Alert::Raid { .. } => &self.raid,
+        }
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            discord_channel: 0,
+            dedup_secs: 60,
+            follow: AlertSettings::default(),
+            subscribe: AlertSettings::default(),
+            resubscribe: AlertSettings::default(),
+            gift_sub: AlertSettings::default(),
+            cheer: AlertSettings::default(),
+            raid: AlertSettings::default(),
+        }
+    }
+}
+
+/// Where one type of alert is posted. Empty formats use the default message of the alert.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertSettings {
+    pub twitch: bool,
+    pub twitch_format: String,
+    /// Only used if `discord_channel` is set.
+    pub discord: bool,
+    pub discord_format: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShoutoutConfig {
+    /// The shoutout posted in chat. Can use `{channel}`, `{login}`, `{game}`, `{title}`
+    /// and `{url}`.
+    pub format: String,
+    /// Also give a native Twitch shoutout, which needs the bot to be a moderator.
+    pub native: bool,
+    /// Give a shoutout to incoming raids.
+    pub on_raid: bool,
+    /// The fewest viewers a raid needs to get a shoutout.
+    pub min_raid_viewers: i64,
+}
+
+impl Default for ShoutoutConfig {
+    fn default() -> Self {
+        Self {
+            format: "Go check out {channel} at {url}! They were last playing {game}.".into(),
+            native: false,
+            on_raid: false,
+            min_raid_viewers: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// The Discord channel ID that is relayed. The relay is off if this is 0.
+    pub discord_channel: u64,
+    /// Mirror Twitch chat into the channel through a webhook.
+    pub twitch_to_discord: bool,
+    /// Post messages from the channel in Twitch chat.
+    pub discord_to_twitch: bool,
+    /// Only Discord messages starting with this are posted in Twitch chat, without it.
+    /// Every message is if empty.
+    pub discord_trigger: String,
+    /// Discord messages in Twitch chat. Can use `{user}` and `{message}`.
+    pub twitch_format: String,
+    /// The most messages relayed from Twitch to Discord each minute.
+    pub twitch_to_discord_per_minute: usize,
+    /// The most messages relayed from Discord to Twitch each minute.
+    pub discord_to_twitch_per_minute: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            discord_channel: 0,
+            twitch_to_discord: false,
+            discord_to_twitch: false,
+            discord_trigger: String::new(),
+            twitch_format: "[Discord] {user}: {message}".into(),
+            twitch_to_discord_per_minute: 30,
+            discord_to_twitch_per_minute: 10,
+        }
+    }
 }
 
 pub fn default_tick_duration() -> f32 {
@@ -73,6 +591,10 @@ pub fn default_check_live_ticks() -> u64 {
     240
 }
 
+fn default_channel_info_cache_secs() -> u64 {
+    30
+}
+
 fn default_max_message_width() -> u16 {
     36
 }
@@ -80,3 +602,43 @@ fn default_max_message_width() -> u16 {
 fn default_min_stream_notification_secs() -> u64 {
     21600
 }
+
+fn default_giveaway_keyword() -> String {
+    "!enter".into()
+}
+
+pub fn default_sync_roles_ticks() -> u64 {
+    7200
+}
+
+fn default_points_per_interval() -> i64 {
+    10
+}
+
+pub fn default_points_ticks() -> u64 {
+    600
+}
+
+fn default_chat_bonus_points() -> i64 {
+    1
+}
+
+fn default_chat_bonus_cooldown_secs() -> u64 {
+    60
+}
+
+fn default_active_chatter_secs() -> u64 {
+    600
+}
+
+fn default_blocklist_action() -> SpamAction {
+    SpamAction::Delete
+}
+
+fn default_blocklist_escalate() -> bool {
+    true
+}
+
+pub fn default_save_state_ticks() -> u64 {
+    60
+}
@@ -15,6 +15,9 @@ pub struct TwitchCreds {
 
     pub bot_name: String,
     pub channel_name: String,
+
+    /// The secret EventSub webhook notifications are signed with.
+    pub eventsub_secret: String,
 }
 
 impl TwitchCreds {
@@ -24,6 +27,7 @@ impl TwitchCreds {
         client_secret: &str,
         bot_name: &str,
         channel_name: &str,
+        eventsub_secret: &str,
     ) -> Self {
         Self {
             refresh_token: refresh_token.to_string(),
@@ -31,6 +35,7 @@ impl TwitchCreds {
             client_secret: client_secret.to_string(),
             bot_name: bot_name.to_string(),
             channel_name: channel_name.to_string(),
+            eventsub_secret: eventsub_secret.to_string(),
         }
     }
 }
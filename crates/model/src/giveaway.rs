@@ -0,0 +1,185 @@
+use std::{fmt::Display, str::FromStr};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::state::unix_now;
+
+/// Who is allowed to enter a giveaway.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Eligibility {
+    #[default]
+    Everyone,
+    Subscribers,
+    Vips,
+}
+
+impl FromStr for Eligibility {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "everyone" | "all" => Ok(Self::Everyone),
+            "subscribers" | "subs" => Ok(Self::Subscribers),
+            "vips" | "vip" => Ok(Self::Vips),
+            _ => Err(format!(
+                "Unknown eligibility {s}, expected everyone, subs or vips"
+            )),
+        }
+    }
+}
+
+impl Display for Eligibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Everyone => write!(f, "everyone"),
+            Self::Subscribers => write!(f, "subscribers"),
+            Self::Vips => write!(f, "vips"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The platform-prefixed user key, e.g. `twitch:someone`.
+    pub user: String,
+    /// The display name of the user.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Giveaway {
+    pub prize: String,
+    /// Unix timestamp in seconds of when entries close. Runs until ended manually if not set.
+    pub ends_at: Option<u64>,
+    pub eligibility: Eligibility,
+    /// The seed used for drawing winners. Stored so draws can be audited later, but must
+    /// not be shown before entries close since it decides the winner.
+    pub seed: u64,
+
+    /// The Discord channel and message IDs of the giveaway message, if it has been posted.
+    #[serde(default)]
+    pub discord_message: Option<(u64, u64)>,
+    #[serde(default)]
+    pub entries: Vec<Entry>,
+    /// Every winner that has been drawn, the last one being the current winner.
+    #[serde(default)]
+    pub winners: Vec<Entry>,
+    /// Whether entries have been closed and a winner has been drawn.
+    #[serde(default)]
+    pub is_closed: bool,
+}
+
+impl Giveaway {
+    /// Create a new `Giveaway` with a random seed. Entries close after `duration`
+    /// seconds if a duration is given.
+    pub fn new(prize: String, duration: Option<u64>, eligibility: Eligibility) -> Self {
+        Self {
+            prize,
+            ends_at: duration.map(|d| unix_now() + d),
+            eligibility,
+            seed: rand::random(),
+            discord_message: None,
+            entries: vec![],
+            winners: vec![],
+            is_closed: false,
+        }
+    }
+
+    /// Whether entries should be closed and a winner drawn.
+    pub fn should_close(&self) -> bool {
+        !self.is_closed && self.ends_at.is_some_and(|t| unix_now() >= t)
+    }
+
+    /// Add an entry. Returns false if the user has already entered or entries are closed.
+    pub fn enter(&mut self, entry: Entry) -> bool {
+        if self.is_closed || self.entries.iter().any(|e| e.user == entry.user) {
+            return false;
+        }
+
+        self.entries.push(entry);
+
+        true
+    }
+
+    /// Draw a winner from the entries that have not already won. The draw only depends
+    /// on the seed, the entries and the number of previous draws, so it can be repeated
+    /// to verify the result.
+    pub fn draw(&mut self) -> Option<Entry> {
+        self.is_closed = true;
+
+        let mut pool = self
+            .entries
+            .iter()
+            .filter(|e| !self.winners.iter().any(|w| w.user == e.user))
+            .collect::<Vec<&Entry>>();
+        if pool.is_empty() {
+            return None;
+        }
+        pool.sort_by(|a, b| a.user.cmp(&b.user));
+
+        let mut rng = StdRng::seed_from_u64(self.seed.wrapping_add(self.winners.len() as u64));
+        let winner = pool[rng.gen_range(0..pool.len())].clone();
+        self.winners.push(winner.clone());
+
+        Some(winner)
+    }
+
+    /// The SHA-256 hash of the seed as hex. It can be shown while entries are open and
+    /// checked against the seed once it is revealed.
+    pub fn commitment(&self) -> String {
+        hex::encode(Sha256::digest(self.seed.to_string()))
+    }
+
+    /// Information needed to verify the draws. The seed is only revealed once entries are
+    /// closed, before that only its commitment is shown.
+    pub fn audit_text(&self) -> String {
+        let seed = if self.is_closed {
+            format!("seed: {}", self.seed)
+        } else {
+            format!("seed sha256: {}", self.commitment())
+        };
+
+        format!(
+            "Giveaway for {} | {seed} | entries: {} | winners: {}",
+            self.prize,
+            self.entries.len(),
+            self.winners
+                .iter()
+                .map(|w| w.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(user: &str) -> Entry {
+        Entry {
+            user: format!("twitch:{user}"),
+            name: user.into(),
+        }
+    }
+
+    #[test]
+    fn seed_is_hidden_until_closed() {
+        let mut giveaway = Giveaway::new("A game".into(), None, Eligibility::Everyone);
+        giveaway.seed = 42;
+        giveaway.enter(entry("someone"));
+
+        let text = giveaway.audit_text();
+        assert!(!text.contains("seed: 42"));
+        assert!(text.contains(&giveaway.commitment()));
+        assert_eq!(
+            giveaway.commitment(),
+            "73475cb40a568e8da8a045ced110137e159f890ac4da883b6b17dc651b3a8049"
+        );
+
+        giveaway.draw();
+        assert!(giveaway.audit_text().contains("seed: 42"));
+    }
+}
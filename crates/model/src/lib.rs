@@ -1,3 +1,10 @@
+pub mod alerts;
+pub mod blocklist;
 pub mod config;
 pub mod creds;
+pub mod giveaway;
 pub mod messages;
+pub mod modlog;
+pub mod relay;
+pub mod state;
+pub mod stream;
@@ -1,10 +1,25 @@
-use crate::config::Config;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::{broadcast, oneshot};
+
+use crate::{
+    alerts::Alert, config::Config, giveaway::Giveaway, modlog::ModAction, state::Poll,
+    stream::StreamInfo,
+};
+
+/// How long to wait for the answer to a `Request`.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone)]
 pub enum CentralMessage {
     Discord(DiscordMessage),
     Twitch(TwitchMessage),
     Server(ServerMessage),
+    /// A request for the subsystem in `Request::target`. Only that subsystem answers it.
+    Request(Envelope),
 
     ConfigUpdated,
 
@@ -18,6 +33,16 @@ pub enum DiscordMessage {
 
     ConfigUpdated(Config),
 
+    PollStarted(Poll),
+    /// The poll has been closed and tallied. Contains the results text.
+    PollEnded(String),
+    GiveawayStarted(Giveaway),
+    /// The giveaway has been closed and a winner was drawn. Contains the results text.
+    GiveawayEnded(String),
+    /// Twitch roles should be synced to Discord now instead of waiting for the next sync.
+    SyncRolesRequested,
+    Request(Envelope),
+
     Ready,
 }
 
@@ -27,19 +52,257 @@ pub enum TwitchMessage {
     Error(String),
 
     Ready,
-    ChannelLive {
-        channel: String,
-        title: String,
-        url: String,
+    StreamStarted(StreamInfo),
+    /// The title or game of the live stream changed.
+    StreamUpdated(StreamInfo),
+    StreamEnded {
+        stream: StreamInfo,
+        vod_url: Option<String>,
+    },
+    PollStarted(Poll),
+    GiveawayStarted(Giveaway),
+    /// The Twitch roles of every linked account.
+    UserRoles(Vec<TwitchUserRoles>),
+    /// Twitch roles should be synced to Discord now instead of waiting for the next sync.
+    SyncRolesRequested,
+    /// A moderation action was taken in Twitch chat and should be posted to the mod log.
+    ModAction(ModAction),
+    Followed {
+        user: String,
+    },
+    /// Someone subscribed or was gifted a subscription.
+    Subscribed {
+        user: String,
+        tier: String,
+        is_gift: bool,
+    },
+    /// The user is None for anonymous cheers.
+    Cheered {
+        user: Option<String>,
+        bits: i64,
+        message: String,
     },
+    Raided {
+        from: String,
+        /// The login of the raiding channel.
+        login: String,
+        viewers: i64,
+    },
+    RewardRedeemed(Redemption),
+    /// An alert that was posted in Twitch chat and should also be posted to Discord.
+    Alert(Alert),
+    /// A moderator asked for a shoutout to the channel with this login.
+    ShoutoutRequested {
+        user: String,
+    },
+    /// A moderator asked to raid the channel with this login.
+    RaidRequested {
+        user: String,
+    },
+    /// Text that should be posted in Twitch chat.
+    ChatMessage(String),
+    Request(Envelope),
 
     TokenExpired,
 }
 
+#[derive(Debug, Clone)]
+pub struct TwitchUserRoles {
+    pub login: String,
+    pub is_subscriber: bool,
+    pub is_vip: bool,
+    pub is_moderator: bool,
+}
+
+/// A question about the bot's Twitch channel, or a change to it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChannelRequest {
+    Uptime,
+    Game,
+    Title,
+    /// How long the user with this login has followed the channel.
+    Followage(String),
+    SetGame(String),
+    SetTitle(String),
+}
+
+/// A redemption of a channel point reward.
+#[derive(Debug, Clone)]
+pub struct Redemption {
+    pub id: String,
+    pub reward_id: String,
+    pub reward: String,
+    pub cost: i64,
+    /// The display name of the user that redeemed the reward.
+    pub user: String,
+    pub login: String,
+    /// Text entered by the user, if the reward asks for it.
+    pub input: String,
+}
+
 #[derive(Debug, Clone)]
 pub enum ServerMessage {
     Debug(String),
     Error(String),
 
+    /// A verified EventSub notification received through the webhook transport.
+    EventSub {
+        message_id: String,
+        body: String,
+    },
+    Request(Envelope),
+
     Ready,
 }
+
+/// A part of the bot that can answer requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Discord,
+    Twitch,
+}
+
+/// Something one subsystem needs from another and waits for.
+#[derive(Debug, Clone)]
+pub enum Request {
+    /// Answered with the text to show.
+    Channel(ChannelRequest),
+    /// Post text in Twitch chat.
+    PostTwitch(String),
+    /// Answered with the profile image URL of the Twitch user with this login.
+    ProfileImage(String),
+    /// Post text in a Discord channel. Mentions are not allowed to ping anyone.
+    PostDiscord { channel_id: u64, text: String },
+}
+
+impl Request {
+    /// The subsystem that answers the request.
+    pub fn target(&self) -> Subsystem {
+        match self {
+            Self::Channel(_) | Self::PostTwitch(_) | Self::ProfileImage(_) => Subsystem::Twitch,
+            Self::PostDiscord { .. } => Subsystem::Discord,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Done,
+    Text(String),
+    Error(String),
+}
+
+/// A request with the channel its answer is sent through. Copies made by the broadcast
+/// channels share the reply channel, so only the first reply is delivered.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    pub request: Request,
+    reply: Arc<Mutex<Option<oneshot::Sender<Response>>>>,
+}
+
+impl Envelope {
+    pub fn new(request: Request) -> (Self, oneshot::Receiver<Response>) {
+        let (sender, receiver) = oneshot::channel();
+
+        (
+            Self {
+                request,
+                reply: Arc::new(Mutex::new(Some(sender))),
+            },
+            receiver,
+        )
+    }
+
+    /// Whether the request is for `subsystem`.
+    pub fn is_for(&self, subsystem: Subsystem) -> bool {
+        self.request.target() == subsystem
+    }
+
+    /// Send the answer to the request. Returns false if it was already answered or the
+    /// requester stopped waiting.
+    pub fn reply(&self, response: Response) -> bool {
+        let sender = match self.reply.lock() {
+            Ok(mut v) => v.take(),
+            Err(_) => None,
+        };
+
+        match sender {
+            Some(v) => v.send(response).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Send a request through `sender`, wrapped in the message type of the sending subsystem,
+/// and wait for the answer. `Response::Error` is returned as an error.
+pub async fn send_request<M>(
+    sender: &broadcast::Sender<M>,
+    wrap: impl FnOnce(Envelope) -> M,
+    request: Request,
+) -> anyhow::Result<Response> {
+    let target = request.target();
+    let (envelope, receiver) = Envelope::new(request);
+    if sender.send(wrap(envelope)).is_err() {
+        anyhow::bail!("Unable to send request to {target:?}");
+    }
+
+    match tokio::time::timeout(REQUEST_TIMEOUT, receiver).await {
+        Ok(Ok(Response::Error(e))) => anyhow::bail!(e),
+        Ok(Ok(v)) => Ok(v),
+        Ok(Err(_)) => anyhow::bail!("{target:?} dropped the request"),
+        Err(_) => anyhow::bail!("{target:?} did not answer in time"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn answers_requests() {
+        let (sender, mut receiver) = broadcast::channel(10);
+        let answer = tokio::spawn(async move {
+            match receiver.recv().await {
+                Ok(ServerMessage::Request(envelope)) => {
+                    assert!(envelope.is_for(Subsystem::Discord));
+                    assert!(envelope.clone().reply(Response::Done));
+                    // Only the first answer counts
+                    assert!(!envelope.reply(Response::Error("Too late".into())));
+                }
+                v => panic!("Unexpected message {v:?}"),
+            }
+        });
+
+        let response = send_request(
+            &sender,
+            ServerMessage::Request,
+            Request::PostDiscord {
+                channel_id: 1,
+                text: "hi".into(),
+            },
+        )
+        .await;
+
+        answer.await.unwrap();
+        assert_eq!(response.unwrap(), Response::Done);
+    }
+
+    #[tokio::test]
+    async fn returns_errors() {
+        let (sender, mut receiver) = broadcast::channel(10);
+        tokio::spawn(async move {
+            if let Ok(TwitchMessage::Request(envelope)) = receiver.recv().await {
+                envelope.reply(Response::Error("Not live!".into()));
+            }
+        });
+
+        let response = send_request(
+            &sender,
+            TwitchMessage::Request,
+            Request::Channel(ChannelRequest::Uptime),
+        )
+        .await;
+
+        assert_eq!(response.unwrap_err().to_string(), "Not live!");
+    }
+}
@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+use crate::state::unix_now;
+
+/// The max amount of moderation actions kept in the log. Older actions are removed first.
+pub const MAX_MOD_LOG: usize = 1000;
+
+/// A moderation action taken by the bot or a moderator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModAction {
+    /// Unix timestamp in seconds of when the action was taken.
+    pub timestamp: u64,
+    /// Who took the action, e.g. `antispam`.
+    pub actor: String,
+    /// The platform-prefixed user key of the user the action was taken against.
+    pub target: String,
+    /// The display name of the target.
+    pub target_name: String,
+    /// What was done, e.g. `delete` or `remove reaction`.
+    pub action: String,
+    pub reason: String,
+    /// The antispam rule that was broken, if any.
+    #[serde(default)]
+    pub rule: Option<String>,
+    /// The content of the message that caused the action, if any.
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+impl ModAction {
+    /// Create a new `ModAction` that happened now.
+    pub fn new(
+        actor: impl Into<String>,
+        target: impl Into<String>,
+        target_name: impl Into<String>,
+        action: impl Into<String>,
+        reason: impl Into<String>,
+    ) -> Self {
+        Self {
+            timestamp: unix_now(),
+            actor: actor.into(),
+            target: target.into(),
+            target_name: target_name.into(),
+            action: action.into(),
+            reason: reason.into(),
+            rule: None,
+            content: None,
+        }
+    }
+
+    pub fn with_rule(mut self, rule: impl Into<String>) -> Self {
+        self.rule = Some(rule.into());
+        self
+    }
+
+    pub fn with_content(mut self, content: impl Into<String>) -> Self {
+        self.content = Some(content.into());
+        self
+    }
+
+    /// Format the action on a single line.
+    pub fn summary(&self) -> String {
+        let rule = match &self.rule {
+            Some(r) => format!(" [{r}]"),
+            None => String::new(),
+        };
+
+        format!(
+            "{} ago: {} by {} - {}{rule}",
+            format_age(unix_now().saturating_sub(self.timestamp)),
+            self.action,
+            self.actor,
+            self.reason
+        )
+    }
+}
+
+/// Format a duration in seconds using its largest unit, e.g. `3h`.
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_ages_with_the_largest_unit() {
+        assert_eq!(format_age(0), "0s");
+        assert_eq!(format_age(59), "59s");
+        assert_eq!(format_age(60), "1m");
+        assert_eq!(format_age(3599), "59m");
+        assert_eq!(format_age(3600), "1h");
+        assert_eq!(format_age(86399), "23h");
+        assert_eq!(format_age(86400 * 3), "3d");
+    }
+
+    #[test]
+    fn summarizes_on_one_line() {
+        let mut action = ModAction::new("antispam", "twitch:spammer", "Spammer", "delete", "spam");
+        action.timestamp = unix_now() - 120;
+        assert_eq!(action.summary(), "2m ago: delete by antispam - spam");
+
+        let action = action.with_rule("links").with_content("buy followers");
+        assert_eq!(
+            action.summary(),
+            "2m ago: delete by antispam - spam [links]"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::VecDeque, sync::OnceLock};
+
+use regex::{Captures, Regex};
+
+/// Twitch does not allow longer chat messages.
+pub const MAX_TWITCH_MESSAGE_LEN: usize = 500;
+const RATE_WINDOW_SECS: u64 = 60;
+/// Characters Discord formats messages with.
+const MARKDOWN: [char; 7] = ['\\', '*', '~', '`', '|', '[', ']'];
+
+static EMOTE: OnceLock<Regex> = OnceLock::new();
+static MENTION: OnceLock<Regex> = OnceLock::new();
+static BLOCKED_USERNAME: OnceLock<Regex> = OnceLock::new();
+
+/// Matches a custom Discord emote, capturing its name.
+fn emote() -> &'static Regex {
+    EMOTE.get_or_init(|| Regex::new(r"<a?:(\w+):\d+>").unwrap())
+}
+
+/// Matches a Discord user, role or channel mention, capturing its kind and ID.
+fn mention() -> &'static Regex {
+    MENTION.get_or_init(|| Regex::new(r"<(@[!&]?|#)(\d+)>").unwrap())
+}
+
+/// A Twitch chat message to mirror into Discord.
+#[derive(Debug, Clone)]
+pub struct ChatLine {
+    /// The display name of the chatter.
+    pub user: String,
+    pub login: String,
+    pub text: String,
+}
+
+/// Limits how many messages are relayed within any minute.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    sent: VecDeque<u64>,
+}
+
+impl RateLimiter {
+    /// Whether another message may be relayed at `now`. The message is counted if so.
+    pub fn allow(&mut self, max_per_minute: usize, now: u64) -> bool {
+        while let Some(sent_at) = self.sent.front() {
+            if sent_at + RATE_WINDOW_SECS > now {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= max_per_minute {
+            return false;
+        }
+        self.sent.push_back(now);
+
+        true
+    }
+}
+
+/// Make Twitch chat safe to post in Discord. Mentions, emotes and formatting typed in chat
+/// are shown as text.
+pub fn for_discord(text: &str) -> String {
+    let text = emote().replace_all(text, ":$1:");
+    let text = mention().replace_all(&text, "$1$2");
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    // Quotes and headings only start a message
+    if escaped.starts_with(['>', '#', '-']) {
+        escaped.insert(0, '\\');
+    }
+
+    escaped
+        .replace("@everyone", "@\u{200B}everyone")
+        .replace("@here", "@\u{200B}here")
+}
+
+/// Make a Discord message readable in Twitch chat. `name` gives the name of a mentioned
+/// user, role or channel by ID. Custom emotes are replaced by their name, so Twitch shows
+/// its own emote of the same name if there is one.
+pub fn for_twitch(text: &str, name: impl Fn(u64) -> Option<String>) -> String {
+    let text = emote().replace_all(text, " $1 ");
+    let text = mention().replace_all(&text, |c: &Captures| {
+        let kind = if &c[1] == "#" { "#" } else { "@" };
+        let name = c[2].parse().ok().and_then(&name);
+
+        format!("{kind}{}", name.as_deref().unwrap_or("unknown"))
+    });
+
+    // Chat messages are a single line
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Put a Discord message in the relay format for Twitch chat. Returns None if there is
+/// nothing to post.
+pub fn twitch_message(format: &str, user: &str, text: &str) -> Option<String> {
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    let message = format.replace("{user}", user).replace("{message}", text);
+    // Twitch runs messages starting with these as chat commands
+    let message = message.trim().trim_start_matches(['/', '.']).trim();
+
+    if message.is_empty() {
+        return None;
+    }
+
+    Some(message.chars().take(MAX_TWITCH_MESSAGE_LEN).collect())
+}
+
+/// A webhook username for a Twitch chatter. Discord rejects names containing these words.
+pub fn discord_username(user: &str) -> String {
+    BLOCKED_USERNAME
+        .get_or_init(|| Regex::new(r"(?i)discord|clyde").unwrap())
+        .replace_all(user, "*")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_messages_per_minute() {
+        let mut limiter = RateLimiter::default();
+
+        assert!(limiter.allow(2, 100));
+        assert!(limiter.allow(2, 110));
+        assert!(!limiter.allow(2, 120));
+        // The first message is a minute old
+        assert!(limiter.allow(2, 160));
+        assert!(!limiter.allow(2, 165));
+    }
+
+    #[test]
+    fn sanitizes_twitch_chat() {
+        assert_eq!(
+            for_discord("@everyone look <@1234> <:pog:5678> [free](https://x.y) *hi*"),
+            "@\u{200B}everyone look @1234 :pog: \\[free\\](https://x.y) \\*hi\\*"
+        );
+        assert_eq!(for_discord("> quoted"), "\\> quoted");
+    }
+
+    #[test]
+    fn sanitizes_discord_messages() {
+        let text = for_twitch(
+            "hey <@!12> and <@&34>\nin <#56> <a:catJAM:78>!",
+            |id| match id {
+                12 => Some("cool_user".into()),
+                56 => Some("general".into()),
+                _ => None,
+            },
+        );
+
+        assert_eq!(text, "hey @cool_user and @unknown in #general catJAM !");
+    }
+
+    #[test]
+    fn never_sends_chat_commands() {
+        assert_eq!(
+            twitch_message("{message}", "user", "/ban someone"),
+            Some("ban someone".into())
+        );
+        assert_eq!(
+            twitch_message("[Discord] {user}: {message}", "user", "hi {user}"),
+            Some("[Discord] user: hi {user}".into())
+        );
+        assert_eq!(twitch_message("{message}", "user", "  "), None);
+    }
+}
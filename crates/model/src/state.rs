@@ -0,0 +1,760 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocklist::Blocklist,
+    giveaway::Giveaway,
+    modlog::{ModAction, MAX_MOD_LOG},
+    stream::{self, StreamChange, StreamInfo, StreamNotification},
+};
+
+/// The max number of options a poll can have.
+pub const MAX_POLL_OPTIONS: usize = 10;
+/// How long a link code can be used for in seconds.
+pub const LINK_CODE_SECS: u64 = 600;
+
+/// Runtime state that is shared between all bots.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct State {
+    /// The currently running poll, if any.
+    #[serde(default)]
+    pub poll: Option<Poll>,
+    /// The current giveaway. Kept after it closes so winners can be rerolled.
+    #[serde(default)]
+    pub giveaway: Option<Giveaway>,
+
+    /// Discord user ID to Twitch login.
+    #[serde(default)]
+    pub links: HashMap<u64, String>,
+    /// One-time link codes to the Discord user that requested them.
+    #[serde(default)]
+    pub pending_links: HashMap<String, PendingLink>,
+    /// Discord users that were unlinked and may still have synced Twitch roles.
+    #[serde(default)]
+    pub unlinked: Vec<u64>,
+
+    /// Platform-prefixed user key to their points.
+    #[serde(default)]
+    pub points: HashMap<String, Wallet>,
+
+    /// Discord users that were given the timeout role and when to remove it.
+    #[serde(default)]
+    pub silences: Vec<Silence>,
+    /// Moderation actions from oldest to newest.
+    #[serde(default)]
+    pub mod_log: Vec<ModAction>,
+    /// Role menu name to the Discord channel and message IDs of its posted message.
+    #[serde(default)]
+    pub role_menu_messages: HashMap<String, (u64, u64)>,
+    /// The Discord channel and message IDs of the posted rules.
+    #[serde(default)]
+    pub rules_message: Option<(u64, u64)>,
+    /// The Twitch stream that is currently live.
+    #[serde(default)]
+    pub stream_session: Option<StreamInfo>,
+    /// Twitch login to the live stream of each watched channel.
+    #[serde(default)]
+    pub watched_sessions: HashMap<String, StreamInfo>,
+    /// Twitch login to the go-live notification of its current or most recent stream.
+    #[serde(default)]
+    pub stream_notifications: HashMap<String, StreamNotification>,
+    /// Blocklist scope to its blocklist. Scopes are `discord:<guild id>` or
+    /// `twitch:<channel name>`.
+    #[serde(default)]
+    pub blocklists: HashMap<String, Blocklist>,
+    /// Queue name to the users waiting in it, oldest first. Names are lowercase.
+    #[serde(default)]
+    pub queues: HashMap<String, VecDeque<QueueEntry>>,
+
+    /// Platform-prefixed user key to Twitch chatters that recently sent a message.
+    #[serde(skip)]
+    pub active_chatters: HashMap<String, Chatter>,
+    /// Alert key to the Unix timestamp in seconds of when it was last posted.
+    #[serde(skip)]
+    pub recent_alerts: HashMap<String, u64>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the state from the given `path`. Returns a new state if the file does not exist.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(v) => {
+                let mut state: Self = serde_json::from_str(&v)?;
+                state.blocklists.values_mut().for_each(Blocklist::compile);
+
+                Ok(state)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Serialize the state so it can be written to disk.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).map_err(anyhow::Error::from)
+    }
+
+    /// Get the Discord user ID linked to the given Twitch login.
+    pub fn linked_discord(&self, twitch_login: &str) -> Option<u64> {
+        self.links
+            .iter()
+            .find(|(_, v)| v.eq_ignore_ascii_case(twitch_login))
+            .map(|(k, _)| *k)
+    }
+
+    /// Resolve a platform-prefixed user key to the key of the linked Discord account,
+    /// so the same person is only counted once across platforms.
+    pub fn canonical_user(&self, user: &str) -> String {
+        match user
+            .strip_prefix("twitch:")
+            .and_then(|login| self.linked_discord(login))
+        {
+            Some(id) => format!("discord:{id}"),
+            None => user.to_string(),
+        }
+    }
+
+    /// Create a one-time code that links the given Discord user once it is sent
+    /// in Twitch chat. Replaces any previous code for the user.
+    pub fn create_link_code(&mut self, discord_id: u64) -> String {
+        self.pending_links
+            .retain(|_, v| v.discord_id != discord_id && !v.is_expired());
+
+        let code = loop {
+            let code = format!("{:06}", rand::random::<u32>() % 1_000_000);
+            if !self.pending_links.contains_key(&code) {
+                break code;
+            }
+        };
+        self.pending_links.insert(
+            code.clone(),
+            PendingLink {
+                discord_id,
+                expires_at: unix_now() + LINK_CODE_SECS,
+            },
+        );
+
+        code
+    }
+
+    /// Link the Discord user that requested the `code` to the Twitch login.
+    /// Returns the linked Discord user ID.
+    pub fn complete_link(&mut self, code: &str, twitch_login: &str) -> Option<u64> {
+        let pending = self.pending_links.remove(code)?;
+        if pending.is_expired() {
+            return None;
+        }
+
+        let twitch_login = twitch_login.to_lowercase();
+        let previous = self
+            .links
+            .iter()
+            .filter(|(k, v)| **k != pending.discord_id && **v == twitch_login)
+            .map(|(k, _)| *k)
+            .collect::<Vec<u64>>();
+        for discord_id in previous {
+            self.unlink(discord_id);
+        }
+
+        // Points are shared between linked accounts
+        if let Some(wallet) = self.points.remove(&format!("twitch:{twitch_login}")) {
+            self.add_points(
+                &format!("discord:{}", pending.discord_id),
+                &wallet.name,
+                wallet.points,
+            );
+        }
+
+        self.links.insert(pending.discord_id, twitch_login);
+
+        Some(pending.discord_id)
+    }
+
+    /// Remove the link of the given Discord user. Returns false if they were not linked.
+    pub fn unlink(&mut self, discord_id: u64) -> bool {
+        if self.links.remove(&discord_id).is_none() {
+            return false;
+        }
+        self.unlinked.push(discord_id);
+
+        true
+    }
+
+    /// Remove and return the Discord users that were unlinked since the last call.
+    pub fn take_unlinked(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.unlinked)
+    }
+
+    /// Get the points for the given user.
+    pub fn points(&self, user: &str) -> i64 {
+        self.points
+            .get(&self.canonical_user(user))
+            .map(|w| w.points)
+            .unwrap_or_default()
+    }
+
+    /// Add points to the given user. Use a negative `amount` to remove points. `name` is
+    /// only used if the user has no points yet. Returns the new amount of points.
+    pub fn add_points(&mut self, user: &str, name: &str, amount: i64) -> i64 {
+        let wallet = self
+            .points
+            .entry(self.canonical_user(user))
+            .or_insert_with(|| Wallet {
+                name: name.to_string(),
+                points: 0,
+            });
+        wallet.points = wallet.points.saturating_add(amount);
+
+        wallet.points
+    }
+
+    /// Set the points for the given user. `name` is only used if the user has no points yet.
+    pub fn set_points(&mut self, user: &str, name: &str, amount: i64) {
+        self.points
+            .entry(self.canonical_user(user))
+            .or_insert_with(|| Wallet {
+                name: name.to_string(),
+                points: 0,
+            })
+            .points = amount;
+    }
+
+    /// Remove points from the given user. Returns false without removing anything if
+    /// the user does not have enough points.
+    pub fn spend_points(&mut self, user: &str, amount: i64) -> bool {
+        match self.points.get_mut(&self.canonical_user(user)) {
+            Some(w) if w.points >= amount => {
+                w.points -= amount;
+                true
+            }
+            _ => amount <= 0,
+        }
+    }
+
+    /// The `count` users with the most points, from most to least.
+    pub fn leaderboard(&self, count: usize) -> Vec<&Wallet> {
+        let mut wallets = self.points.values().collect::<Vec<&Wallet>>();
+        wallets.sort_by_key(|w| Reverse(w.points));
+        wallets.truncate(count);
+
+        wallets
+    }
+
+    /// Record that a Twitch user sent a chat message, giving them `bonus` points if the
+    /// stream is live and they have not received a bonus in the last `cooldown` seconds.
+    pub fn record_chatter(&mut self, user: &str, name: &str, bonus: i64, cooldown: u64) {
+        let now = unix_now();
+        let user = self.canonical_user(user);
+        let is_live = self.is_live();
+
+        let chatter = self
+            .active_chatters
+            .entry(user.clone())
+            .or_insert_with(|| Chatter {
+                name: name.to_string(),
+                last_message: now,
+                last_bonus: 0,
+            });
+        chatter.last_message = now;
+
+        if is_live && now.saturating_sub(chatter.last_bonus) >= cooldown {
+            chatter.last_bonus = now;
+            self.add_points(&user, name, bonus);
+        }
+    }
+
+    /// Whether the Twitch stream is currently live.
+    pub fn is_live(&self) -> bool {
+        self.stream_session.is_some()
+    }
+
+    /// Update the stream session with the stream that is `live` right now, if any.
+    /// Returns what changed.
+    pub fn update_stream(&mut self, live: Option<StreamInfo>) -> Vec<StreamChange> {
+        stream::update_session(&mut self.stream_session, live)
+    }
+
+    /// Like `update_stream`, but for the watched channel `login`.
+    pub fn update_watched_stream(
+        &mut self,
+        login: &str,
+        live: Option<StreamInfo>,
+    ) -> Vec<StreamChange> {
+        let mut session = self.watched_sessions.remove(login);
+        let changes = stream::update_session(&mut session, live);
+        if let Some(v) = session {
+            self.watched_sessions.insert(login.to_string(), v);
+        }
+
+        changes
+    }
+
+    /// Give `amount` points to every viewer, given as Twitch login and display name, and
+    /// every chatter that sent a message in the last `active_secs` seconds. Chatters that
+    /// have been quiet for longer are forgotten. Everyone is only paid once.
+    pub fn award_watch_points(
+        &mut self,
+        viewers: &[(String, String)],
+        amount: i64,
+        active_secs: u64,
+    ) {
+        let now = unix_now();
+        self.active_chatters
+            .retain(|_, c| now.saturating_sub(c.last_message) <= active_secs);
+
+        if !self.is_live() {
+            return;
+        }
+
+        let watching = viewers
+            .iter()
+            .map(|(login, name)| (format!("twitch:{}", login.to_lowercase()), name.clone()))
+            .chain(
+                self.active_chatters
+                    .iter()
+                    .map(|(k, c)| (k.clone(), c.name.clone())),
+            )
+            .collect::<Vec<(String, String)>>();
+
+        let mut paid = HashSet::new();
+        for (user, name) in watching {
+            if paid.insert(self.canonical_user(&user)) {
+                self.add_points(&user, &name, amount);
+            }
+        }
+    }
+
+    /// Remember to remove the timeout `role_id` from the Discord user after `secs` seconds.
+    /// Replaces any previous silence for the user.
+    pub fn add_silence(&mut self, user_id: u64, role_id: u64, secs: u64) {
+        self.silences.retain(|s| s.user_id != user_id);
+        self.silences.push(Silence {
+            user_id,
+            role_id,
+            until: unix_now() + secs,
+        });
+    }
+
+    /// Remove and return the silences that have run out.
+    pub fn take_expired_silences(&mut self) -> Vec<Silence> {
+        let now = unix_now();
+        let (expired, silences) = self.silences.drain(..).partition(|s| s.until <= now);
+        self.silences = silences;
+
+        expired
+    }
+
+    /// Add an action to the moderation log, removing the oldest actions if the log is full.
+    pub fn record_mod_action(&mut self, action: ModAction) {
+        self.mod_log.push(action);
+        if self.mod_log.len() > MAX_MOD_LOG {
+            let excess = self.mod_log.len() - MAX_MOD_LOG;
+            self.mod_log.drain(..excess);
+        }
+    }
+
+    /// The `count` most recent moderation actions against the given user, newest first.
+    pub fn mod_actions_for(&self, user: &str, count: usize) -> Vec<&ModAction> {
+        let user = self.canonical_user(user);
+
+        self.mod_log
+            .iter()
+            .rev()
+            .filter(|a| self.canonical_user(&a.target) == user)
+            .take(count)
+            .collect()
+    }
+
+    /// Record that an alert is being posted. Returns false if it was already posted
+    /// within the last `secs`.
+    pub fn record_alert(&mut self, key: &str, secs: u64) -> bool {
+        let now = unix_now();
+        self.recent_alerts.retain(|_, t| *t + secs > now);
+        if self.recent_alerts.contains_key(key) {
+            return false;
+        }
+
+        self.recent_alerts.insert(key.to_string(), now);
+        true
+    }
+
+    /// Add an entry to the end of a queue. Returns its position, starting at 1.
+    pub fn enqueue(&mut self, queue: &str, entry: QueueEntry) -> usize {
+        let queue = self.queues.entry(queue.to_lowercase()).or_default();
+        queue.push_back(entry);
+
+        queue.len()
+    }
+
+    /// Remove and return the next entry in a queue.
+    pub fn dequeue(&mut self, queue: &str) -> Option<QueueEntry> {
+        self.queues.get_mut(&queue.to_lowercase())?.pop_front()
+    }
+
+    /// Get the running poll, ignoring polls that have already finished.
+    pub fn active_poll(&mut self) -> Option<&mut Poll> {
+        self.poll.as_mut().filter(|p| !p.is_finished())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Wallet {
+    /// The display name of the user when they first got points.
+    pub name: String,
+    pub points: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    /// The display name of the user.
+    pub name: String,
+    /// Text entered by the user when they joined, if any.
+    #[serde(default)]
+    pub input: String,
+}
+
+impl QueueEntry {
+    /// Format the entry on a single line.
+    pub fn summary(&self) -> String {
+        if self.input.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.input)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chatter {
+    pub name: String,
+    /// Unix timestamp in seconds of their last message.
+    pub last_message: u64,
+    /// Unix timestamp in seconds of the last time they received a chat bonus.
+    pub last_bonus: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Silence {
+    pub user_id: u64,
+    pub role_id: u64,
+    /// Unix timestamp in seconds of when the role should be removed.
+    pub until: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingLink {
+    pub discord_id: u64,
+    /// Unix timestamp in seconds of when the code stops working.
+    pub expires_at: u64,
+}
+
+impl PendingLink {
+    pub fn is_expired(&self) -> bool {
+        unix_now() >= self.expires_at
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Poll {
+    pub question: String,
+    pub options: Vec<String>,
+    /// Unix timestamp in seconds of when the poll closes.
+    pub ends_at: u64,
+
+    /// The Discord channel and message IDs of the poll message, if it has been posted.
+    #[serde(default)]
+    pub discord_message: Option<(u64, u64)>,
+    /// Voter to the index of the option they voted for. Voters are prefixed with
+    /// their platform, e.g. `twitch:someone`.
+    #[serde(default)]
+    pub votes: HashMap<String, usize>,
+}
+
+impl Poll {
+    /// Create a new `Poll` that closes after `duration` seconds.
+    pub fn new(question: String, options: Vec<String>, duration: u64) -> Self {
+        Self {
+            question,
+            options,
+            ends_at: unix_now() + duration,
+            discord_message: None,
+            votes: HashMap::new(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        unix_now() >= self.ends_at
+    }
+
+    /// Record a vote for the given 1-based `option`. Returns false if the option does not exist.
+    pub fn vote(&mut self, voter: String, option: usize) -> bool {
+        if option == 0 || option > self.options.len() {
+            return false;
+        }
+
+        self.votes.insert(voter, option - 1);
+
+        true
+    }
+
+    /// Count the recorded votes plus any `extra` votes per option, e.g. Discord reactions.
+    pub fn tally(&self, extra: &[u64]) -> Vec<u64> {
+        let mut counts = vec![0; self.options.len()];
+        for option in self.votes.values() {
+            if let Some(c) = counts.get_mut(*option) {
+                *c += 1;
+            }
+        }
+        for (c, e) in counts.iter_mut().zip(extra) {
+            *c += e;
+        }
+
+        counts
+    }
+
+    /// Format the poll options as a numbered list, one option per line.
+    pub fn options_text(&self) -> String {
+        self.options
+            .iter()
+            .enumerate()
+            .map(|(i, o)| format!("{}. {o}", i + 1))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Format the results of the poll on a single line.
+    pub fn results_text(&self, tally: &[u64]) -> String {
+        let results = self
+            .options
+            .iter()
+            .zip(tally)
+            .map(|(o, c)| format!("{o}: {c}"))
+            .collect::<Vec<String>>()
+            .join(" | ");
+
+        let max = tally.iter().max().copied().unwrap_or_default();
+        let winners = self
+            .options
+            .iter()
+            .zip(tally)
+            .filter(|(_, c)| max > 0 && **c == max)
+            .map(|(o, _)| o.as_str())
+            .collect::<Vec<&str>>();
+
+        let winner = match winners.len() {
+            0 => "No votes".to_string(),
+            1 => format!("Winner: {}", winners[0]),
+            _ => format!("Tie: {}", winners.join(", ")),
+        };
+
+        format!("Poll closed: {} | {results} | {winner}", self.question)
+    }
+}
+
+/// The current Unix timestamp in seconds.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll() -> Poll {
+        Poll::new(
+            "Best game?".into(),
+            vec!["A".into(), "B".into(), "C".into()],
+            60,
+        )
+    }
+
+    #[test]
+    fn tallies_votes_with_extra_counts() {
+        let mut poll = poll();
+        assert!(poll.vote("one".into(), 1));
+        assert!(poll.vote("two".into(), 3));
+        assert!(poll.vote("three".into(), 3));
+
+        assert_eq!(poll.tally(&[]), vec![1, 0, 2]);
+        assert_eq!(poll.tally(&[2, 1, 0]), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn a_new_vote_replaces_the_old_one() {
+        let mut poll = poll();
+        assert!(poll.vote("one".into(), 1));
+        assert!(poll.vote("one".into(), 2));
+
+        assert_eq!(poll.tally(&[]), vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn unlinking_queues_role_removal() {
+        let mut state = State::new();
+        let code = state.create_link_code(1);
+        state.complete_link(&code, "Someone");
+        assert!(state.take_unlinked().is_empty());
+
+        // Linking the login to another Discord user unlinks the first one
+        let code = state.create_link_code(2);
+        state.complete_link(&code, "someone");
+        assert_eq!(state.take_unlinked(), vec![1]);
+
+        assert!(state.unlink(2));
+        assert!(!state.unlink(2));
+        assert_eq!(state.take_unlinked(), vec![2]);
+        assert!(state.links.is_empty());
+    }
+
+    #[test]
+    fn pays_viewers_and_chatters_once() {
+        let mut state = State::new();
+        state.stream_session = Some(StreamInfo::default());
+        state.record_chatter("twitch:chatty", "Chatty", 0, 0);
+        state.record_chatter("twitch:lurker", "Lurker", 0, 0);
+
+        let viewers = vec![
+            ("lurker".to_string(), "Lurker".to_string()),
+            ("viewer".to_string(), "Viewer".to_string()),
+        ];
+        state.award_watch_points(&viewers, 5, 60);
+
+        assert_eq!(state.points("twitch:chatty"), 5);
+        assert_eq!(state.points("twitch:lurker"), 5);
+        assert_eq!(state.points("twitch:viewer"), 5);
+    }
+
+    #[test]
+    fn keeps_the_first_wallet_name() {
+        let mut state = State::new();
+        state.add_points("twitch:someone", "Someone", 5);
+        state.add_points("twitch:someone", "@someone", 5);
+        state.set_points("twitch:someone", "someone", 20);
+        state.set_points("twitch:other", "Other", 3);
+
+        let names = state
+            .leaderboard(2)
+            .iter()
+            .map(|w| (w.name.as_str(), w.points))
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![("Someone", 20), ("Other", 3)]);
+    }
+
+    #[test]
+    fn keeps_the_newest_mod_actions() {
+        let mut state = State::new();
+        for i in 0..MAX_MOD_LOG + 5 {
+            state.record_mod_action(ModAction::new("mod", "twitch:a", "A", "ban", i.to_string()));
+        }
+
+        assert_eq!(state.mod_log.len(), MAX_MOD_LOG);
+        assert_eq!(state.mod_log[0].reason, "5");
+    }
+
+    #[test]
+    fn finds_mod_actions_for_linked_accounts() {
+        let mut state = State::new();
+        state.record_mod_action(ModAction::new(
+            "mod",
+            "twitch:someone",
+            "Someone",
+            "ban",
+            "1",
+        ));
+        state.record_mod_action(ModAction::new("mod", "twitch:other", "Other", "ban", "2"));
+        state.record_mod_action(ModAction::new("mod", "discord:1", "Someone", "kick", "3"));
+        state.record_mod_action(ModAction::new(
+            "mod",
+            "twitch:someone",
+            "Someone",
+            "ban",
+            "4",
+        ));
+
+        let reasons = |state: &State, user: &str, count: usize| {
+            state
+                .mod_actions_for(user, count)
+                .iter()
+                .map(|a| a.reason.clone())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(reasons(&state, "twitch:someone", 5), vec!["4", "1"]);
+
+        let code = state.create_link_code(1);
+        state.complete_link(&code, "someone");
+        assert_eq!(reasons(&state, "twitch:someone", 5), vec!["4", "3", "1"]);
+        assert_eq!(reasons(&state, "discord:1", 2), vec!["4", "3"]);
+    }
+
+    #[test]
+    fn tracks_watched_streams() {
+        let mut state = State::new();
+        let stream = StreamInfo {
+            id: "1".into(),
+            login: "friend".into(),
+            title: "Hello".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            state.update_watched_stream("friend", Some(stream.clone())),
+            vec![StreamChange::Started(stream.clone())]
+        );
+        assert!(state
+            .update_watched_stream("friend", Some(stream.clone()))
+            .is_empty());
+        // The bot's own stream is tracked separately
+        assert!(state.update_stream(None).is_empty());
+
+        let renamed = StreamInfo {
+            title: "Bye".into(),
+            ..stream.clone()
+        };
+        assert_eq!(
+            state.update_watched_stream("friend", Some(renamed.clone())),
+            vec![StreamChange::Updated(renamed.clone())]
+        );
+        assert_eq!(state.watched_sessions["friend"], renamed);
+    }
+
+    #[test]
+    fn unwatching_ends_the_session() {
+        let mut state = State::new();
+        let stream = StreamInfo {
+            id: "1".into(),
+            login: "friend".into(),
+            ..Default::default()
+        };
+        state.update_watched_stream("friend", Some(stream.clone()));
+
+        // Channels that are no longer watched are updated as offline
+        assert_eq!(
+            state.update_watched_stream("friend", None),
+            vec![StreamChange::Ended(stream)]
+        );
+        assert!(state.watched_sessions.is_empty());
+        assert!(state.update_watched_stream("friend", None).is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let mut poll = poll();
+        assert!(!poll.vote("one".into(), 0));
+        assert!(!poll.vote("one".into(), 4));
+
+        assert_eq!(poll.tally(&[]), vec![0, 0, 0]);
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+
+/// A live Twitch stream.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamInfo {
+    /// The Twitch stream ID, which identifies the stream session.
+    pub id: String,
+    /// The Twitch user ID of the channel.
+    pub user_id: String,
+    /// The Twitch login of the channel.
+    pub login: String,
+    /// The display name of the channel.
+    pub channel: String,
+    pub title: String,
+    pub url: String,
+    /// The game or category being streamed.
+    pub game: String,
+    pub viewer_count: usize,
+    /// The thumbnail URL with its size filled in.
+    pub thumbnail_url: String,
+    /// When the stream started in RFC 3339 format.
+    pub started_at: String,
+    pub tags: Vec<String>,
+}
+
+/// A change in the stream session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChange {
+    Started(StreamInfo),
+    /// The title or game changed.
+    Updated(StreamInfo),
+    Ended(StreamInfo),
+}
+
+/// Move the `session` to the stream that is `live` right now, if any. Returns the changes
+/// in the order they happened. A different stream ID means the previous stream ended and
+/// a new one started in between checks.
+pub fn update_session(
+    session: &mut Option<StreamInfo>,
+    live: Option<StreamInfo>,
+) -> Vec<StreamChange> {
+    let previous = session.take();
+    *session = live.clone();
+
+    match (previous, live) {
+        (None, None) => vec![],
+        (None, Some(new)) => vec![StreamChange::Started(new)],
+        (Some(old), None) => vec![StreamChange::Ended(old)],
+        (Some(old), Some(new)) if old.id != new.id => {
+            vec![StreamChange::Ended(old), StreamChange::Started(new)]
+        }
+        (Some(old), Some(new)) if old.title != new.title || old.game != new.game => {
+            vec![StreamChange::Updated(new)]
+        }
+        (Some(_), Some(_)) => vec![],
+    }
+}
+
+/// A posted go-live notification, kept so it can be edited when the stream ends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamNotification {
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub stream: StreamInfo,
+}
+
+/// Format a duration in seconds as hours and minutes, e.g. `2h 5m`.
+pub fn format_duration(secs: u64) -> String {
+    let hours = secs / 3600;
+    let minutes = secs % 3600 / 60;
+
+    match hours {
+        0 => format!("{minutes}m"),
+        _ => format!("{hours}h {minutes}m"),
+    }
+}
+
+/// Format a duration in days as years, months and days, e.g. `1 year, 2 months`.
+pub fn format_days(days: u64) -> String {
+    let parts = [
+        (days / 365, "year"),
+        (days % 365 / 30, "month"),
+        (days % 365 % 30, "day"),
+    ];
+    let text = parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{n} {unit}{}", if *n == 1 { "" } else { "s" }))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    match text.is_empty() {
+        true => "less than a day".into(),
+        false => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(id: &str, title: &str) -> StreamInfo {
+        StreamInfo {
+            id: id.into(),
+            title: title.into(),
+            game: "Just Chatting".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn starts_and_ends() {
+        let mut session = None;
+
+        assert_eq!(
+            update_session(&mut session, Some(stream("1", "Hi"))),
+            vec![StreamChange::Started(stream("1", "Hi"))]
+        );
+        assert_eq!(
+            update_session(&mut session, None),
+            vec![StreamChange::Ended(stream("1", "Hi"))]
+        );
+        assert!(session.is_none());
+        assert!(update_session(&mut session, None).is_empty());
+    }
+
+    #[test]
+    fn same_stream_is_not_restarted() {
+        let mut session = Some(stream("1", "Hi"));
+        let mut live = stream("1", "Hi");
+        live.viewer_count = 10;
+
+        assert!(update_session(&mut session, Some(live)).is_empty());
+        assert_eq!(session.map(|s| s.viewer_count), Some(10));
+    }
+
+    #[test]
+    fn title_change_updates() {
+        let mut session = Some(stream("1", "Hi"));
+
+        assert_eq!(
+            update_session(&mut session, Some(stream("1", "Bye"))),
+            vec![StreamChange::Updated(stream("1", "Bye"))]
+        );
+    }
+
+    #[test]
+    fn formats_days() {
+        assert_eq!(format_days(0), "less than a day");
+        assert_eq!(format_days(30), "1 month");
+        assert_eq!(format_days(400), "1 year, 1 month, 5 days");
+    }
+
+    #[test]
+    fn new_stream_id_ends_previous() {
+        let mut session = Some(stream("1", "Hi"));
+
+        assert_eq!(
+            update_session(&mut session, Some(stream("2", "Hi"))),
+            vec![
+                StreamChange::Ended(stream("1", "Hi")),
+                StreamChange::Started(stream("2", "Hi"))
+            ]
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use rhai::{plugin::*, Dynamic, Engine, Locked, Module, Shared};
+use rhai::{plugin::*, Dynamic, Engine, Locked, Module, Scope, Shared};
 
 /// The max number of operations that a Rhai script can do before it is
 /// forcible halted.
@@ -15,6 +15,24 @@ mod bot_prelude {
 }
 
 pub fn execute_timed(text: impl AsRef<str>, max_time: u64) -> anyhow::Result<String> {
+    execute_with_scope(text, max_time, &mut Scope::new())
+}
+
+/// Run a script with the given string variables in scope.
+pub fn execute_with_vars(text: impl AsRef<str>, vars: &[(&str, String)]) -> anyhow::Result<String> {
+    let mut scope = Scope::new();
+    for (name, value) in vars {
+        scope.push(*name, value.clone());
+    }
+
+    execute_with_scope(text, MAX_SCRIPTING_OPS, &mut scope)
+}
+
+fn execute_with_scope(
+    text: impl AsRef<str>,
+    max_time: u64,
+    scope: &mut Scope,
+) -> anyhow::Result<String> {
     let mut engine = Engine::new();
 
     let out = Shared::new(Locked::new(Vec::new()));
@@ -41,7 +59,7 @@ pub fn execute_timed(text: impl AsRef<str>, max_time: u64) -> anyhow::Result<Str
     engine.register_global_module(bot_prelude.into());
 
     let script_ret = engine
-        .eval::<Dynamic>(text.as_ref())
+        .eval_with_scope::<Dynamic>(scope, text.as_ref())
         .map_err(anyhow::Error::from)
         .map(|x| x.to_string())?;
     let print_ret = if let Ok(v) = out.read() {
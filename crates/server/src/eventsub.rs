@@ -0,0 +1,296 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, error, warn};
+use model::messages::ServerMessage;
+use sha2::Sha256;
+
+use crate::AppState;
+
+const MESSAGE_ID_HEADER: &str = "Twitch-Eventsub-Message-Id";
+const TIMESTAMP_HEADER: &str = "Twitch-Eventsub-Message-Timestamp";
+const SIGNATURE_HEADER: &str = "Twitch-Eventsub-Message-Signature";
+const TYPE_HEADER: &str = "Twitch-Eventsub-Message-Type";
+/// Older messages are rejected so they cannot be replayed, as are messages from further in
+/// the future.
+const MAX_MESSAGE_AGE_SECS: i64 = 600;
+
+/// An EventSub message with a valid signature.
+#[derive(Debug, PartialEq, Eq)]
+enum EventSubMessage {
+    /// Twitch is checking that the callback belongs to us and expects the challenge back.
+    Verification {
+        challenge: String,
+    },
+    Notification {
+        message_id: String,
+    },
+    Revocation,
+    Unknown(String),
+}
+
+pub(crate) async fn handle_eventsub(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    let secret = match state.eventsub_secret.as_deref() {
+        Some(v) if !v.is_empty() => v,
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match verify(secret, &headers, &body, Utc::now()) {
+        Ok(EventSubMessage::Verification { challenge }) => {
+            debug!("Verified EventSub callback");
+            (StatusCode::OK, challenge).into_response()
+        }
+        Ok(EventSubMessage::Notification { message_id }) => {
+            if let Err(e) = state
+                .sender
+                .send(ServerMessage::EventSub { message_id, body })
+            {
+                error!("{e}");
+            }
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(EventSubMessage::Revocation) => {
+            warn!("EventSub subscription was revoked: {body}");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(EventSubMessage::Unknown(kind)) => {
+            debug!("Ignoring EventSub message of type {kind}");
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Check the signature and age of an EventSub message and work out what it is.
+fn verify(
+    secret: &str,
+    headers: &HeaderMap,
+    body: &str,
+    now: DateTime<Utc>,
+) -> Result<EventSubMessage, StatusCode> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    let message_id = header(MESSAGE_ID_HEADER);
+    let timestamp = header(TIMESTAMP_HEADER);
+
+    if !is_signed(
+        secret,
+        message_id,
+        timestamp,
+        body,
+        header(SIGNATURE_HEADER),
+    ) {
+        error!("Invalid EventSub signature for message {message_id}");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(v) if (now - v.with_timezone(&Utc)).num_seconds().abs() <= MAX_MESSAGE_AGE_SECS => {}
+        _ => {
+            error!("Stale EventSub message {message_id} sent at {timestamp}");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    match header(TYPE_HEADER) {
+        "webhook_callback_verification" => serde_json::from_str::<serde_json::Value>(body)
+            .ok()
+            .and_then(|v| v.get("challenge")?.as_str().map(|c| c.to_string()))
+            .map(|challenge| EventSubMessage::Verification { challenge })
+            .ok_or(StatusCode::BAD_REQUEST),
+        "notification" => Ok(EventSubMessage::Notification {
+            message_id: message_id.to_string(),
+        }),
+        "revocation" => Ok(EventSubMessage::Revocation),
+        kind => Ok(EventSubMessage::Unknown(kind.to_string())),
+    }
+}
+
+/// Whether the message was signed with `secret` the way Twitch signs EventSub messages.
+fn is_signed(secret: &str, message_id: &str, timestamp: &str, body: &str, signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|v| hex::decode(v).ok())
+    {
+        Some(v) => v,
+        None => return false,
+    };
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(v) => v,
+        Err(_) => return false,
+    };
+    mac.update(message_id.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(body.as_bytes());
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Duration;
+    use model::{config::Config, state::State as BotState};
+    use tokio::sync::{broadcast, RwLock};
+
+    use super::*;
+
+    const SECRET: &str = "s3cre7";
+
+    /// Sends messages signed the way Twitch signs them, so the webhook can be tested
+    /// without Twitch.
+    struct FakeEventSub {
+        secret: String,
+        sent_at: DateTime<Utc>,
+    }
+
+    impl FakeEventSub {
+        fn new(secret: &str) -> Self {
+            Self {
+                secret: secret.to_string(),
+                sent_at: Utc::now(),
+            }
+        }
+
+        fn headers(&self, kind: &str, message_id: &str, body: &str) -> HeaderMap {
+            let timestamp = self.sent_at.to_rfc3339();
+            let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes()).unwrap();
+            mac.update(message_id.as_bytes());
+            mac.update(timestamp.as_bytes());
+            mac.update(body.as_bytes());
+            let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+            let mut headers = HeaderMap::new();
+            headers.insert(MESSAGE_ID_HEADER, message_id.parse().unwrap());
+            headers.insert(TIMESTAMP_HEADER, timestamp.parse().unwrap());
+            headers.insert(SIGNATURE_HEADER, signature.parse().unwrap());
+            headers.insert(TYPE_HEADER, kind.parse().unwrap());
+
+            headers
+        }
+
+        fn follow(&self, message_id: &str) -> (HeaderMap, String) {
+            let body = r#"{"subscription":{"id":"f1c2a387-161a-49f9-a165-0f21d7a4e1c4","type":"channel.follow","version":"2","status":"enabled","cost":0,"condition":{"broadcaster_user_id":"1337","moderator_user_id":"1337"},"transport":{"method":"webhook","callback":"https://example.com/eventsub"},"created_at":"2019-11-16T10:11:12.634234626Z"},"event":{"user_id":"1234","user_login":"cool_user","user_name":"Cool_User","broadcaster_user_id":"1337","broadcaster_user_login":"cooler_user","broadcaster_user_name":"Cooler_User","followed_at":"2020-07-15T18:16:11.17106713Z"}}"#;
+
+            (self.headers("notification", message_id, body), body.into())
+        }
+
+        fn challenge(&self, challenge: &str) -> (HeaderMap, String) {
+            let body = format!(r#"{{"challenge":"{challenge}","subscription":{{}}}}"#);
+
+            (
+                self.headers("webhook_callback_verification", "challenge", &body),
+                body,
+            )
+        }
+
+        async fn send(&self, state: &AppState, (headers, body): (HeaderMap, String)) -> StatusCode {
+            handle_eventsub(State(state.clone()), headers, body)
+                .await
+                .status()
+        }
+    }
+
+    fn app_state() -> (AppState, broadcast::Receiver<ServerMessage>) {
+        let (_, receiver) = broadcast::channel(10);
+        let (sender, server_receiver) = broadcast::channel(10);
+        let mut state = AppState::new(
+            Arc::new(RwLock::new(Config::new())),
+            Arc::new(RwLock::new(BotState::new())),
+            receiver,
+            sender,
+        );
+        state.eventsub_secret = Some(SECRET.into());
+
+        (state, server_receiver)
+    }
+
+    #[tokio::test]
+    async fn forwards_signed_notifications() {
+        let (state, mut receiver) = app_state();
+        let fake = FakeEventSub::new(SECRET);
+
+        let status = fake.send(&state, fake.follow("message-1")).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        match receiver.try_recv() {
+            Ok(ServerMessage::EventSub { message_id, body }) => {
+                assert_eq!(message_id, "message-1");
+                assert!(body.contains("channel.follow"));
+            }
+            v => panic!("Unexpected message {v:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signatures() {
+        let (state, mut receiver) = app_state();
+
+        let status = FakeEventSub::new("wrong")
+            .send(&state, FakeEventSub::new("wrong").follow("message-1"))
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_bodies() {
+        let (state, _receiver) = app_state();
+        let fake = FakeEventSub::new(SECRET);
+        let (headers, body) = fake.follow("message-1");
+
+        let status = fake
+            .send(&state, (headers, body.replace("Cool_User", "Evil_User")))
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_old_messages() {
+        let (state, _receiver) = app_state();
+        let mut fake = FakeEventSub::new(SECRET);
+        fake.sent_at = Utc::now() - Duration::seconds(MAX_MESSAGE_AGE_SECS + 60);
+
+        let status = fake.send(&state, fake.follow("message-1")).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn rejects_future_messages() {
+        let (state, _receiver) = app_state();
+        let mut fake = FakeEventSub::new(SECRET);
+        fake.sent_at = Utc::now() + Duration::seconds(MAX_MESSAGE_AGE_SECS + 60);
+
+        let status = fake.send(&state, fake.follow("message-1")).await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn answers_challenges() {
+        let fake = FakeEventSub::new(SECRET);
+        let (headers, body) = fake.challenge("pogchamp-kappa-360noscope-vohiyo");
+
+        assert_eq!(
+            verify(SECRET, &headers, &body, Utc::now()),
+            Ok(EventSubMessage::Verification {
+                challenge: "pogchamp-kappa-360noscope-vohiyo".into()
+            })
+        );
+    }
+}
@@ -1,16 +1,20 @@
+mod eventsub;
+
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use axum::{
     body::Body,
     extract::{ConnectInfo, Path, State},
-    http::{HeaderMap, Request},
+    http::{HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
     routing::post,
     Router,
 };
 use log::error;
 use model::{
     config::Config,
-    messages::{CentralMessage, ServerMessage},
+    messages::{self, CentralMessage, ServerMessage},
+    state,
 };
 use tokio::sync::{
     broadcast::{Receiver, Sender},
@@ -19,6 +23,7 @@ use tokio::sync::{
 
 const KEY_HEADER: &str = "A-Cool-Key";
 const API_KEY: &str = env!("SYWB_SERVER_API_KEY");
+const EVENTSUB_SECRET: Option<&str> = option_env!("TWITCH_EVENTSUB_SECRET");
 
 enum Bail {
     No,
@@ -29,10 +34,15 @@ enum Bail {
 #[derive(Debug)]
 struct AppState {
     config: Arc<RwLock<Config>>,
+    bot_state: Arc<RwLock<state::State>>,
 
     receiver: Receiver<CentralMessage>,
     sender: Sender<ServerMessage>,
 
+    /// The secret EventSub webhook notifications are signed with. The webhook is
+    /// disabled if there is none.
+    eventsub_secret: Option<String>,
+
     confused_actors: Vec<SocketAddr>,
     bad_actors: Vec<SocketAddr>,
     repeat_offenders: Vec<SocketAddr>,
@@ -42,8 +52,10 @@ impl Clone for AppState {
     fn clone(&self) -> Self {
         Self {
             config: self.config.clone(),
+            bot_state: self.bot_state.clone(),
             receiver: self.receiver.resubscribe(),
             sender: self.sender.clone(),
+            eventsub_secret: self.eventsub_secret.clone(),
             confused_actors: self.confused_actors.clone(),
             bad_actors: self.bad_actors.clone(),
             repeat_offenders: self.repeat_offenders.clone(),
@@ -54,15 +66,19 @@ impl Clone for AppState {
 impl AppState {
     fn new(
         config: Arc<RwLock<Config>>,
+        bot_state: Arc<RwLock<state::State>>,
         receiver: Receiver<CentralMessage>,
         sender: Sender<ServerMessage>,
     ) -> Self {
         Self {
             config,
+            bot_state,
 
             receiver,
             sender,
 
+            eventsub_secret: EVENTSUB_SECRET.map(|s| s.to_string()),
+
             confused_actors: Vec::new(),
             bad_actors: Vec::new(),
             repeat_offenders: Vec::new(),
@@ -98,13 +114,16 @@ impl AppState {
 
 pub async fn run(
     config: Arc<RwLock<Config>>,
+    bot_state: Arc<RwLock<state::State>>,
     receiver: Receiver<CentralMessage>,
     sender: Sender<ServerMessage>,
 ) -> anyhow::Result<()> {
-    let state = AppState::new(config, receiver, sender);
+    let state = AppState::new(config, bot_state, receiver, sender);
 
     let app = Router::new()
         .route("/", post(handle_command_direct))
+        .route("/eventsub", post(eventsub::handle_eventsub))
+        .route("/discord/:channel_id", post(handle_post_discord))
         .route("/:bot", post(handle_command_indirect))
         .with_state(state);
 
@@ -146,6 +165,56 @@ async fn handle_command_indirect(
     }
 }
 
+/// Post the body in the Discord channel with the given ID.
+async fn handle_post_discord(
+    Path(channel_id): Path<u64>,
+    State(mut state): State<AppState>,
+    ConnectInfo(info): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    match state.should_bail(&info) {
+        Bail::No => {}
+        Bail::YesWithResponse | Bail::YesIgnore => return StatusCode::FORBIDDEN.into_response(),
+    }
+    if let Err(e) = check_api_key(&mut state, info, &headers) {
+        error!("{e}");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let request = messages::Request::PostDiscord {
+        channel_id,
+        text: body,
+    };
+    match messages::send_request(&state.sender, ServerMessage::Request, request).await {
+        Ok(_) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            error!("Unable to post to Discord: {e}");
+            (StatusCode::BAD_GATEWAY, e.to_string()).into_response()
+        }
+    }
+}
+
+fn check_api_key(
+    state: &mut AppState,
+    info: SocketAddr,
+    headers: &HeaderMap,
+) -> anyhow::Result<()> {
+    if let Some(key) = headers.get(KEY_HEADER) {
+        if key.to_str()? != API_KEY {
+            state.add_bad_actor(info);
+            error!("BAD_ACTOR={}:{}", info.ip(), info.port());
+            anyhow::bail!("Invalid api key detected");
+        }
+    } else {
+        state.add_confused_actor(info);
+        error!("CONFUSED_ACTOR={}:{}", info.ip(), info.port());
+        anyhow::bail!("No api key found, bailing out");
+    }
+
+    Ok(())
+}
+
 async fn handle_command(
     state: &mut AppState,
     info: SocketAddr,
@@ -154,22 +223,13 @@ async fn handle_command(
 ) -> anyhow::Result<String> {
     match state.should_bail(&info) {
         Bail::No => {
-            if let Some(key) = headers.get(KEY_HEADER) {
-                if key.to_str()? != API_KEY {
-                    state.add_bad_actor(info);
-                    error!("BAD_ACTOR={}:{}", info.ip(), info.port());
-                    anyhow::bail!("Invalid api key detected");
-                }
-            } else {
-                state.add_confused_actor(info);
-                error!("CONFUSED_ACTOR={}:{}", info.ip(), info.port());
-                anyhow::bail!("No api key found, bailing out");
-            }
+            check_api_key(state, info, &headers)?;
 
             let response = commands::parse(
                 body,
                 commands::AdditionalInfo::None,
                 &*state.config.read().await,
+                &mut *state.bot_state.write().await,
             );
 
             match response {
@@ -190,3 +250,70 @@ async fn handle_command(
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use model::{config::Config, messages::Response, state::State as BotState};
+    use tokio::sync::broadcast;
+
+    use super::*;
+
+    fn app_state() -> (AppState, broadcast::Receiver<ServerMessage>) {
+        let (_, receiver) = broadcast::channel(10);
+        let (sender, server_receiver) = broadcast::channel(10);
+        let state = AppState::new(
+            Arc::new(RwLock::new(Config::new())),
+            Arc::new(RwLock::new(BotState::new())),
+            receiver,
+            sender,
+        );
+
+        (state, server_receiver)
+    }
+
+    async fn post_discord(state: AppState, headers: HeaderMap, text: &str) -> StatusCode {
+        let info = "127.0.0.1:1234".parse().unwrap();
+
+        handle_post_discord(
+            Path(1234),
+            State(state),
+            ConnectInfo(info),
+            headers,
+            text.into(),
+        )
+        .await
+        .status()
+    }
+
+    #[tokio::test]
+    async fn posts_to_discord() {
+        let (state, mut receiver) = app_state();
+        tokio::spawn(async move {
+            if let Ok(ServerMessage::Request(envelope)) = receiver.recv().await {
+                let response = match &envelope.request {
+                    messages::Request::PostDiscord {
+                        channel_id: 1234,
+                        text,
+                    } if text == "Hello Discord" => Response::Done,
+                    v => Response::Error(format!("Unexpected request {v:?}")),
+                };
+                envelope.reply(response);
+            }
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert(KEY_HEADER, API_KEY.parse().unwrap());
+
+        let status = post_discord(state, headers, "Hello Discord").await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn needs_the_api_key() {
+        let (state, mut receiver) = app_state();
+
+        let status = post_discord(state, HeaderMap::new(), "Hello Discord").await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use model::{alerts::Alert, messages::TwitchMessage};
+
+/// The login Twitch uses for anonymous gifts in chat.
+const ANONYMOUS_GIFTER: &str = "ananonymousgifter";
+
+/// Work out the alert for a USERNOTICE from its tags and attached message. Returns None
+/// for notices that have no alert.
+pub fn from_user_notice(tag: impl Fn(&str) -> Option<String>, message: &str) -> Option<Alert> {
+    let user = tag("display-name")
+        .filter(|v| !v.is_empty())
+        .or_else(|| tag("login"))
+        .unwrap_or_default();
+    let tier = || plan_name(&tag("msg-param-sub-plan").unwrap_or_default());
+
+    let alert = match tag("msg-id")?.as_str() {
+        "sub" => Alert::Subscribe { user, tier: tier() },
+        "resub" => Alert::Resubscribe {
+            user,
+            tier: tier(),
+            months: tag("msg-param-cumulative-months")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1),
+            message: message.to_string(),
+        },
+        kind @ ("subgift" | "anonsubgift") => {
+            let is_anonymous =
+                kind == "anonsubgift" || tag("login").as_deref() == Some(ANONYMOUS_GIFTER);
+
+            Alert::GiftSub {
+                gifter: Some(user).filter(|_| !is_anonymous),
+                recipient: tag("msg-param-recipient-display-name")?,
+                tier: tier(),
+            }
+        }
+        "raid" => Alert::Raid {
+            from: tag("msg-param-displayName")?,
+            viewers: tag("msg-param-viewerCount")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+        },
+        _ => return None,
+    };
+
+    Some(alert)
+}
+
+/// Work out the alert for an EventSub notification.
+pub fn from_message(message: &TwitchMessage) -> Option<Alert> {
+    let alert = match message {
+        TwitchMessage::Followed { user } => Alert::Follow { user: user.clone() },
+        TwitchMessage::Subscribed {
+            user,
+            tier,
+            is_gift: false,
+        } => Alert::Subscribe {
+            user: user.clone(),
+            tier: tier.clone(),
+        },
+        // EventSub does not say who gifted the sub, chat usually does so first
+        TwitchMessage::Subscribed {
+            user,
+            tier,
+            is_gift: true,
+        } => Alert::GiftSub {
+            gifter: None,
+            recipient: user.clone(),
+            tier: tier.clone(),
+        },
+        TwitchMessage::Cheered {
+            user,
+            bits,
+            message,
+        } => Alert::Cheer {
+            user: user.clone(),
+            bits: *bits,
+            message: message.clone(),
+        },
+        TwitchMessage::Raided { from, viewers, .. } => Alert::Raid {
+            from: from.clone(),
+            viewers: *viewers,
+        },
+        _ => return None,
+    };
+
+    Some(alert)
+}
+
+/// The name of a `msg-param-sub-plan`, matching the tier names EventSub uses.
+fn plan_name(plan: &str) -> String {
+    match plan {
+        "1000" => "Tier 1".into(),
+        "2000" => "Tier 2".into(),
+        "3000" => "Tier 3".into(),
+        other => other.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn parse(tags: &[(&str, &str)], message: &str) -> Option<Alert> {
+        let tags = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<String, String>>();
+
+        from_user_notice(|k| tags.get(k).cloned(), message)
+    }
+
+    #[test]
+    fn parses_resubs() {
+        let alert = parse(
+            &[
+                ("msg-id", "resub"),
+                ("display-name", "Cool_User"),
+                ("login", "cool_user"),
+                ("msg-param-cumulative-months", "7"),
+                ("msg-param-sub-plan", "Prime"),
+            ],
+            "hi chat",
+        );
+
+        assert_eq!(
+            alert,
+            Some(Alert::Resubscribe {
+                user: "Cool_User".into(),
+                tier: "Prime".into(),
+                months: 7,
+                message: "hi chat".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn hides_anonymous_gifters() {
+        let alert = parse(
+            &[
+                ("msg-id", "subgift"),
+                ("display-name", "AnAnonymousGifter"),
+                ("login", ANONYMOUS_GIFTER),
+                ("msg-param-recipient-display-name", "Lucky_User"),
+                ("msg-param-sub-plan", "1000"),
+            ],
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(
+            alert,
+            Alert::GiftSub {
+                gifter: None,
+                recipient: "Lucky_User".into(),
+                tier: "Tier 1".into(),
+            }
+        );
+        // The same gift from EventSub is a repeat
+        let eventsub = from_message(&TwitchMessage::Subscribed {
+            user: "lucky_user".into(),
+            tier: "Tier 1".into(),
+            is_gift: true,
+        });
+        assert_eq!(eventsub.map(|a| a.key()), Some(alert.key()));
+    }
+
+    #[test]
+    fn ignores_other_notices() {
+        assert_eq!(parse(&[("msg-id", "ritual")], ""), None);
+    }
+}
@@ -0,0 +1,357 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, warn};
+use model::messages::{Redemption, TwitchMessage};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, sync::mpsc::UnboundedSender};
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message as WebSocketMessage, MaybeTlsStream, WebSocketStream,
+};
+use twitch_api::{
+    eventsub::{
+        Event, EventSubscription, EventType, EventsubWebsocketData, Message, Payload,
+        PayloadParseError,
+    },
+    twitch_oauth2::Scope,
+    types::{DisplayName, SubscriptionTier, Timestamp, UserId, UserName},
+};
+
+const WEBSOCKET_URL: &str = "wss://eventsub.wss.twitch.tv/ws";
+/// How long to wait for a message before giving up on the connection, if Twitch does
+/// not say otherwise.
+const DEFAULT_KEEPALIVE_SECS: u64 = 30;
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Something received over the EventSub WebSocket.
+#[derive(Debug)]
+pub enum EventSubUpdate {
+    /// A new session was started and needs subscriptions within a few seconds.
+    Connected { session_id: String },
+    Notification {
+        message_id: String,
+        notification: Notification,
+    },
+}
+
+/// What an EventSub notification means for the bot.
+#[derive(Debug)]
+pub enum Notification {
+    /// The stream of the channel `login` went online or offline. Helix is checked for the
+    /// full stream info.
+    StreamChanged {
+        login: String,
+        is_live: bool,
+    },
+    Message(TwitchMessage),
+}
+
+/// [`channel.follow`](https://dev.twitch.tv/docs/eventsub/eventsub-subscription-types#channelfollow)
+/// version 2, which this version of twitch_api does not have yet. Twitch retired version 1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelFollowV2 {
+    pub broadcaster_user_id: UserId,
+    /// The broadcaster or a moderator of the channel, who authorized the bot to read followers.
+    pub moderator_user_id: UserId,
+}
+
+/// The event of a [`ChannelFollowV2`] notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelFollowV2Payload {
+    pub user_id: UserId,
+    pub user_login: UserName,
+    pub user_name: DisplayName,
+    pub followed_at: Timestamp,
+}
+
+impl EventSubscription for ChannelFollowV2 {
+    type Payload = ChannelFollowV2Payload;
+
+    const EVENT_TYPE: EventType = EventType::ChannelFollow;
+    const SCOPE: &'static [Scope] = &[];
+    const VERSION: &'static str = "2";
+}
+
+/// Receive EventSub messages over a WebSocket until the connection is lost. Reconnect
+/// requests from Twitch are followed without losing the subscriptions.
+pub async fn run_websocket(sender: UnboundedSender<EventSubUpdate>) -> anyhow::Result<()> {
+    let (mut socket, _) = connect_async(WEBSOCKET_URL).await?;
+    let mut previous_socket: Option<Socket> = None;
+    let mut keepalive = Duration::from_secs(DEFAULT_KEEPALIVE_SECS);
+
+    loop {
+        let message = match tokio::time::timeout(keepalive, socket.next()).await {
+            Ok(Some(v)) => v?,
+            Ok(None) => anyhow::bail!("EventSub WebSocket closed"),
+            Err(_) => anyhow::bail!("EventSub WebSocket timed out"),
+        };
+        let text = match message {
+            WebSocketMessage::Text(v) => v,
+            WebSocketMessage::Ping(v) => {
+                socket.send(WebSocketMessage::Pong(v)).await?;
+                continue;
+            }
+            WebSocketMessage::Close(frame) => {
+                anyhow::bail!("EventSub WebSocket closed: {frame:?}")
+            }
+            _ => continue,
+        };
+
+        let data = match Event::parse_websocket(&text) {
+            Ok(v) => v,
+            Err(PayloadParseError::UnimplementedEvent { .. }) => {
+                if let Some((message_id, notification)) = parse_unsupported_websocket(&text) {
+                    sender.send(EventSubUpdate::Notification {
+                        message_id,
+                        notification,
+                    })?;
+                }
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        match data {
+            EventsubWebsocketData::Welcome { payload, .. } => {
+                if let Some(secs) = payload.session.keepalive_timeout_seconds {
+                    // Leave some room for network delays
+                    keepalive = Duration::from_secs(secs as u64 + 10);
+                }
+
+                // A reconnected session keeps the subscriptions of the previous one
+                match previous_socket.take() {
+                    Some(mut v) => {
+                        debug!("Reconnected to EventSub");
+                        let _ = v.close(None).await;
+                    }
+                    None => sender.send(EventSubUpdate::Connected {
+                        session_id: payload.session.id.to_string(),
+                    })?,
+                }
+            }
+            EventsubWebsocketData::Keepalive { .. } => {}
+            EventsubWebsocketData::Notification { metadata, payload } => {
+                if let Some(notification) = parse_notification(payload) {
+                    sender.send(EventSubUpdate::Notification {
+                        message_id: metadata.message_id.to_string(),
+                        notification,
+                    })?;
+                }
+            }
+            EventsubWebsocketData::Revocation { metadata, .. } => {
+                warn!(
+                    "EventSub subscription to {:?} was revoked",
+                    metadata.subscription_type
+                );
+            }
+            EventsubWebsocketData::Reconnect { payload, .. } => {
+                let url = payload
+                    .session
+                    .reconnect_url
+                    .ok_or_else(|| anyhow::anyhow!("EventSub reconnect without a URL"))?;
+                debug!("Reconnecting to EventSub");
+
+                // Messages keep arriving on the old connection until the new one is welcomed
+                let (new_socket, _) = connect_async(&*url).await?;
+                previous_socket = Some(std::mem::replace(&mut socket, new_socket));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Work out what an EventSub notification means for the bot. Returns None for events the
+/// bot does not use.
+pub fn parse_notification(event: Event) -> Option<Notification> {
+    let message = match event {
+        Event::StreamOnlineV1(p) => {
+            return notification(p).map(|e| Notification::StreamChanged {
+                login: e.broadcaster_user_login.to_string(),
+                is_live: true,
+            })
+        }
+        Event::StreamOfflineV1(p) => {
+            return notification(p).map(|e| Notification::StreamChanged {
+                login: e.broadcaster_user_login.to_string(),
+                is_live: false,
+            })
+        }
+        Event::ChannelSubscribeV1(p) => {
+            let event = notification(p)?;
+            TwitchMessage::Subscribed {
+                user: event.user_name.to_string(),
+                tier: tier_name(&event.tier),
+                is_gift: event.is_gift,
+            }
+        }
+        Event::ChannelCheerV1(p) => {
+            let event = notification(p)?;
+            TwitchMessage::Cheered {
+                user: event
+                    .user_name
+                    .filter(|_| !event.is_anonymous)
+                    .map(|v| v.to_string()),
+                bits: event.bits,
+                message: event.message,
+            }
+        }
+        Event::ChannelRaidV1(p) => {
+            let event = notification(p)?;
+            TwitchMessage::Raided {
+                from: event.from_broadcaster_user_name.to_string(),
+                login: event.from_broadcaster_user_login.to_string(),
+                viewers: event.viewers,
+            }
+        }
+        Event::ChannelPointsCustomRewardRedemptionAddV1(p) => {
+            let event = notification(p)?;
+            TwitchMessage::RewardRedeemed(Redemption {
+                id: event.id.to_string(),
+                reward_id: event.reward.id.to_string(),
+                reward: event.reward.title,
+                cost: event.reward.cost,
+                user: event.user_name.to_string(),
+                login: event.user_login.to_string(),
+                input: event.user_input,
+            })
+        }
+        _ => return None,
+    };
+
+    Some(Notification::Message(message))
+}
+
+/// Parse the body of an EventSub webhook notification. Returns None for events the bot
+/// does not use.
+pub fn parse_webhook(body: &str) -> anyhow::Result<Option<Notification>> {
+    match Event::parse(body) {
+        Ok(event) => Ok(parse_notification(event)),
+        Err(PayloadParseError::UnimplementedEvent { .. }) => Ok(parse_unsupported(body)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Parse a WebSocket notification of an event that twitch_api does not support yet.
+/// Returns the message ID and the notification.
+fn parse_unsupported_websocket(frame: &str) -> Option<(String, Notification)> {
+    let frame = serde_json::from_str::<serde_json::Value>(frame).ok()?;
+    if frame["metadata"]["message_type"] != "notification" {
+        return None;
+    }
+
+    Some((
+        frame["metadata"]["message_id"].as_str()?.to_string(),
+        parse_unsupported(&frame["payload"].to_string())?,
+    ))
+}
+
+/// Like `parse_notification`, but for events that twitch_api does not support yet.
+fn parse_unsupported(payload: &str) -> Option<Notification> {
+    let payload = Payload::<ChannelFollowV2>::parse_notification(payload).ok()?;
+    if payload.subscription.version != ChannelFollowV2::VERSION {
+        return None;
+    }
+    let message = TwitchMessage::Followed {
+        user: notification(payload)?.user_name.to_string(),
+    };
+
+    Some(Notification::Message(message))
+}
+
+fn notification<E: EventSubscription + Clone>(payload: Payload<E>) -> Option<E::Payload> {
+    match payload.message {
+        Message::Notification(v) => Some(v),
+        _ => None,
+    }
+}
+
+fn tier_name(tier: &SubscriptionTier) -> String {
+    match tier {
+        SubscriptionTier::Tier1 => "Tier 1".into(),
+        SubscriptionTier::Tier2 => "Tier 2".into(),
+        SubscriptionTier::Tier3 => "Tier 3".into(),
+        SubscriptionTier::Prime => "Prime".into(),
+        other => format!("{other:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A webhook notification of `kind` with the given condition and event.
+    fn body(kind: &str, version: &str, condition: &str, event: &str) -> String {
+        format!(
+            r#"{{"subscription":{{"id":"f1c2a387-161a-49f9-a165-0f21d7a4e1c4","type":"{kind}","version":"{version}","status":"enabled","cost":0,"condition":{condition},"transport":{{"method":"webhook","callback":"https://example.com/eventsub"}},"created_at":"2019-11-16T10:11:12.123Z"}},"event":{event}}}"#
+        )
+    }
+
+    #[test]
+    fn parses_cheers() {
+        let body = body(
+            "channel.cheer",
+            "1",
+            r#"{"broadcaster_user_id":"1337"}"#,
+            r#"{"is_anonymous":false,"user_id":"1234","user_login":"cool_user","user_name":"Cool_User","broadcaster_user_id":"1337","broadcaster_user_login":"cooler_user","broadcaster_user_name":"Cooler_User","message":"pogchamp","bits":1000}"#,
+        );
+
+        match parse_notification(Event::parse(&body).unwrap()) {
+            Some(Notification::Message(TwitchMessage::Cheered {
+                user,
+                bits,
+                message,
+            })) => {
+                assert_eq!(user.as_deref(), Some("Cool_User"));
+                assert_eq!(bits, 1000);
+                assert_eq!(message, "pogchamp");
+            }
+            v => panic!("Unexpected notification {v:?}"),
+        }
+    }
+
+    #[test]
+    fn stream_changes_are_checked_on_helix() {
+        let body = body(
+            "stream.online",
+            "1",
+            r#"{"broadcaster_user_id":"1337"}"#,
+            r#"{"id":"9001","broadcaster_user_id":"1337","broadcaster_user_login":"cool_user","broadcaster_user_name":"Cool_User","type":"live","started_at":"2020-10-11T10:11:12.123Z"}"#,
+        );
+
+        match parse_notification(Event::parse(&body).unwrap()) {
+            Some(Notification::StreamChanged { login, is_live }) => {
+                assert_eq!(login, "cool_user");
+                assert!(is_live);
+            }
+            v => panic!("Unexpected notification {v:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_follows_twitch_api_does_not_support() {
+        let body = body(
+            "channel.follow",
+            "2",
+            r#"{"broadcaster_user_id":"1337","moderator_user_id":"1337"}"#,
+            r#"{"user_id":"1234","user_login":"cool_user","user_name":"Cool_User","broadcaster_user_id":"1337","broadcaster_user_login":"cooler_user","broadcaster_user_name":"Cooler_User","followed_at":"2020-07-15T18:16:11.17106713Z"}"#,
+        );
+
+        match parse_webhook(&body).unwrap() {
+            Some(Notification::Message(TwitchMessage::Followed { user })) => {
+                assert_eq!(user, "Cool_User");
+            }
+            v => panic!("Unexpected notification {v:?}"),
+        }
+
+        let frame = format!(
+            r#"{{"metadata":{{"message_id":"message-1","message_type":"notification","message_timestamp":"2023-07-19T10:11:12.634234626Z","subscription_type":"channel.follow","subscription_version":"2"}},"payload":{body}}}"#
+        );
+        let (message_id, notification) = parse_unsupported_websocket(&frame).unwrap();
+        assert_eq!(message_id, "message-1");
+        assert!(matches!(
+            notification,
+            Notification::Message(TwitchMessage::Followed { .. })
+        ));
+    }
+}
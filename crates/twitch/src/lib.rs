@@ -1,9 +1,15 @@
+mod alerts;
+mod eventsub;
+mod moderation;
+mod rewards;
+mod shoutouts;
 mod twitch_bot;
 
 use log::{error, info};
 use std::sync::Arc;
 use tokio::sync::{
     broadcast::{Receiver, Sender},
+    mpsc::UnboundedSender,
     RwLock,
 };
 
@@ -11,6 +17,8 @@ use model::{
     config::Config,
     creds::TwitchCreds,
     messages::{CentralMessage, TwitchMessage},
+    relay::ChatLine,
+    state::State,
 };
 
 use crate::twitch_bot::create_bots;
@@ -18,12 +26,15 @@ use crate::twitch_bot::create_bots;
 pub async fn run_bot(
     config: Arc<RwLock<Config>>,
     creds: TwitchCreds,
+    state: Arc<RwLock<State>>,
     receiver: Receiver<CentralMessage>,
     sender: Sender<TwitchMessage>,
+    relay_sender: UnboundedSender<ChatLine>,
 ) -> anyhow::Result<()> {
     info!("Starting Twitch bot");
 
-    let (mut api_bot, mut chat_bot) = create_bots(config, creds, receiver, sender).await?;
+    let (mut api_bot, mut chat_bot) =
+        create_bots(config, creds, state, receiver, sender, relay_sender).await?;
 
     let handle = tokio::spawn(async move {
         loop {
@@ -36,10 +47,17 @@ pub async fn run_bot(
 
     tokio::spawn({
         let mut ticks: u64 = 0;
+        let mut sync_roles_ticks: u64 = 0;
+        let mut points_ticks: u64 = 0;
 
         async move {
+            api_bot.start_eventsub().await;
+
             loop {
-                api_bot.tick().await;
+                if !api_bot.tick().await {
+                    handle.abort();
+                    break;
+                }
 
                 ticks += 1;
                 if ticks >= api_bot.check_live_ticks {
@@ -50,10 +68,24 @@ pub async fn run_bot(
                     }
                 }
 
-                if !api_bot.handle_central_message().await {
-                    handle.abort();
-                    break;
+                points_ticks += 1;
+                if points_ticks >= api_bot.points_ticks {
+                    points_ticks = 0;
+
+                    api_bot.award_watch_points().await;
+                }
+
+                sync_roles_ticks += 1;
+                if sync_roles_ticks >= api_bot.sync_roles_ticks {
+                    sync_roles_ticks = 0;
+
+                    if let Err(e) = api_bot.sync_roles().await {
+                        error!("Unable to sync roles: {e}");
+                    }
                 }
+
+                api_bot.handle_eventsub().await;
+                api_bot.recheck_streams().await;
             }
         }
     });
@@ -0,0 +1,227 @@
+use antispam::Verdict;
+use async_trait::async_trait;
+use model::config::SpamAction;
+use twitch_api::{helix::HelixClient, twitch_oauth2::UserToken};
+
+/// Twitch does not allow timeouts longer than 2 weeks.
+const MAX_TIMEOUT_SECS: u64 = 1_209_600;
+
+/// Moderation actions in a Twitch channel. `broadcaster_id` and `user_id` are Twitch user IDs.
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    async fn delete_message(&self, broadcaster_id: &str, message_id: &str) -> anyhow::Result<()>;
+
+    async fn timeout(
+        &self,
+        broadcaster_id: &str,
+        user_id: &str,
+        secs: u64,
+        reason: &str,
+    ) -> anyhow::Result<()>;
+
+    async fn ban(&self, broadcaster_id: &str, user_id: &str, reason: &str) -> anyhow::Result<()>;
+}
+
+/// A `Moderator` that uses the Helix moderation endpoints, acting as the bot's user.
+pub struct HelixModerator {
+    client: HelixClient<'static, reqwest::Client>,
+    token: fn() -> UserToken,
+}
+
+impl HelixModerator {
+    /// Create a new `HelixModerator`. `token` is called for every request so refreshed
+    /// tokens are picked up.
+    pub fn new(token: fn() -> UserToken) -> Self {
+        Self {
+            client: HelixClient::default(),
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl Moderator for HelixModerator {
+    async fn delete_message(&self, broadcaster_id: &str, message_id: &str) -> anyhow::Result<()> {
+        let token = (self.token)();
+
+        self.client
+            .delete_chat_message(broadcaster_id, &token.user_id, message_id, &token)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn timeout(
+        &self,
+        broadcaster_id: &str,
+        user_id: &str,
+        secs: u64,
+        reason: &str,
+    ) -> anyhow::Result<()> {
+        let token = (self.token)();
+
+        let secs = secs.clamp(1, MAX_TIMEOUT_SECS) as u32;
+        self.client
+            .ban_user(
+                user_id,
+                reason,
+                Some(secs),
+                broadcaster_id,
+                &token.user_id,
+                &token,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ban(&self, broadcaster_id: &str, user_id: &str, reason: &str) -> anyhow::Result<()> {
+        let token = (self.token)();
+
+        self.client
+            .ban_user(
+                user_id,
+                reason,
+                None,
+                broadcaster_id,
+                &token.user_id,
+                &token,
+            )
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Apply the antispam `verdict` to the message `message_id` sent by `user_id`.
+/// Warnings are left to the caller since they are sent as chat messages.
+pub async fn apply_verdict(
+    moderator: &dyn Moderator,
+    verdict: &Verdict,
+    broadcaster_id: &str,
+    user_id: &str,
+    message_id: &str,
+) -> anyhow::Result<()> {
+    match verdict.action {
+        SpamAction::Ignore | SpamAction::Warn => {}
+        SpamAction::Delete => moderator.delete_message(broadcaster_id, message_id).await?,
+        SpamAction::Timeout => {
+            moderator.delete_message(broadcaster_id, message_id).await?;
+            moderator
+                .timeout(
+                    broadcaster_id,
+                    user_id,
+                    verdict.silence_secs,
+                    &verdict.reason,
+                )
+                .await?;
+        }
+        SpamAction::Kick => {
+            moderator.delete_message(broadcaster_id, message_id).await?;
+            moderator
+                .ban(broadcaster_id, user_id, &verdict.reason)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records every call instead of talking to Twitch.
+    #[derive(Default)]
+    struct MockModerator {
+        calls: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Moderator for MockModerator {
+        async fn delete_message(
+            &self,
+            broadcaster_id: &str,
+            message_id: &str,
+        ) -> anyhow::Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("delete {broadcaster_id} {message_id}"));
+            Ok(())
+        }
+
+        async fn timeout(
+            &self,
+            broadcaster_id: &str,
+            user_id: &str,
+            secs: u64,
+            _reason: &str,
+        ) -> anyhow::Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("timeout {broadcaster_id} {user_id} {secs}"));
+            Ok(())
+        }
+
+        async fn ban(
+            &self,
+            broadcaster_id: &str,
+            user_id: &str,
+            _reason: &str,
+        ) -> anyhow::Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("ban {broadcaster_id} {user_id}"));
+            Ok(())
+        }
+    }
+
+    fn verdict(action: SpamAction) -> Verdict {
+        Verdict {
+            rule: "test",
+            action,
+            reason: "testing".into(),
+            silent: false,
+            silence_secs: 300,
+        }
+    }
+
+    async fn calls_for(action: SpamAction) -> Vec<String> {
+        let moderator = MockModerator::default();
+        apply_verdict(&moderator, &verdict(action), "1", "2", "msg")
+            .await
+            .unwrap();
+
+        moderator.calls.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn warn_does_not_moderate() {
+        assert!(calls_for(SpamAction::Warn).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_removes_message() {
+        assert_eq!(calls_for(SpamAction::Delete).await, vec!["delete 1 msg"]);
+    }
+
+    #[tokio::test]
+    async fn timeout_removes_message_and_times_out() {
+        assert_eq!(
+            calls_for(SpamAction::Timeout).await,
+            vec!["delete 1 msg", "timeout 1 2 300"]
+        );
+    }
+
+    #[tokio::test]
+    async fn kick_bans() {
+        assert_eq!(
+            calls_for(SpamAction::Kick).await,
+            vec!["delete 1 msg", "ban 1 2"]
+        );
+    }
+}
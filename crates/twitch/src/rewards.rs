@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use commands::{AdditionalInfo, CommandOutput};
+use log::error;
+use model::{
+    config::{Config, RewardAction},
+    messages::Redemption,
+    state::{QueueEntry, State},
+};
+use strfmt::Format;
+
+/// Run the actions of a redeemed channel point reward that happen in chat. Returns the
+/// messages to post. Fulfilling and refunding need Helix, so they are left to the API bot.
+pub fn run_chat_actions(
+    redemption: &Redemption,
+    prefix: &str,
+    channel: &str,
+    config: &Config,
+    state: &mut State,
+) -> Vec<String> {
+    config
+        .actions_for_reward(&redemption.reward_id, &redemption.reward)
+        .iter()
+        .filter_map(
+            |action| match run_action(action, redemption, prefix, channel, config, state) {
+                Ok(v) => v.filter(|v| !v.trim().is_empty()),
+                Err(e) => {
+                    error!("Unable to run {action:?} for {}: {e}", redemption.reward);
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+fn run_action(
+    action: &RewardAction,
+    redemption: &Redemption,
+    prefix: &str,
+    channel: &str,
+    config: &Config,
+    state: &mut State,
+) -> anyhow::Result<Option<String>> {
+    let text = match action {
+        RewardAction::Command { command } => {
+            // The input comes from the redeemer, so the command never runs as a moderator.
+            // Commands key users by login, like they do for chat messages
+            let info = AdditionalInfo::Twitch {
+                name: redemption.login.clone(),
+                channel: channel.to_string(),
+                is_vip: false,
+                is_subscriber: false,
+                is_moderator: false,
+            };
+            let input = format!("{prefix} {}", fill(command, redemption)?);
+
+            match commands::parse(&input, info, config, state) {
+                CommandOutput::Error { .. } => anyhow::bail!("Unknown command {input}"),
+                output => output.get_value(),
+            }
+        }
+        RewardAction::Message { format } => Some(fill(format, redemption)?),
+        RewardAction::Script { script } => {
+            let output = scripting::execute_with_vars(script, &vars(redemption))?;
+
+            // Chat messages are a single line
+            Some(
+                output
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            )
+        }
+        RewardAction::Queue { name } => {
+            let position = state.enqueue(
+                name,
+                QueueEntry {
+                    name: redemption.user.clone(),
+                    input: redemption.input.clone(),
+                },
+            );
+
+            Some(format!(
+                "{} joined the {name} queue at position {position}.",
+                redemption.user
+            ))
+        }
+        RewardAction::Fulfill | RewardAction::Refund => None,
+    };
+
+    Ok(text)
+}
+
+fn vars(redemption: &Redemption) -> [(&'static str, String); 3] {
+    [
+        ("user", redemption.user.clone()),
+        ("reward", redemption.reward.clone()),
+        ("input", redemption.input.clone()),
+    ]
+}
+
+fn fill(format: &str, redemption: &Redemption) -> anyhow::Result<String> {
+    let vars = vars(redemption)
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v))
+        .collect::<HashMap<String, String>>();
+
+    Ok(format.format(&vars)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redemption(reward: &str, input: &str) -> Redemption {
+        Redemption {
+            id: "redemption-1".into(),
+            reward_id: "reward-1".into(),
+            reward: reward.into(),
+            cost: 100,
+            user: "Cool_User".into(),
+            login: "cool_user".into(),
+            input: input.into(),
+        }
+    }
+
+    fn config(key: &str, actions: Vec<RewardAction>) -> Config {
+        let mut config = Config::new();
+        config.reward_actions.insert(key.into(), actions);
+
+        config
+    }
+
+    #[test]
+    fn finds_rewards_by_title() {
+        let config = config(
+            "hydrate",
+            vec![RewardAction::Message {
+                format: "{user} says: {input}".into(),
+            }],
+        );
+
+        let messages = run_chat_actions(
+            &redemption("Hydrate", "drink water"),
+            "bot?",
+            "channel",
+            &config,
+            &mut State::new(),
+        );
+
+        assert_eq!(messages, vec!["Cool_User says: drink water"]);
+    }
+
+    #[test]
+    fn queues_redeemers() {
+        let config = config(
+            "reward-1",
+            vec![
+                RewardAction::Queue {
+                    name: "Songs".into(),
+                },
+                RewardAction::Fulfill,
+            ],
+        );
+        let mut state = State::new();
+
+        let messages = run_chat_actions(
+            &redemption("Song request", "never gonna give you up"),
+            "bot?",
+            "channel",
+            &config,
+            &mut state,
+        );
+
+        assert_eq!(
+            messages,
+            vec!["Cool_User joined the Songs queue at position 1."]
+        );
+        let entry = state.dequeue("songs").unwrap();
+        assert_eq!(entry.summary(), "Cool_User (never gonna give you up)");
+    }
+
+    #[test]
+    fn commands_run_as_the_redeemer_login() {
+        let config = config(
+            "reward-1",
+            vec![RewardAction::Command {
+                command: "points".into(),
+            }],
+        );
+        let mut state = State::new();
+        state.add_points("twitch:cool_user", "Cool_User", 5);
+
+        let messages = run_chat_actions(
+            &redemption("Points", ""),
+            "bot?",
+            "channel",
+            &config,
+            &mut state,
+        );
+
+        assert_eq!(messages, vec!["cool_user has 5 points!"]);
+    }
+
+    #[test]
+    fn scripts_see_the_redemption() {
+        let config = config(
+            "reward-1",
+            vec![RewardAction::Script {
+                script: r#"print(user + " redeemed " + reward); input.len()"#.into(),
+            }],
+        );
+
+        let messages = run_chat_actions(
+            &redemption("Count", "abc"),
+            "bot?",
+            "channel",
+            &config,
+            &mut State::new(),
+        );
+
+        assert_eq!(messages, vec!["Cool_User redeemed Count 3"]);
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use log::error;
+use model::config::ShoutoutConfig;
+use strfmt::Format;
+
+/// Whether a raid with `viewers` viewers gets a shoutout.
+pub fn is_raid_shouted_out(config: &ShoutoutConfig, viewers: i64) -> bool {
+    config.on_raid && viewers >= config.min_raid_viewers
+}
+
+/// Fill in the shoutout `format` for a channel, falling back to a plain shoutout if the
+/// format is broken.
+pub fn message(format: &str, channel: &str, login: &str, game: &str, title: &str) -> String {
+    let url = format!("https://twitch.tv/{login}");
+    let game = match game {
+        "" => "something",
+        v => v,
+    };
+    let vars = HashMap::from([
+        ("channel".to_string(), channel.to_string()),
+        ("login".to_string(), login.to_string()),
+        ("game".to_string(), game.to_string()),
+        ("title".to_string(), title.to_string()),
+        ("url".to_string(), url.clone()),
+    ]);
+
+    match format.format(&vars) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to format shoutout using custom format: {e}");
+            format!("Go check out {url}!")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_shouts_out_big_enough_raids() {
+        let mut config = ShoutoutConfig {
+            on_raid: true,
+            min_raid_viewers: 5,
+            ..Default::default()
+        };
+        assert!(!is_raid_shouted_out(&config, 4));
+        assert!(is_raid_shouted_out(&config, 5));
+
+        config.on_raid = false;
+        assert!(!is_raid_shouted_out(&config, 100));
+    }
+
+    #[test]
+    fn fills_in_the_format() {
+        let format = ShoutoutConfig::default().format;
+
+        assert_eq!(
+            message(&format, "Friend", "friend", "Celeste", "Speedruns"),
+            "Go check out Friend at https://twitch.tv/friend! They were last playing Celeste."
+        );
+        assert_eq!(
+            message(&format, "Friend", "friend", "", "Speedruns"),
+            "Go check out Friend at https://twitch.tv/friend! They were last playing something."
+        );
+        assert_eq!(
+            message("{title}", "Friend", "friend", "", "Speedruns"),
+            "Speedruns"
+        );
+    }
+
+    #[test]
+    fn falls_back_for_broken_formats() {
+        assert_eq!(
+            message("Hi {nobody}", "Friend", "friend", "", ""),
+            "Go check out https://twitch.tv/friend!"
+        );
+    }
+}
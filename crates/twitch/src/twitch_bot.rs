@@ -1,26 +1,84 @@
-use commands::CommandOutput;
-use log::{debug, error, info};
-use std::{sync::Arc, time::Duration};
+use crate::{
+    alerts,
+    eventsub::{self, ChannelFollowV2, EventSubUpdate, Notification},
+    moderation::{self, HelixModerator, Moderator},
+    rewards, shoutouts,
+};
+use antispam::{Antispam, SpamMessage};
+use chrono::{DateTime, Utc};
+use commands::{AdminCommands, CommandOutput, Commands, GiveawayArgs, GiveawayCommands};
+use log::{debug, error, info, warn};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     sync::{
-        broadcast::{error::TryRecvError, Receiver, Sender},
-        RwLock,
+        broadcast::{error::RecvError, Receiver, Sender},
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        Mutex, RwLock,
     },
     time::Interval,
 };
 
 use model::{
-    config::Config,
+    alerts::Alert,
+    config::{Config, EventSubTransport, RelayConfig, RewardAction, SpamAction},
     creds::{BotCreds, TwitchCreds},
-    messages::{CentralMessage, TwitchMessage},
+    giveaway::Giveaway,
+    messages::{
+        send_request, CentralMessage, ChannelRequest, DiscordMessage, Redemption, Request,
+        Response, ServerMessage, Subsystem, TwitchMessage, TwitchUserRoles,
+    },
+    modlog::ModAction,
+    relay::{ChatLine, RateLimiter},
+    state::{unix_now, Poll, State},
+    stream::{format_days, format_duration, StreamChange, StreamInfo},
 };
+use reqwest::{Method, RequestBuilder};
+use strfmt::Format;
 use twitch_api::{
-    helix::streams::GetStreamsRequest,
-    twitch_oauth2::{ClientId, ClientSecret, RefreshToken, UserToken},
-    types::UserNameRef,
+    eventsub::{
+        channel::{
+            ChannelCheerV1, ChannelPointsCustomRewardRedemptionAddV1, ChannelRaidV1,
+            ChannelSubscribeV1,
+        },
+        stream::{StreamOfflineV1, StreamOnlineV1},
+        EventSubscription, Transport,
+    },
+    helix::{
+        channels::{GetVipsRequest, ModifyChannelInformationBody, ModifyChannelInformationRequest},
+        chat::GetChattersRequest,
+        moderation::GetModeratorsRequest,
+        points::{
+            CustomRewardRedemptionStatus, UpdateRedemptionStatusBody, UpdateRedemptionStatusRequest,
+        },
+        search::SearchCategoriesRequest,
+        streams::{GetStreamsRequest, Stream},
+        subscriptions::GetBroadcasterSubscriptionsRequest,
+        videos::{GetVideosRequest, VideoTypeFilter},
+    },
+    twitch_oauth2::{AppAccessToken, ClientId, ClientSecret, RefreshToken, TwitchToken, UserToken},
+    types::{UserId, UserIdRef, UserNameRef},
     TwitchClient,
 };
-use twitchchat::messages::Privmsg;
+use twitchchat::{
+    messages::Privmsg,
+    writer::{AsyncWriter, MpscWriter},
+};
+
+/// How many EventSub message IDs are remembered, since Twitch can send a message more than once.
+const MAX_RECENT_EVENTSUB_MESSAGES: usize = 100;
+/// How often Helix is checked again for a stream change that EventSub reported before
+/// Helix showed it.
+const STREAM_RECHECK_SECS: u64 = 15;
+/// How many times Helix is checked again before leaving a stream change to regular polling.
+const MAX_STREAM_RECHECKS: u32 = 20;
+/// For the endpoints that twitch_api does not support yet.
+const HELIX_URL: &str = "https://api.twitch.tv/helix";
+/// The most users or channels Helix accepts in a single request.
+const MAX_HELIX_IDS: usize = 100;
 
 // TODO rewrite the entire thing to use my own library
 // Both the twitch_api lib and the twitchchat lib are too obtuse to actually be useful
@@ -30,9 +88,14 @@ use twitchchat::messages::Privmsg;
 // - twitch_api makes doing anything extraordinarily difficult
 static mut USER_TOKEN: Option<UserToken> = None;
 
+fn user_token() -> UserToken {
+    unsafe { USER_TOKEN.clone().unwrap() }
+}
+
 pub struct BotCommon {
     config: Arc<RwLock<Config>>,
     creds: TwitchCreds,
+    state: Arc<RwLock<State>>,
 
     receiver: Receiver<CentralMessage>,
     sender: Sender<TwitchMessage>,
@@ -43,6 +106,7 @@ impl Clone for BotCommon {
         Self {
             config: self.config.clone(),
             creds: self.creds.clone(),
+            state: self.state.clone(),
             receiver: self.receiver.resubscribe(),
             sender: self.sender.clone(),
         }
@@ -54,6 +118,20 @@ pub struct ApiBot<'a> {
     client: TwitchClient<'a, reqwest::Client>,
     interval: Interval,
     pub check_live_ticks: u64,
+    pub sync_roles_ticks: u64,
+    pub points_ticks: u64,
+
+    broadcaster_id: Option<UserId>,
+
+    eventsub_sender: UnboundedSender<EventSubUpdate>,
+    eventsub_receiver: UnboundedReceiver<EventSubUpdate>,
+    recent_eventsub_messages: VecDeque<String>,
+    /// Channels whose stream change Helix does not show yet, to whether they went live and
+    /// how many checks are left.
+    stream_rechecks: HashMap<String, (bool, u32)>,
+    next_stream_recheck: u64,
+    /// Stream info commands to when they were answered and their answer.
+    channel_cache: HashMap<ChannelRequest, (u64, String)>,
 }
 
 impl<'a> std::ops::Deref for ApiBot<'a> {
@@ -71,61 +149,876 @@ impl<'a> std::ops::DerefMut for ApiBot<'a> {
 }
 
 impl<'a> ApiBot<'a> {
-    pub async fn tick(&mut self) {
-        let _ = self.interval.tick().await;
+    /// Wait for the next tick. Messages from the main controller are handled as they come
+    /// in, so requests are answered between ticks no matter how slow the periodic work is.
+    /// Returns false if the loop for the twitch bot should stop running.
+    pub async fn tick(&mut self) -> bool {
+        loop {
+            let message = tokio::select! {
+                _ = self.interval.tick() => return true,
+                message = self.common.receiver.recv() => message,
+            };
+            if !self.handle_central_message(message).await {
+                return false;
+            }
+        }
     }
 
-    pub async fn check_channel_live(&self) -> anyhow::Result<()> {
-        debug!("Checking if channel is live");
+    /// Check the bot's own channel and every watched channel, up to 100 channels per
+    /// request.
+    pub async fn check_channel_live(&mut self) -> anyhow::Result<()> {
+        debug!("Checking if channels are live");
 
-        match self
+        let own_login = self.creds.channel_name.to_lowercase();
+        let watched = self
+            .config
+            .read()
+            .await
+            .watched_channels
+            .iter()
+            .map(|c| c.login.to_lowercase())
+            .filter(|l| *l != own_login)
+            .collect::<Vec<String>>();
+        let logins = std::iter::once(&own_login)
+            .chain(watched.iter())
+            .map(|l| UserNameRef::from_str(l))
+            .collect::<Vec<&UserNameRef>>();
+
+        let mut live = HashMap::new();
+        for chunk in logins.chunks(MAX_HELIX_IDS) {
+            let response = self
+                .client
+                .helix
+                .req_get(
+                    GetStreamsRequest::user_logins(chunk).first(MAX_HELIX_IDS),
+                    unsafe { &USER_TOKEN.clone().unwrap() },
+                )
+                .await;
+
+            match response {
+                Ok(r) => live.extend(
+                    r.data
+                        .iter()
+                        .map(|stream| (stream.user_login.to_string(), stream_info(stream))),
+                ),
+                Err(e) => {
+                    if self.is_token_expired(&e) {
+                        if let Ok((_, token)) = create_api_resources(&self.creds).await {
+                            unsafe { USER_TOKEN = Some(token) };
+                        } else {
+                            if let Err(e) = self
+                                .common
+                                .sender
+                                .send(TwitchMessage::Error("Unable to refresh user token".into()))
+                            {
+                                error!("{e}");
+                            }
+                        }
+
+                        return Ok(());
+                    }
+
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let user_ids = live
+            .values()
+            .map(|s| s.user_id.clone())
+            .collect::<Vec<String>>();
+        match self.channel_tags(&user_ids).await {
+            Ok(mut tags) => {
+                for stream in live.values_mut() {
+                    stream.tags = tags.remove(&stream.user_id).unwrap_or_default();
+                }
+            }
+            // The tags are only shown in notifications, so they can be left out
+            Err(e) => error!("Unable to get stream tags: {e}"),
+        }
+
+        let changes = {
+            let mut state = self.state.write().await;
+            let mut changes = state.update_stream(live.remove(&own_login));
+
+            // Channels that are no longer watched end their sessions
+            let unwatched = state
+                .watched_sessions
+                .keys()
+                .filter(|l| !watched.contains(l))
+                .cloned()
+                .collect::<Vec<String>>();
+            for login in watched.iter().chain(unwatched.iter()) {
+                changes.extend(state.update_watched_stream(login, live.remove(login)));
+            }
+
+            changes
+        };
+        for change in changes {
+            let message = match change {
+                StreamChange::Started(stream) => TwitchMessage::StreamStarted(stream),
+                StreamChange::Updated(stream) => TwitchMessage::StreamUpdated(stream),
+                StreamChange::Ended(stream) => {
+                    let vod_url = self
+                        .latest_vod_url(&stream.user_id)
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("Unable to get VOD: {e}");
+                            None
+                        });
+                    TwitchMessage::StreamEnded { stream, vod_url }
+                }
+            };
+            debug!("Stream changed: {message:?}");
+
+            self.sender.send(message)?;
+        }
+
+        Ok(())
+    }
+
+    /// The tags of each channel by user ID. Streams in this version of twitch_api only have
+    /// the deprecated tag IDs, so Get Channel Information is requested directly.
+    async fn channel_tags(
+        &self,
+        user_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, Vec<String>>> {
+        if user_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut tags = HashMap::new();
+        for chunk in user_ids.chunks(MAX_HELIX_IDS) {
+            let query = chunk
+                .iter()
+                .map(|id| ("broadcaster_id", id.as_str()))
+                .collect::<Vec<(&str, &str)>>();
+            let channels: serde_json::Value = self
+                .helix_request(Method::GET, "channels")
+                .query(&query)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            tags.extend(
+                channels["data"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|c| {
+                        let tags = c["tags"]
+                            .as_array()?
+                            .iter()
+                            .filter_map(|t| t.as_str().map(String::from))
+                            .collect();
+                        Some((c["broadcaster_id"].as_str()?.to_string(), tags))
+                    }),
+            );
+        }
+
+        Ok(tags)
+    }
+
+    /// Get the URL of the most recent VOD of the channel, if there is one.
+    async fn latest_vod_url(&self, user_id: &str) -> anyhow::Result<Option<String>> {
+        let mut request = GetVideosRequest::user_id(UserIdRef::from_str(user_id));
+        request.first = Some(1);
+        request.type_ = Some(VideoTypeFilter::Archive);
+
+        let response = self
             .client
             .helix
-            .req_get(
-                GetStreamsRequest::user_logins(
-                    [UserNameRef::from_str(self.creds.channel_name.as_str())].as_slice(),
-                ),
-                unsafe { &USER_TOKEN.clone().unwrap() },
+            .req_get(request, unsafe { &USER_TOKEN.clone().unwrap() })
+            .await?;
+
+        Ok(response.data.first().map(|v| v.url.clone()))
+    }
+
+    /// Give points to everyone that is watching or has been chatting recently while the
+    /// stream is live.
+    pub async fn award_watch_points(&mut self) {
+        let is_live = self.state.read().await.is_live();
+        let viewers = if is_live {
+            // Recent chatters are still paid if the viewer list is unavailable
+            self.viewers().await.unwrap_or_else(|e| {
+                error!("Unable to get the viewer list: {e}");
+                vec![]
+            })
+        } else {
+            vec![]
+        };
+
+        let config = self.config.read().await;
+        self.state.write().await.award_watch_points(
+            &viewers,
+            config.points_per_interval,
+            config.active_chatter_secs,
+        );
+    }
+
+    /// The login and display name of everyone connected to the channel's chat, besides
+    /// the bot.
+    async fn viewers(&mut self) -> anyhow::Result<Vec<(String, String)>> {
+        let broadcaster_id = self.broadcaster_id().await?;
+        let token = user_token();
+
+        let mut viewers = vec![];
+        let mut response = Some(
+            self.client
+                .helix
+                .req_get(
+                    GetChattersRequest::new(broadcaster_id, token.user_id.clone()).first(1000),
+                    &token,
+                )
+                .await?,
+        );
+        while let Some(r) = response {
+            viewers.extend(
+                r.data
+                    .iter()
+                    .filter(|c| {
+                        !c.user_login
+                            .as_str()
+                            .eq_ignore_ascii_case(&self.creds.bot_name)
+                    })
+                    .map(|c| (c.user_login.to_string(), c.user_name.to_string())),
+            );
+            response = r.get_next(&self.client.helix, &token).await?;
+        }
+
+        Ok(viewers)
+    }
+
+    /// Get the user ID of the configured channel, caching it after the first request.
+    async fn broadcaster_id(&mut self) -> anyhow::Result<UserId> {
+        if let Some(id) = &self.broadcaster_id {
+            return Ok(id.clone());
+        }
+
+        let user = self
+            .client
+            .helix
+            .get_user_from_login(self.creds.channel_name.as_str(), unsafe {
+                &USER_TOKEN.clone().unwrap()
+            })
+            .await?
+            .ok_or_else(|| {
+                anyhow::anyhow!("Unable to find Twitch user {}", self.creds.channel_name)
+            })?;
+
+        self.broadcaster_id = Some(user.id.clone());
+
+        Ok(user.id)
+    }
+
+    /// Start receiving EventSub notifications using the configured transport.
+    pub async fn start_eventsub(&mut self) {
+        let (transport, callback_url) = {
+            let config = self.config.read().await;
+            (
+                config.eventsub_transport,
+                config.eventsub_callback_url.clone(),
             )
+        };
+
+        match transport {
+            EventSubTransport::Disabled => {}
+            EventSubTransport::Websocket => {
+                let sender = self.eventsub_sender.clone();
+                tokio::spawn(async move {
+                    let mut wait_interval = tokio::time::interval(Duration::from_secs_f32(5.0));
+                    while !sender.is_closed() {
+                        if let Err(e) = eventsub::run_websocket(sender.clone()).await {
+                            error!("{e}");
+                        }
+                        wait_interval.tick().await;
+                    }
+                });
+            }
+            EventSubTransport::Webhook => {
+                if let Err(e) = self.subscribe_webhook(&callback_url).await {
+                    error!("Unable to subscribe to EventSub: {e}");
+                }
+            }
+        }
+    }
+
+    async fn subscribe_webhook(&mut self, callback_url: &str) -> anyhow::Result<()> {
+        if callback_url.is_empty() || self.creds.eventsub_secret.is_empty() {
+            anyhow::bail!("The EventSub webhook needs a callback URL and a secret");
+        }
+
+        // Webhook subscriptions can only be created with an app access token
+        let token = AppAccessToken::get_app_access_token(
+            &self.client,
+            ClientId::new(self.creds.client_id.clone()),
+            ClientSecret::new(self.creds.client_secret.clone()),
+            vec![],
+        )
+        .await?;
+        let transport = Transport::webhook(callback_url, self.creds.eventsub_secret.clone());
+
+        self.subscribe_eventsub(transport, &token).await
+    }
+
+    /// Subscribe to every EventSub event the bot uses.
+    async fn subscribe_eventsub<T: TwitchToken + Send + Sync>(
+        &mut self,
+        transport: Transport,
+        token: &T,
+    ) -> anyhow::Result<()> {
+        let id = self.broadcaster_id().await?;
+
+        self.subscribe(
+            StreamOnlineV1::broadcaster_user_id(id.clone()),
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            StreamOfflineV1::broadcaster_user_id(id.clone()),
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            ChannelFollowV2 {
+                broadcaster_user_id: id.clone(),
+                moderator_user_id: user_token().user_id,
+            },
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            ChannelSubscribeV1::broadcaster_user_id(id.clone()),
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            ChannelCheerV1::broadcaster_user_id(id.clone()),
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            ChannelRaidV1::to_broadcaster_user_id(id.clone()),
+            &transport,
+            token,
+        )
+        .await;
+        self.subscribe(
+            ChannelPointsCustomRewardRedemptionAddV1::broadcaster_user_id(id),
+            &transport,
+            token,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn subscribe<E, T>(&self, subscription: E, transport: &Transport, token: &T)
+    where
+        E: EventSubscription + Send,
+        T: TwitchToken + Send + Sync,
+    {
+        match self
+            .client
+            .helix
+            .create_eventsub_subscription(subscription, transport.clone(), token)
             .await
         {
-            Ok(r) => {
-                if r.data.is_empty() {
-                    return Ok(());
+            Ok(_) => debug!("Subscribed to {:?}", E::EVENT_TYPE),
+            Err(e) => error!("Unable to subscribe to {:?}: {e}", E::EVENT_TYPE),
+        }
+    }
+
+    /// Check Helix again for the stream changes from EventSub that it did not show yet.
+    pub async fn recheck_streams(&mut self) {
+        let now = unix_now();
+        if self.stream_rechecks.is_empty() || now < self.next_stream_recheck {
+            return;
+        }
+        self.next_stream_recheck = now + STREAM_RECHECK_SECS;
+
+        if let Err(e) = self.check_channel_live().await {
+            error!("{e}");
+        }
+
+        let own_login = self.creds.channel_name.to_lowercase();
+        let state = self.state.clone();
+        let state = state.read().await;
+        self.stream_rechecks
+            .retain(|login, (is_live, checks_left)| {
+                let is_shown_live = match *login == own_login {
+                    true => state.is_live(),
+                    false => state.watched_sessions.contains_key(login),
+                };
+                *checks_left = checks_left.saturating_sub(1);
+                if is_shown_live != *is_live && *checks_left == 0 {
+                    warn!("Helix still does not show the stream change of {login}");
                 }
-                let stream = r.data.first().unwrap();
 
-                self.sender
-                    .send(TwitchMessage::ChannelLive {
-                        channel: stream.user_name.to_string(),
-                        title: stream.title.to_string(),
-                        url: format!("https://twitch.tv/{}", self.creds.channel_name.as_str()),
-                    })
-                    .map(|_| ())
-                    .map_err(anyhow::Error::from)
+                is_shown_live != *is_live && *checks_left > 0
+            });
+    }
+
+    /// Handle the EventSub messages received over the WebSocket since the last tick.
+    pub async fn handle_eventsub(&mut self) {
+        while let Ok(update) = self.eventsub_receiver.try_recv() {
+            match update {
+                EventSubUpdate::Connected { session_id } => {
+                    debug!("Connected to EventSub");
+
+                    if let Err(e) = self
+                        .subscribe_eventsub(Transport::websocket(session_id), &user_token())
+                        .await
+                    {
+                        error!("Unable to subscribe to EventSub: {e}");
+                    }
+                }
+                EventSubUpdate::Notification {
+                    message_id,
+                    notification,
+                } => {
+                    self.handle_notification(message_id, notification).await;
+                }
+            }
+        }
+    }
+
+    async fn handle_notification(&mut self, message_id: String, notification: Notification) {
+        if self.recent_eventsub_messages.contains(&message_id) {
+            debug!("Ignoring repeated EventSub message {message_id}");
+            return;
+        }
+        self.recent_eventsub_messages.push_back(message_id);
+        if self.recent_eventsub_messages.len() > MAX_RECENT_EVENTSUB_MESSAGES {
+            self.recent_eventsub_messages.pop_front();
+        }
+
+        match notification {
+            Notification::StreamChanged { login, is_live } => {
+                // Helix shows the change a while after EventSub, so it is checked until it does
+                self.stream_rechecks
+                    .insert(login.to_lowercase(), (is_live, MAX_STREAM_RECHECKS));
+                self.next_stream_recheck = 0;
+                self.recheck_streams().await;
+            }
+            Notification::Message(message) => {
+                if let TwitchMessage::RewardRedeemed(redemption) = &message {
+                    if let Err(e) = self.update_redemption_status(redemption).await {
+                        error!("Unable to update redemption {}: {e}", redemption.id);
+                    }
+                }
+                if let TwitchMessage::Raided { login, viewers, .. } = &message {
+                    let shoutouts = self.config.read().await.shoutouts.clone();
+                    if shoutouts::is_raid_shouted_out(&shoutouts, *viewers) {
+                        let text = self.shoutout(login).await;
+                        self.say(text);
+                    }
+                }
+                if let Err(e) = self.sender.send(message) {
+                    error!("{e}");
+                }
+            }
+        }
+    }
+
+    /// Fulfill or refund a redemption if its reward is configured to.
+    async fn update_redemption_status(&mut self, redemption: &Redemption) -> anyhow::Result<()> {
+        let status = self
+            .config
+            .read()
+            .await
+            .actions_for_reward(&redemption.reward_id, &redemption.reward)
+            .iter()
+            .find_map(|a| match a {
+                RewardAction::Fulfill => Some(CustomRewardRedemptionStatus::Fulfilled),
+                RewardAction::Refund => Some(CustomRewardRedemptionStatus::Canceled),
+                _ => None,
+            });
+        let status = match status {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let broadcaster_id = self.broadcaster_id().await?;
+        let request = UpdateRedemptionStatusRequest::new(
+            broadcaster_id.as_str(),
+            redemption.reward_id.as_str(),
+            redemption.id.as_str(),
+        );
+        self.client
+            .helix
+            .req_patch(
+                request,
+                UpdateRedemptionStatusBody::status(status),
+                &user_token(),
+            )
+            .await?;
+        debug!("Marked redemption {} as {status:?}", redemption.id);
+
+        Ok(())
+    }
+
+    /// Create the shoutout message for a channel, giving it a native shoutout too if
+    /// configured. Returns an error message if the channel could not be found.
+    async fn shoutout(&mut self, login: &str) -> String {
+        let login = login.trim_start_matches('@');
+        let channel = match self
+            .client
+            .helix
+            .get_channel_from_login(login, &user_token())
+            .await
+        {
+            Ok(Some(v)) => v,
+            Ok(None) => return format!("Unable to find the channel {login}!"),
+            Err(e) => {
+                error!("Unable to get the channel {login}: {e}");
+                return format!("Unable to give a shoutout to {login}!");
             }
+        };
+
+        let shoutouts = self.config.read().await.shoutouts.clone();
+        if shoutouts.native {
+            if let Err(e) = self.native_shoutout(channel.broadcaster_id.as_str()).await {
+                error!("Unable to give a native shoutout to {login}: {e}");
+            }
+        }
+
+        shoutouts::message(
+            &shoutouts.format,
+            channel.broadcaster_name.as_str(),
+            channel.broadcaster_login.as_str(),
+            &channel.game_name,
+            &channel.title,
+        )
+    }
+
+    /// Give a channel a native Twitch shoutout, which shows it to viewers.
+    async fn native_shoutout(&mut self, to_id: &str) -> anyhow::Result<()> {
+        let broadcaster_id = self.broadcaster_id().await?;
+
+        self.helix_request(Method::POST, "chat/shoutouts")
+            .query(&[
+                ("from_broadcaster_id", broadcaster_id.as_str()),
+                ("to_broadcaster_id", to_id),
+                ("moderator_id", user_token().user_id.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Start a request to a Helix endpoint that twitch_api does not support yet.
+    fn helix_request(&self, method: Method, path: &str) -> RequestBuilder {
+        let token = user_token();
+
+        self.client
+            .helix
+            .get_client()
+            .request(method, format!("{HELIX_URL}/{path}"))
+            .header("Client-Id", token.client_id().as_str())
+            .bearer_auth(token.access_token.secret())
+    }
+
+    /// Answer a stream info command, reusing a recent answer so chat cannot spam Helix.
+    async fn answer_channel_request(&mut self, request: &ChannelRequest) -> String {
+        let cache_secs = self.config.read().await.channel_info_cache_secs;
+        if let Some((answered_at, text)) = self.channel_cache.get(request) {
+            if unix_now() < answered_at + cache_secs {
+                return text.clone();
+            }
+        }
+
+        let text = match self.channel_info(request).await {
+            Ok(v) => v,
             Err(e) => {
-                if self.is_token_expired(&e) {
-                    if let Ok((_, token)) = create_api_resources(&self.creds).await {
-                        unsafe { USER_TOKEN = Some(token) };
-                    } else {
-                        if let Err(e) = self
-                            .common
-                            .sender
-                            .send(TwitchMessage::Error("Unable to refresh user token".into()))
-                        {
-                            error!("{e}");
-                        }
+                error!("Unable to answer {request:?}: {e}");
+                return "Unable to reach Twitch, try again later!".into();
+            }
+        };
+        match request {
+            ChannelRequest::SetGame(_) | ChannelRequest::SetTitle(_) => {
+                self.channel_cache.remove(&ChannelRequest::Game);
+                self.channel_cache.remove(&ChannelRequest::Title);
+            }
+            _ => {
+                self.channel_cache
+                    .insert(request.clone(), (unix_now(), text.clone()));
+            }
+        }
+
+        text
+    }
+
+    async fn channel_info(&mut self, request: &ChannelRequest) -> anyhow::Result<String> {
+        let token = user_token();
+        let broadcaster_id = self.broadcaster_id().await?;
+        let channel_name = self.creds.channel_name.clone();
+
+        let text = match request {
+            ChannelRequest::Uptime => {
+                let ids = [UserIdRef::from_str(broadcaster_id.as_str())];
+                let stream = self
+                    .client
+                    .helix
+                    .req_get(GetStreamsRequest::user_ids(ids.as_slice()), &token)
+                    .await?
+                    .data
+                    .into_iter()
+                    .next();
+
+                match stream {
+                    Some(s) => {
+                        let started_at = DateTime::parse_from_rfc3339(s.started_at.as_str())?;
+                        let secs = (Utc::now() - started_at.with_timezone(&Utc)).num_seconds();
+                        format!(
+                            "{channel_name} has been live for {}.",
+                            format_duration(secs.max(0) as u64)
+                        )
                     }
+                    None => format!("{channel_name} is offline."),
+                }
+            }
+            ChannelRequest::Game | ChannelRequest::Title => {
+                let channel = self
+                    .client
+                    .helix
+                    .get_channel_from_id(&broadcaster_id, &token)
+                    .await?
+                    .ok_or_else(|| anyhow::anyhow!("Unable to find channel {channel_name}"))?;
+
+                match (request, channel.game_name.as_str()) {
+                    (ChannelRequest::Title, _) => channel.title,
+                    (_, "") => format!("{channel_name} has not set a game."),
+                    (_, game) => format!("{channel_name} is playing {game}."),
+                }
+            }
+            ChannelRequest::Followage(login) => {
+                let user = match self
+                    .client
+                    .helix
+                    .get_user_from_login(login.as_str(), &token)
+                    .await?
+                {
+                    Some(v) => v,
+                    None => return Ok(format!("Unable to find the user {login}!")),
+                };
+                // Get Users Follows was removed from Helix, but twitch_api does not have
+                // its replacement yet
+                let followers: serde_json::Value = self
+                    .helix_request(Method::GET, "channels/followers")
+                    .query(&[
+                        ("broadcaster_id", broadcaster_id.as_str()),
+                        ("user_id", user.id.as_str()),
+                    ])
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
 
-                    return Ok(());
+                match followers["data"][0]["followed_at"].as_str() {
+                    Some(followed_at) => {
+                        let followed_at = DateTime::parse_from_rfc3339(followed_at)?;
+                        let days = (Utc::now() - followed_at.with_timezone(&Utc)).num_days();
+                        format!(
+                            "{} has followed {channel_name} for {}.",
+                            user.display_name,
+                            format_days(days.max(0) as u64)
+                        )
+                    }
+                    None => format!("{} is not following {channel_name}.", user.display_name),
                 }
+            }
+            ChannelRequest::SetGame(name) => {
+                let category = self
+                    .client
+                    .helix
+                    .req_get(
+                        SearchCategoriesRequest::query(name.as_str()).first(1),
+                        &token,
+                    )
+                    .await?
+                    .data
+                    .into_iter()
+                    .next();
+                let category = match category {
+                    Some(v) => v,
+                    None => return Ok(format!("Unable to find the game {name}!")),
+                };
+
+                let mut body = ModifyChannelInformationBody::new();
+                body.game_id(category.id.as_str());
+                self.client
+                    .helix
+                    .req_patch(
+                        ModifyChannelInformationRequest::broadcaster_id(broadcaster_id.as_str()),
+                        body,
+                        &token,
+                    )
+                    .await?;
+
+                format!("The game is now {}.", category.name)
+            }
+            ChannelRequest::SetTitle(title) => {
+                let mut body = ModifyChannelInformationBody::new();
+                body.title(title.as_str());
+                self.client
+                    .helix
+                    .req_patch(
+                        ModifyChannelInformationRequest::broadcaster_id(broadcaster_id.as_str()),
+                        body,
+                        &token,
+                    )
+                    .await?;
 
-                return Err(e.into());
+                format!("The title is now {title}.")
             }
+        };
+
+        Ok(text)
+    }
+
+    /// Start a raid to a channel. Only works if the bot is logged in as the broadcaster.
+    async fn start_raid(&mut self, login: &str) -> anyhow::Result<String> {
+        let login = login.trim_start_matches('@');
+        let token = user_token();
+        let target = self
+            .client
+            .helix
+            .get_user_from_login(login, &token)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Unable to find Twitch user {login}"))?;
+        let broadcaster_id = self.broadcaster_id().await?;
+
+        self.client
+            .helix
+            .start_a_raid(broadcaster_id.as_str(), target.id.as_str(), &token)
+            .await?;
+
+        Ok(format!("Raiding {}!", target.display_name))
+    }
+
+    /// Post a message in Twitch chat through the chat bot.
+    fn say(&self, text: String) {
+        if let Err(e) = self.sender.send(TwitchMessage::ChatMessage(text)) {
+            error!("{e}");
         }
     }
 
+    /// Answer a request from another subsystem.
+    async fn answer_request(&mut self, request: &Request) -> Response {
+        match request {
+            Request::Channel(request) => Response::Text(self.answer_channel_request(request).await),
+            Request::PostTwitch(text) => {
+                self.say(text.clone());
+                Response::Done
+            }
+            Request::ProfileImage(login) => match self.profile_image(login).await {
+                Ok(v) => Response::Text(v),
+                Err(e) => Response::Error(format!("Unable to get the user {login}: {e}")),
+            },
+            _ => Response::Error(format!("Twitch cannot answer {request:?}")),
+        }
+    }
+
+    async fn profile_image(&self, login: &str) -> anyhow::Result<String> {
+        let user = self
+            .client
+            .helix
+            .get_user_from_login(login, &user_token())
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("No user found"))?;
+
+        Ok(user.profile_image_url.unwrap_or_default())
+    }
+
+    /// Get the Twitch roles of every linked account and send them so they can be
+    /// synced to Discord.
+    pub async fn sync_roles(&mut self) -> anyhow::Result<()> {
+        debug!("Syncing Twitch roles");
+
+        let logins = self
+            .state
+            .read()
+            .await
+            .links
+            .values()
+            .cloned()
+            .collect::<Vec<String>>();
+        if logins.is_empty() {
+            return Ok(());
+        }
+
+        let broadcaster_id = self.broadcaster_id().await?;
+        let token = unsafe { USER_TOKEN.clone().unwrap() };
+
+        let mut subscribers = vec![];
+        let mut response = Some(
+            self.client
+                .helix
+                .req_get(
+                    GetBroadcasterSubscriptionsRequest::broadcaster_id(broadcaster_id.clone()),
+                    &token,
+                )
+                .await?,
+        );
+        while let Some(r) = response {
+            subscribers.extend(r.data.iter().map(|s| s.user_login.to_string()));
+            response = r.get_next(&self.client.helix, &token).await?;
+        }
+
+        let mut moderators = vec![];
+        let mut response = Some(
+            self.client
+                .helix
+                .req_get(
+                    GetModeratorsRequest::broadcaster_id(broadcaster_id.clone()),
+                    &token,
+                )
+                .await?,
+        );
+        while let Some(r) = response {
+            moderators.extend(r.data.iter().map(|m| m.user_login.to_string()));
+            response = r.get_next(&self.client.helix, &token).await?;
+        }
+
+        let mut vips = vec![];
+        let mut response = Some(
+            self.client
+                .helix
+                .req_get(GetVipsRequest::broadcaster_id(broadcaster_id), &token)
+                .await?,
+        );
+        while let Some(r) = response {
+            vips.extend(r.data.iter().map(|v| v.user_login.to_string()));
+            response = r.get_next(&self.client.helix, &token).await?;
+        }
+
+        let roles = logins
+            .into_iter()
+            .map(|login| TwitchUserRoles {
+                is_subscriber: subscribers.contains(&login),
+                is_vip: vips.contains(&login),
+                is_moderator: moderators.contains(&login),
+                login,
+            })
+            .collect();
+
+        self.sender
+            .send(TwitchMessage::UserRoles(roles))
+            .map(|_| ())
+            .map_err(anyhow::Error::from)
+    }
+
     fn is_token_expired<RE: std::error::Error + Send + Sync + 'static>(
         &self,
         e: &twitch_api::helix::ClientRequestError<RE>,
@@ -145,10 +1038,10 @@ impl<'a> ApiBot<'a> {
         false
     }
 
-    /// Handles a message from the main controller. Returns false if the loop for
-    /// the twitch bot should stop running.
-    pub async fn handle_central_message(&mut self) -> bool {
-        match &self.receiver.try_recv() {
+    /// Handles a message from the main controller. Returns false if the loop for the twitch
+    /// bot should stop running.
+    async fn handle_central_message(&mut self, message: Result<CentralMessage, RecvError>) -> bool {
+        match &message {
             Ok(m) => match m {
                 CentralMessage::ConfigUpdated => {
                     debug!("Updating from config");
@@ -158,11 +1051,59 @@ impl<'a> ApiBot<'a> {
 
                     self.interval =
                         tokio::time::interval(Duration::from_secs_f32(config.tick_duration));
+                    self.check_live_ticks = config.check_live_ticks;
+                    self.sync_roles_ticks = config.sync_roles_ticks;
+                    self.points_ticks = config.points_ticks;
 
                     debug!("Finished updating from config");
 
                     true
                 }
+                CentralMessage::Discord(DiscordMessage::SyncRolesRequested)
+                | CentralMessage::Twitch(TwitchMessage::SyncRolesRequested) => {
+                    if let Err(e) = self.sync_roles().await {
+                        error!("Unable to sync roles: {e}");
+                    }
+
+                    true
+                }
+                CentralMessage::Twitch(TwitchMessage::ShoutoutRequested { user }) => {
+                    let text = self.shoutout(user).await;
+                    self.say(text);
+
+                    true
+                }
+                CentralMessage::Twitch(TwitchMessage::RaidRequested { user }) => {
+                    match self.start_raid(user).await {
+                        Ok(v) => self.say(v),
+                        Err(e) => {
+                            error!("Unable to raid {user}: {e}");
+                            self.say(format!("Unable to raid {user}!"));
+                        }
+                    }
+
+                    true
+                }
+                CentralMessage::Request(envelope) if envelope.is_for(Subsystem::Twitch) => {
+                    let response = self.answer_request(&envelope.request).await;
+                    if !envelope.reply(response) {
+                        debug!("Nobody is waiting for {:?}", envelope.request);
+                    }
+
+                    true
+                }
+                CentralMessage::Server(ServerMessage::EventSub { message_id, body }) => {
+                    match eventsub::parse_webhook(body) {
+                        Ok(Some(notification)) => {
+                            self.handle_notification(message_id.clone(), notification)
+                                .await
+                        }
+                        Ok(None) => {}
+                        Err(e) => error!("Unable to parse EventSub notification: {e}"),
+                    }
+
+                    true
+                }
                 CentralMessage::Discord(_) => {
                     // TODO stub
                     true
@@ -175,28 +1116,42 @@ impl<'a> ApiBot<'a> {
                 _ => true,
             },
             Err(e) => match e {
-                TryRecvError::Closed => {
+                RecvError::Closed => {
                     error!("Channel closed");
 
                     false
                 }
-                TryRecvError::Lagged(n) => {
+                RecvError::Lagged(n) => {
                     debug!("Channel lagged by {} messages", n);
 
                     true
                 }
-                _ => true,
             },
         }
     }
 }
 
 pub struct ChatBot {
-    common: BotCommon,
     client: twitchchat::AsyncRunner,
+    chat: Chat,
+}
+
+/// Everything the chat bot uses besides the reader, so messages from the main controller
+/// can be handled while a chat message is being read.
+struct Chat {
+    common: BotCommon,
+    writer: Mutex<AsyncWriter<MpscWriter>>,
+
+    antispam: RwLock<Antispam>,
+    moderator: Arc<dyn Moderator>,
+    /// Limits the chat messages mirrored into Discord.
+    relay_limiter: RwLock<RateLimiter>,
+    /// Chat mirrored into Discord. It skips the controller so chat bursts cannot crowd
+    /// out other messages.
+    relay_sender: UnboundedSender<ChatLine>,
 }
 
-impl std::ops::Deref for ChatBot {
+impl std::ops::Deref for Chat {
     type Target = BotCommon;
 
     fn deref(&self) -> &Self::Target {
@@ -205,11 +1160,25 @@ impl std::ops::Deref for ChatBot {
 }
 
 impl ChatBot {
+    /// Handles chat messages and messages from the main controller as they come in.
     pub async fn handle_chat(&mut self) -> anyhow::Result<()> {
-        match self.client.next_message().await? {
-            twitchchat::Status::Message(m) => {
-                self.handle_message(m).await?;
+        let status = {
+            // Reading a chat message is not cancel safe, so the same read is kept going
+            // while messages from the main controller are handled
+            let next = self.client.next_message();
+            tokio::pin!(next);
+
+            loop {
+                let message = tokio::select! {
+                    status = &mut next => break status?,
+                    message = self.chat.common.receiver.recv() => message,
+                };
+                self.chat.handle_central_message(message).await?;
             }
+        };
+
+        match status {
+            twitchchat::Status::Message(m) => self.handle_message(m).await?,
             twitchchat::Status::Quit => anyhow::bail!("Quit detected from Twitch chat"),
             _ => {}
         }
@@ -224,13 +1193,13 @@ impl ChatBot {
         match command {
             twitchchat::messages::Commands::IrcReady(v) => {
                 debug!("{:?}", v);
-                if let Err(e) = self.sender.send(TwitchMessage::Ready) {
+                if let Err(e) = self.chat.sender.send(TwitchMessage::Ready) {
                     error!("{e}");
                 }
             }
             twitchchat::messages::Commands::Ready(v) => {
                 debug!("{:?}", v);
-                if let Err(e) = self.send_chat_message("Bot ready!").await {
+                if let Err(e) = self.chat.send_chat_message("Bot ready!").await {
                     error!("{e}");
                 }
             }
@@ -243,10 +1212,20 @@ impl ChatBot {
                 );
             }
             twitchchat::messages::Commands::Privmsg(m) => {
-                self.handle_privmsg(&m).await?;
+                self.chat.handle_privmsg(&m).await?;
+            }
+            twitchchat::messages::Commands::UserNotice(v) => {
+                let alert = alerts::from_user_notice(
+                    |k| v.tags().get(k).map(|t| t.to_string()),
+                    v.message().unwrap_or_default(),
+                );
+                if let Some(alert) = alert {
+                    self.chat.post_alert(alert).await?;
+                }
             }
             twitchchat::messages::Commands::Reconnect(_) => {
                 if let Err(e) = self
+                    .chat
                     .sender
                     .send(TwitchMessage::Debug("Reconnect received".into()))
                 {
@@ -258,16 +1237,99 @@ impl ChatBot {
 
                 self.client = create_irc_resources(
                     unsafe { USER_TOKEN.clone().unwrap().access_token.secret() },
-                    &self.creds.bot_name,
-                    &self.creds.channel_name,
+                    &self.chat.creds.bot_name,
+                    &self.chat.creds.channel_name,
                 )
                 .await?;
+                *self.chat.writer.lock().await = self.client.writer();
             }
             _ => {}
         }
 
         Ok(())
     }
+}
+
+impl Chat {
+    async fn handle_central_message(
+        &self,
+        message: Result<CentralMessage, RecvError>,
+    ) -> anyhow::Result<()> {
+        match message {
+            Ok(CentralMessage::Discord(DiscordMessage::PollStarted(poll))) => {
+                self.send_chat_message(poll_announcement(&poll).as_str())
+                    .await?;
+            }
+            Ok(CentralMessage::Discord(DiscordMessage::PollEnded(results)))
+            | Ok(CentralMessage::Discord(DiscordMessage::GiveawayEnded(results))) => {
+                self.send_chat_message(results.as_str()).await?;
+            }
+            Ok(CentralMessage::Discord(DiscordMessage::GiveawayStarted(giveaway))) => {
+                let keyword = self.common.config.read().await.giveaway_keyword.clone();
+                self.send_chat_message(giveaway_announcement(&giveaway, &keyword).as_str())
+                    .await?;
+            }
+            Ok(CentralMessage::Twitch(TwitchMessage::RewardRedeemed(redemption))) => {
+                let messages = rewards::run_chat_actions(
+                    &redemption,
+                    self.common.creds.bot_prefix(),
+                    &self.common.creds.channel_name,
+                    &*self.common.config.read().await,
+                    &mut *self.common.state.write().await,
+                );
+                for message in messages {
+                    self.send_chat_message(&message).await?;
+                }
+            }
+            Ok(CentralMessage::Twitch(TwitchMessage::ChatMessage(text))) => {
+                self.send_chat_message(&text).await?;
+            }
+            Ok(CentralMessage::Twitch(message)) => {
+                if let Some(alert) = alerts::from_message(&message) {
+                    self.post_alert(alert).await?;
+                }
+            }
+            Ok(_) => {}
+            Err(RecvError::Closed) => anyhow::bail!("Channel closed"),
+            Err(RecvError::Lagged(n)) => debug!("Channel lagged by {} messages", n),
+        }
+
+        Ok(())
+    }
+
+    /// Mirror a chat message into the Discord relay channel if the relay is on.
+    async fn relay_to_discord(&self, msg: &Privmsg<'_>, config: &RelayConfig) {
+        // Discord messages are posted by the bot, so they must not be sent back
+        if !config.twitch_to_discord
+            || config.discord_channel == 0
+            || msg.name().eq_ignore_ascii_case(&self.common.creds.bot_name)
+        {
+            return;
+        }
+        if !self
+            .relay_limiter
+            .write()
+            .await
+            .allow(config.twitch_to_discord_per_minute, unix_now())
+        {
+            debug!("Too many relayed messages, not relaying {}", msg.name());
+            return;
+        }
+
+        let user = msg
+            .tags()
+            .get("display-name")
+            .filter(|v| !v.is_empty())
+            .unwrap_or(msg.name());
+        let line = ChatLine {
+            user: user.to_string(),
+            login: msg.name().to_lowercase(),
+            text: msg.data().to_string(),
+        };
+        if let Err(e) = self.relay_sender.send(line) {
+            error!("{e}");
+        }
+    }
 
     async fn handle_privmsg(&self, msg: &Privmsg<'_>) -> anyhow::Result<()> {
         let id = msg.tags().get("id").unwrap_or_default();
@@ -276,23 +1338,151 @@ impl ChatBot {
             return Ok(());
         }
 
-        let text = msg.data();
-        if !text.starts_with(self.common.creds.bot_prefix()) {
+        if !is_moderator(msg) && self.check_spam(msg, id).await? {
             return Ok(());
         }
 
         let config = &*self.common.config.read().await;
+        self.relay_to_discord(msg, &config.relay).await;
 
-        let output = commands::parse(
-            text,
-            commands::AdditionalInfo::Twitch {
-                name: msg.name().to_string(),
-                is_vip: msg.is_vip(),
-            },
-            &config,
+        let mut state = self.common.state.write().await;
+
+        state.record_chatter(
+            &format!("twitch:{}", msg.name().to_lowercase()),
+            msg.name(),
+            config.chat_bonus_points,
+            config.chat_bonus_cooldown_secs,
         );
 
+        let is_giveaway_entry = msg
+            .data()
+            .trim()
+            .eq_ignore_ascii_case(&config.giveaway_keyword)
+            && state.giveaway.as_ref().is_some_and(|g| !g.is_closed);
+
+        let text = if is_giveaway_entry {
+            format!("{} giveaway enter", self.common.creds.bot_prefix())
+        } else {
+            match msg.data().strip_prefix("!vote ") {
+                Some(option) => {
+                    format!("{} vote {}", self.common.creds.bot_prefix(), option.trim())
+                }
+                None => msg.data().to_string(),
+            }
+        };
+        if !text.starts_with(self.common.creds.bot_prefix()) {
+            return Ok(());
+        }
+
+        let info = commands::AdditionalInfo::Twitch {
+            name: msg.name().to_string(),
+            channel: self.common.creds.channel_name.clone(),
+            is_vip: msg.is_vip(),
+            is_subscriber: msg.tags().get("subscriber") == Some("1"),
+            is_moderator: is_moderator(msg),
+        };
+        let output = commands::parse(&text, info.clone(), &config, &mut state);
+
         let chat_message = match output {
+            CommandOutput::Command {
+                command: Commands::Poll { duration, text },
+                ..
+            } => match commands::start_poll(&text, duration, &info, &mut state) {
+                Ok(poll) => {
+                    if let Err(e) = self.sender.send(TwitchMessage::PollStarted(poll.clone())) {
+                        error!("{e}");
+                    }
+
+                    poll_announcement(&poll)
+                }
+                Err(e) => e,
+            },
+            CommandOutput::Command {
+                command:
+                    Commands::Giveaway(GiveawayArgs {
+                        command: GiveawayCommands::Start { text },
+                    }),
+                ..
+            } => match commands::start_giveaway(&text, &info, &mut state) {
+                Ok(giveaway) => {
+                    if let Err(e) = self
+                        .sender
+                        .send(TwitchMessage::GiveawayStarted(giveaway.clone()))
+                    {
+                        error!("{e}");
+                    }
+
+                    giveaway_announcement(&giveaway, &config.giveaway_keyword)
+                }
+                Err(e) => e,
+            },
+            CommandOutput::Command {
+                value: None,
+                command: Commands::Vote { .. },
+            } => return Ok(()),
+            // The API bot posts the result
+            CommandOutput::Command {
+                value: None,
+                command: Commands::So { user },
+            } => {
+                if let Err(e) = self.sender.send(TwitchMessage::ShoutoutRequested { user }) {
+                    error!("{e}");
+                }
+
+                return Ok(());
+            }
+            CommandOutput::Command {
+                value: None,
+                command:
+                    command @ (Commands::Uptime
+                    | Commands::Game { .. }
+                    | Commands::Title { .. }
+                    | Commands::Followage { .. }),
+            } => {
+                if let Some(request) = commands::channel_request(command, msg.name()) {
+                    // Helix can be slow, so chat keeps being read while waiting for the answer
+                    let sender = self.sender.clone();
+                    tokio::spawn(async move {
+                        let request = Request::Channel(request);
+                        let text =
+                            match send_request(&sender, TwitchMessage::Request, request).await {
+                                Ok(Response::Text(v)) => v,
+                                Ok(_) => return,
+                                Err(e) => {
+                                    error!("Unable to ask the API bot: {e}");
+                                    return;
+                                }
+                            };
+                        if let Err(e) = sender.send(TwitchMessage::ChatMessage(text)) {
+                            error!("{e}");
+                        }
+                    });
+                }
+
+                return Ok(());
+            }
+            CommandOutput::Command {
+                value: None,
+                command: Commands::Raid { user },
+            } => {
+                if let Err(e) = self.sender.send(TwitchMessage::RaidRequested { user }) {
+                    error!("{e}");
+                }
+
+                return Ok(());
+            }
+            CommandOutput::AdminCommand {
+                value,
+                command: AdminCommands::SyncRoles,
+            } => {
+                if let Err(e) = self.sender.send(TwitchMessage::SyncRolesRequested) {
+                    error!("{e}");
+                }
+
+                value.unwrap_or("No output!".into())
+            }
+            // Entering with the keyword should not flood the chat
+            CommandOutput::Command { .. } if is_giveaway_entry => return Ok(()),
             CommandOutput::Command { value, .. } | CommandOutput::AdminCommand { value, .. } => {
                 value.unwrap_or("No output!".into())
             }
@@ -309,9 +1499,163 @@ impl ChatBot {
         Ok(())
     }
 
+    /// Post an alert in chat and pass it on to Discord, unless its type is turned off or
+    /// it was already posted.
+    async fn post_alert(&self, alert: Alert) -> anyhow::Result<()> {
+        let (settings, dedup_secs) = {
+            let config = self.common.config.read().await;
+            (
+                config.alerts.settings(&alert).clone(),
+                config.alerts.dedup_secs,
+            )
+        };
+        if !settings.twitch && !settings.discord {
+            return Ok(());
+        }
+        if !self
+            .common
+            .state
+            .write()
+            .await
+            .record_alert(&alert.key(), dedup_secs)
+        {
+            debug!("Ignoring repeated alert {}", alert.key());
+            return Ok(());
+        }
+
+        if settings.discord {
+            if let Err(e) = self.sender.send(TwitchMessage::Alert(alert.clone())) {
+                error!("{e}");
+            }
+        }
+        if settings.twitch {
+            let format = match settings.twitch_format.as_str() {
+                "" => alert.default_format(),
+                v => v,
+            };
+            match format.format(&alert.vars()) {
+                Ok(v) => self.send_chat_message(&v).await?,
+                Err(e) => error!("Failed to format alert using custom format: {e}"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the antispam rules against the message with the given `id` and act on the result.
+    /// Returns true if the message was removed.
+    async fn check_spam(&self, msg: &Privmsg<'_>, id: &str) -> anyhow::Result<bool> {
+        let config = self.common.config.read().await;
+        let user = format!("twitch:{}", msg.name().to_lowercase());
+
+        let blocked = {
+            let scope = format!("twitch:{}", self.common.creds.channel_name.to_lowercase());
+            let badges = msg
+                .tags()
+                .get("badges")
+                .unwrap_or_default()
+                .split(',')
+                .filter_map(|b| b.split('/').next())
+                .filter(|b| !b.is_empty())
+                .map(String::from)
+                .collect::<Vec<String>>();
+
+            self.common
+                .state
+                .read()
+                .await
+                .blocklists
+                .get(&scope)
+                .filter(|b| !b.is_exempt(&badges))
+                .and_then(|b| b.find_match(msg.data()))
+        };
+
+        let verdict = if let Some(entry) = blocked {
+            debug!("Blocked {entry} from {}", msg.name());
+
+            self.antispam.write().await.blocklist_verdict(
+                &user,
+                config.blocklist_action,
+                config.blocklist_escalate,
+                &config.antispam,
+            )
+        } else {
+            let spam_message = SpamMessage {
+                content: msg.data(),
+                mentions: msg
+                    .data()
+                    .split_whitespace()
+                    .filter(|w| w.starts_with('@'))
+                    .count(),
+                account_age_secs: None,
+            };
+
+            match self
+                .antispam
+                .write()
+                .await
+                .check(&user, &spam_message, &config.antispam)
+            {
+                Some(v) => v,
+                None => return Ok(false),
+            }
+        };
+
+        debug!(
+            "Spam detected from {} by rule {}: {:?}",
+            msg.name(),
+            verdict.rule,
+            verdict.action
+        );
+
+        if !verdict.silent {
+            self.send_chat_message(&format!(
+                "@{} Please do not spam! >:( ({})",
+                msg.name(),
+                verdict.reason
+            ))
+            .await?;
+        }
+
+        let broadcaster_id = msg.tags().get("room-id").unwrap_or_default();
+        let user_id = msg.tags().get("user-id").unwrap_or_default();
+        if let Err(e) = moderation::apply_verdict(
+            self.moderator.as_ref(),
+            &verdict,
+            broadcaster_id,
+            user_id,
+            id,
+        )
+        .await
+        {
+            error!("Unable to moderate {}: {e}", msg.name());
+        }
+
+        let action = ModAction::new(
+            "antispam",
+            &user,
+            msg.name(),
+            verdict.action.to_string(),
+            &verdict.reason,
+        )
+        .with_rule(verdict.rule)
+        .with_content(msg.data());
+        self.common
+            .state
+            .write()
+            .await
+            .record_mod_action(action.clone());
+        if let Err(e) = self.sender.send(TwitchMessage::ModAction(action)) {
+            error!("{e}");
+        }
+
+        Ok(verdict.action > SpamAction::Warn)
+    }
+
     async fn send_chat_message(&self, message: &str) -> anyhow::Result<()> {
-        self.client
-            .writer()
+        self.writer
+            .lock()
+            .await
             .encode(twitchchat::commands::privmsg(
                 self.common.creds.channel_name.as_str(),
                 message,
@@ -321,11 +1665,69 @@ impl ChatBot {
     }
 }
 
+fn poll_announcement(poll: &Poll) -> String {
+    format!(
+        "Poll: {} {} | Type !vote <number> to vote!",
+        poll.question,
+        poll.options
+            .iter()
+            .enumerate()
+            .map(|(i, o)| format!("[{}] {o}", i + 1))
+            .collect::<Vec<String>>()
+            .join(" ")
+    )
+}
+
+fn giveaway_announcement(giveaway: &Giveaway, keyword: &str) -> String {
+    format!(
+        "Giveaway for {} started! Type {keyword} to enter! Open to {}.",
+        giveaway.prize, giveaway.eligibility
+    )
+}
+
+/// Convert a Helix stream into the platform independent `StreamInfo`.
+fn stream_info(stream: &Stream) -> StreamInfo {
+    StreamInfo {
+        id: stream.id.to_string(),
+        user_id: stream.user_id.to_string(),
+        login: stream.user_login.to_string(),
+        channel: stream.user_name.to_string(),
+        title: stream.title.to_string(),
+        url: format!("https://twitch.tv/{}", stream.user_login),
+        game: stream.game_name.clone(),
+        viewer_count: stream.viewer_count,
+        // Discord caches images by URL, so make sure a fresh thumbnail is shown
+        thumbnail_url: format!(
+            "{}?t={}",
+            stream
+                .thumbnail_url
+                .replace("{width}", "1280")
+                .replace("{height}", "720"),
+            unix_now()
+        ),
+        started_at: stream.started_at.to_string(),
+        // Filled in separately, see `ApiBot::channel_tags`
+        tags: vec![],
+    }
+}
+
+/// Whether the sender of the message is a moderator or the broadcaster.
+fn is_moderator(msg: &Privmsg<'_>) -> bool {
+    msg.tags().get("mod") == Some("1")
+        || msg
+            .tags()
+            .get("badges")
+            .unwrap_or_default()
+            .contains("broadcaster/")
+}
+
 pub async fn create_bots<'a>(
     config: Arc<RwLock<Config>>,
     creds: TwitchCreds,
+    state: Arc<RwLock<State>>,
     receiver: Receiver<CentralMessage>,
     sender: Sender<TwitchMessage>,
+    relay_sender: UnboundedSender<ChatLine>,
 ) -> anyhow::Result<(ApiBot<'a>, ChatBot)> {
     let (api_client, user_token) = create_api_resources(&creds).await?;
     let irc_client = create_irc_resources(
@@ -342,21 +1744,39 @@ pub async fn create_bots<'a>(
     let common = BotCommon {
         config: config.clone(),
         creds,
+        state,
         receiver,
         sender,
     };
 
     let config = &config.read().await;
+    let (eventsub_sender, eventsub_receiver) = mpsc::unbounded_channel();
 
     let api_bot = ApiBot {
         common: common.clone(),
         client: api_client,
         interval: tokio::time::interval(Duration::from_secs_f32(config.tick_duration)),
         check_live_ticks: config.check_live_ticks,
+        sync_roles_ticks: config.sync_roles_ticks,
+        points_ticks: config.points_ticks,
+        broadcaster_id: None,
+        eventsub_sender,
+        eventsub_receiver,
+        recent_eventsub_messages: VecDeque::new(),
+        stream_rechecks: HashMap::new(),
+        next_stream_recheck: 0,
+        channel_cache: HashMap::new(),
     };
 
     let chat_bot = ChatBot {
-        common: common.clone(),
+        chat: Chat {
+            common: common.clone(),
+            writer: Mutex::new(irc_client.writer()),
+            antispam: RwLock::new(Antispam::new()),
+            moderator: Arc::new(HelixModerator::new(user_token)),
+            relay_limiter: RwLock::new(RateLimiter::default()),
+            relay_sender,
+        },
         client: irc_client,
     };
 
@@ -11,10 +11,13 @@ use model::{
     config::Config,
     creds,
     messages::{CentralMessage, DiscordMessage, ServerMessage, TwitchMessage},
+    relay::ChatLine,
+    state::State,
 };
 use tokio::{
     sync::{
         broadcast::{self, Receiver, Sender},
+        mpsc::{self, UnboundedSender},
         RwLock,
     },
     task::JoinHandle,
@@ -29,12 +32,20 @@ const WORKSPACE_CRATES: [&str; 6] = [
     "twitch",
 ];
 
+/// Where the bot state is saved between restarts.
+const STATE_PATH: &str = match option_env!("SYWB_STATE_PATH") {
+    Some(v) => v,
+    None => "state.json",
+};
+
 pub static IS_RUNNING: AtomicBool = AtomicBool::new(true);
 
 fn start_twitch_bot(
     sender: Sender<TwitchMessage>,
     receiver: Receiver<CentralMessage>,
+    relay_sender: UnboundedSender<ChatLine>,
     config: Arc<RwLock<Config>>,
+    state: Arc<RwLock<State>>,
 ) -> JoinHandle<()> {
     let sender = sender;
     let receiver = receiver;
@@ -44,6 +55,7 @@ fn start_twitch_bot(
         env!("TWITCH_CLIENT_SECRET"),
         env!("TWITCH_BOT_NAME"),
         env!("TWITCH_CHANNEL_NAME"),
+        option_env!("TWITCH_EVENTSUB_SECRET").unwrap_or_default(),
     );
 
     tokio::spawn({
@@ -52,8 +64,10 @@ fn start_twitch_bot(
             while twitch::run_bot(
                 config.clone(),
                 creds.clone(),
+                state.clone(),
                 receiver.resubscribe(),
                 sender.clone(),
+                relay_sender.clone(),
             )
             .await
             .is_err()
@@ -64,6 +78,28 @@ fn start_twitch_bot(
     })
 }
 
+/// Write the state to disk if it changed since the last save.
+fn save_state(state: &State, last_saved: &mut String) {
+    let json = match state.to_json() {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to serialize state: {e}");
+            return;
+        }
+    };
+
+    if &json == last_saved {
+        return;
+    }
+
+    if let Err(e) = std::fs::write(STATE_PATH, &json) {
+        error!("Unable to save state: {e}");
+    } else {
+        debug!("Saved state!");
+        *last_saved = json;
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     println!(
@@ -91,12 +127,20 @@ async fn main() -> anyhow::Result<()> {
     info!("Logging initted!");
 
     let config = Arc::new(RwLock::new(Config::new()));
+    let state = State::load(STATE_PATH).unwrap_or_else(|e| {
+        error!("Unable to load state from {STATE_PATH}: {e}");
+        State::new()
+    });
+    let mut last_saved_state = state.to_json().unwrap_or_default();
+    let state = Arc::new(RwLock::new(state));
 
     let (host_sender, _) = broadcast::channel(10);
     let (discord_sender, mut discord_receiver) = broadcast::channel(10);
     let (twitch_sender, mut twitch_receiver) = broadcast::channel(10);
     #[cfg(feature = "server")]
     let (server_sender, mut server_receiver) = broadcast::channel(10);
+    // Relayed chat goes straight from the Twitch bot to Discord, so it cannot lag the buses
+    let (relay_sender, relay_receiver) = mpsc::unbounded_channel();
 
     {
         let interrupt_sender = host_sender.clone();
@@ -113,6 +157,7 @@ async fn main() -> anyhow::Result<()> {
 
     let discord_join_handle = {
         let config = config.clone();
+        let state = state.clone();
         let creds = creds::DiscordCreds::new(
             env!("DISCORD_TOKEN"),
             env!("DISCORD_BOT_ID"),
@@ -124,7 +169,16 @@ async fn main() -> anyhow::Result<()> {
         let receiver = host_sender.subscribe();
         tokio::spawn({
             async move {
-                if let Err(e) = discord::run_bot(config, creds, receiver, discord_sender).await {
+                if let Err(e) = discord::run_bot(
+                    config,
+                    creds,
+                    state,
+                    receiver,
+                    discord_sender,
+                    relay_receiver,
+                )
+                .await
+                {
                     error!("{e}");
                 }
             }
@@ -137,142 +191,217 @@ async fn main() -> anyhow::Result<()> {
 
     let mut interval =
         tokio::time::interval(Duration::from_secs_f32(config.read().await.tick_duration));
+    let mut save_state_ticks: u64 = 0;
 
-    loop {
+    'main: loop {
         let _ = interval.tick().await;
 
         if !IS_RUNNING.load(Ordering::Relaxed) {
             break;
         }
 
-        match discord_receiver.try_recv() {
-            Ok(v) => match v {
-                DiscordMessage::Ready => {
-                    info!("Discord ready!");
-
-                    {
-                        twitch_join_handle = Some(start_twitch_bot(
-                            twitch_sender.clone(),
-                            host_sender.subscribe(),
-                            config.clone(),
-                        ));
+        save_state_ticks += 1;
+        if save_state_ticks >= config.read().await.save_state_ticks {
+            save_state_ticks = 0;
+            save_state(&*state.read().await, &mut last_saved_state);
+        }
 
-                        debug!("Spawned task for Twitch bot!");
+        // Drain every pending message, so bursts of messages do not lag the channels
+        loop {
+            match discord_receiver.try_recv() {
+                Ok(v) => match v {
+                    DiscordMessage::Ready => {
+                        info!("Discord ready!");
+
+                        {
+                            twitch_join_handle = Some(start_twitch_bot(
+                                twitch_sender.clone(),
+                                host_sender.subscribe(),
+                                relay_sender.clone(),
+                                config.clone(),
+                                state.clone(),
+                            ));
+
+                            debug!("Spawned task for Twitch bot!");
+                        }
+
+                        #[cfg(feature = "server")]
+                        {
+                            server_join_handle = {
+                                let config = config.clone();
+                                let state = state.clone();
+                                let receiver = host_sender.subscribe();
+                                let sender = server_sender.clone();
+
+                                Some(tokio::spawn(async move {
+                                    if let Err(e) =
+                                        server::run(config, state, receiver, sender).await
+                                    {
+                                        error!("{e}");
+                                    }
+                                }))
+                            };
+
+                            debug!("Spawned task for Server!");
+                        }
                     }
+                    DiscordMessage::ConfigUpdated(c) => {
+                        *config.write().await = c;
+                        debug!("Config updated!");
 
-                    #[cfg(feature = "server")]
-                    {
-                        server_join_handle = {
-                            let config = config.clone();
-                            let receiver = host_sender.subscribe();
-                            let sender = server_sender.clone();
-
-                            Some(tokio::spawn(async move {
-                                if let Err(e) = server::run(config.clone(), receiver, sender).await
-                                {
-                                    error!("{e}");
-                                }
-                            }))
-                        };
-
-                        debug!("Spawned task for Server!");
+                        if let Err(e) = host_sender.send(CentralMessage::ConfigUpdated) {
+                            error!("{e}");
+                        }
                     }
-                }
-                DiscordMessage::ConfigUpdated(c) => {
-                    *config.write().await = c;
-                    debug!("Config updated!");
-
-                    if let Err(e) = host_sender.send(CentralMessage::ConfigUpdated) {
-                        error!("{e}");
+                    DiscordMessage::PollStarted(_)
+                    | DiscordMessage::PollEnded(_)
+                    | DiscordMessage::GiveawayStarted(_)
+                    | DiscordMessage::GiveawayEnded(_)
+                    | DiscordMessage::SyncRolesRequested => {
+                        if let Err(e) = host_sender.send(CentralMessage::Discord(v)) {
+                            error!("{e}");
+                        }
                     }
-                }
-                DiscordMessage::Debug(m) => {
-                    debug!("Discord: {m}");
-                }
-                DiscordMessage::Error(m) => {
-                    error!("Discord: {m}");
-                }
-            },
-            Err(e) => match e {
-                broadcast::error::TryRecvError::Empty => {}
-                broadcast::error::TryRecvError::Closed => {
-                    error!("Discord receiver closed");
-                    host_sender.send(CentralMessage::Shutdown).unwrap();
-                    break;
-                }
-                broadcast::error::TryRecvError::Lagged(n) => {
-                    error!("Discord receiver lagged by {} messages", n)
-                }
-            },
+                    DiscordMessage::Request(envelope) => {
+                        if let Err(e) = host_sender.send(CentralMessage::Request(envelope)) {
+                            error!("{e}");
+                        }
+                    }
+                    DiscordMessage::Debug(m) => {
+                        debug!("Discord: {m}");
+                    }
+                    DiscordMessage::Error(m) => {
+                        error!("Discord: {m}");
+                    }
+                },
+                Err(e) => match e {
+                    broadcast::error::TryRecvError::Empty => break,
+                    broadcast::error::TryRecvError::Closed => {
+                        error!("Discord receiver closed");
+                        host_sender.send(CentralMessage::Shutdown).unwrap();
+                        break 'main;
+                    }
+                    broadcast::error::TryRecvError::Lagged(n) => {
+                        error!("Discord receiver lagged by {} messages", n)
+                    }
+                },
+            }
         }
 
-        match twitch_receiver.try_recv() {
-            Ok(v) => match v {
-                TwitchMessage::Ready => {}
-                TwitchMessage::ChannelLive { .. } => {
-                    debug!("Channel is live: {:?}", &v);
-                    if let Err(e) = host_sender.send(CentralMessage::Twitch(v)) {
-                        error!("{e}");
+        loop {
+            match twitch_receiver.try_recv() {
+                Ok(v) => match v {
+                    TwitchMessage::Ready => {}
+                    TwitchMessage::StreamStarted(_)
+                    | TwitchMessage::StreamUpdated(_)
+                    | TwitchMessage::StreamEnded { .. } => {
+                        debug!("Stream changed: {:?}", &v);
+                        if let Err(e) = host_sender.send(CentralMessage::Twitch(v)) {
+                            error!("{e}");
+                        }
                     }
-                }
-                TwitchMessage::Debug(m) => {
-                    debug!("{m}");
-                }
-                TwitchMessage::Error(m) => {
-                    error!("{m}");
-                }
-                TwitchMessage::TokenExpired => {
-                    // TODO check memory usage to see if this is actually killing the task
-                    let sender = twitch_sender.clone();
-                    let receiver = host_sender.subscribe();
+                    TwitchMessage::PollStarted(_)
+                    | TwitchMessage::GiveawayStarted(_)
+                    | TwitchMessage::UserRoles(_)
+                    | TwitchMessage::SyncRolesRequested
+                    | TwitchMessage::ModAction(_)
+                    | TwitchMessage::Followed { .. }
+                    | TwitchMessage::Subscribed { .. }
+                    | TwitchMessage::Cheered { .. }
+                    | TwitchMessage::Raided { .. }
+                    | TwitchMessage::RewardRedeemed(_)
+                    | TwitchMessage::Alert(_)
+                    | TwitchMessage::ShoutoutRequested { .. }
+                    | TwitchMessage::RaidRequested { .. }
+                    | TwitchMessage::ChatMessage(_) => {
+                        if let Err(e) = host_sender.send(CentralMessage::Twitch(v)) {
+                            error!("{e}");
+                        }
+                    }
+                    TwitchMessage::Request(envelope) => {
+                        if let Err(e) = host_sender.send(CentralMessage::Request(envelope)) {
+                            error!("{e}");
+                        }
+                    }
+                    TwitchMessage::Debug(m) => {
+                        debug!("{m}");
+                    }
+                    TwitchMessage::Error(m) => {
+                        error!("{m}");
+                    }
+                    TwitchMessage::TokenExpired => {
+                        // TODO check memory usage to see if this is actually killing the task
+                        let sender = twitch_sender.clone();
+                        let receiver = host_sender.subscribe();
 
-                    if let Some(handle) = twitch_join_handle {
-                        handle.abort();
+                        if let Some(handle) = twitch_join_handle {
+                            handle.abort();
 
-                        let _ = handle.await;
-                    }
+                            let _ = handle.await;
+                        }
 
-                    twitch_join_handle = Some(start_twitch_bot(sender, receiver, config.clone()));
-                }
-            },
-            Err(e) => match e {
-                broadcast::error::TryRecvError::Empty => {}
-                broadcast::error::TryRecvError::Closed => {
-                    error!("Twitch receiver closed");
-                    host_sender.send(CentralMessage::Shutdown).unwrap();
-                    break;
-                }
-                broadcast::error::TryRecvError::Lagged(n) => {
-                    error!("Twitch receiver lagged by {} messages", n)
-                }
-            },
+                        twitch_join_handle = Some(start_twitch_bot(
+                            sender,
+                            receiver,
+                            relay_sender.clone(),
+                            config.clone(),
+                            state.clone(),
+                        ));
+                    }
+                },
+                Err(e) => match e {
+                    broadcast::error::TryRecvError::Empty => break,
+                    broadcast::error::TryRecvError::Closed => {
+                        error!("Twitch receiver closed");
+                        host_sender.send(CentralMessage::Shutdown).unwrap();
+                        break 'main;
+                    }
+                    broadcast::error::TryRecvError::Lagged(n) => {
+                        error!("Twitch receiver lagged by {} messages", n)
+                    }
+                },
+            }
         }
 
         #[cfg(feature = "server")]
-        match server_receiver.try_recv() {
-            Ok(v) => match v {
-                ServerMessage::Ready => {}
-                ServerMessage::Debug(m) => {
-                    debug!("{m}");
-                }
-                ServerMessage::Error(m) => {
-                    error!("{m}");
-                }
-            },
-            Err(e) => match e {
-                broadcast::error::TryRecvError::Empty => {}
-                broadcast::error::TryRecvError::Closed => {
-                    error!("Server receiver closed");
-                    host_sender.send(CentralMessage::Shutdown).unwrap();
-                    break;
-                }
-                broadcast::error::TryRecvError::Lagged(n) => {
-                    error!("Server receiver lagged by {} messages", n)
-                }
-            },
+        loop {
+            match server_receiver.try_recv() {
+                Ok(v) => match v {
+                    ServerMessage::Ready => {}
+                    ServerMessage::EventSub { .. } => {
+                        if let Err(e) = host_sender.send(CentralMessage::Server(v)) {
+                            error!("{e}");
+                        }
+                    }
+                    ServerMessage::Request(envelope) => {
+                        if let Err(e) = host_sender.send(CentralMessage::Request(envelope)) {
+                            error!("{e}");
+                        }
+                    }
+                    ServerMessage::Debug(m) => {
+                        debug!("{m}");
+                    }
+                    ServerMessage::Error(m) => {
+                        error!("{m}");
+                    }
+                },
+                Err(e) => match e {
+                    broadcast::error::TryRecvError::Empty => break,
+                    broadcast::error::TryRecvError::Closed => {
+                        error!("Server receiver closed");
+                        host_sender.send(CentralMessage::Shutdown).unwrap();
+                        break 'main;
+                    }
+                    broadcast::error::TryRecvError::Lagged(n) => {
+                        error!("Server receiver lagged by {} messages", n)
+                    }
+                },
+            }
         }
     }
 
+    save_state(&*state.read().await, &mut last_saved_state);
+
     discord_join_handle.abort();
     assert!(discord_join_handle.await.unwrap_err().is_cancelled());
 